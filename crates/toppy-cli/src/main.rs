@@ -1,13 +1,27 @@
 use clap::{Parser, Subcommand};
-use std::io;
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use proxy::{proxy_connection, proxy_once, ProxyPool};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
 use std::thread;
-use toppy_core::policy::{Decision, Policy, Target};
+use toppy_core::audit;
+use toppy_core::auth::{inspect_jwt, JwtConfig};
+use toppy_core::bench::{run_benches, DEFAULT_BENCH_ITERATIONS};
+use toppy_core::config::{load_policy, Config, GatewayConfig};
+use toppy_core::logging::{self, LogLevel};
+use toppy_core::net::{resolve_allowed_rules, split_host_port};
+use toppy_core::policy::{load_policy_config, Policy};
+
+mod proxy;
 
 /// Toppy command-line interface
 #[derive(Parser)]
 #[command(name = "toppy", author, version, about = "Toppy CLI for managing MASQUE connections", long_about = None)]
 struct Cli {
+    /// Config profile to apply (`[profiles.<name>]`); overrides TOPPY_PROFILE
+    #[arg(long, global = true)]
+    profile: Option<String>,
     /// Subcommands for the CLI
     #[command(subcommand)]
     command: Option<Commands>,
@@ -20,10 +34,14 @@ enum Commands {
         /// Output JSON instead of human-readable text
         #[arg(long)]
         json: bool,
+        /// Apply safe local fixes (e.g. create a missing config) before checking
+        #[arg(long)]
+        fix: bool,
     },
     /// Start a local TCP forwarder to an allowed target
     Up {
-        /// Target to connect to (ip:port)
+        /// Target to connect to (ip:port or host:port; IPv6 as [ip]:port; the port
+        /// may be a service name such as ssh or https)
         #[arg(long)]
         target: String,
         /// Local listen address (ip:port)
@@ -32,42 +50,148 @@ enum Commands {
         /// Exit after a single connection
         #[arg(long)]
         once: bool,
+        /// Retry a failed connect to the target this many times, with backoff,
+        /// before dropping the inbound connection
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retry_connect: u32,
+        /// Evaluate config and policy for the target, print the decision, and exit
+        /// without binding (exit 0 on allow, 2 on deny)
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Run the gateway (`toppy-gw`) with the `[gw]` section of the config
+    Gw {
+        /// Config file to read instead of TOPPY_CONFIG or the default path
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// QUIC listen address (ip:port); overrides `gw.quic_listen`
+        #[arg(long)]
+        quic_listen: Option<String>,
+    },
+    /// Inspect the configured policy
+    Policy {
+        #[command(subcommand)]
+        command: PolicyCommands,
+    },
+    /// Read the hash-chained audit log
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+    /// Inspect auth tokens
+    Token {
+        #[command(subcommand)]
+        command: TokenCommands,
+    },
+    /// Measure varint codec and rate limiter throughput
+    Bench {
+        /// Iterations per benchmark
+        #[arg(long, default_value_t = DEFAULT_BENCH_ITERATIONS)]
+        iterations: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCommands {
+    /// Print a JWT's claims (signature not verified) and warn when `aud` does not
+    /// match `expected_audience` or the token has expired
+    Inspect {
+        /// Token to inspect instead of the config's `auth_token`
+        #[arg(long)]
+        token: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommands {
+    /// Print verified entries as JSON lines
+    Tail {
+        /// Keep running and print entries as they are appended
+        #[arg(short = 'f', long)]
+        follow: bool,
+        /// Only print entries with a higher seq
+        #[arg(long, default_value_t = 0)]
+        from_seq: u64,
+        /// Audit log to read instead of the config's `audit_path`
+        #[arg(long)]
+        file: Option<PathBuf>,
     },
 }
 
+#[derive(Subcommand)]
+enum PolicyCommands {
+    /// Report rules shadowed by or overlapping earlier rules (advisory)
+    Lint {
+        /// Policy file to lint (.json or .toml) instead of the config's policy
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+/// Checks applied by `toppy token inspect`; only the audience comes from config.
+fn token_check_config(cfg: &Config) -> JwtConfig {
+    JwtConfig {
+        secret: String::new(),
+        issuer: None,
+        audience: cfg.expected_audience.clone(),
+        require_exp: true,
+        max_token_age_secs: None,
+    }
+}
+
 fn parse_socket_addr(label: &str, value: &str) -> Result<SocketAddr, String> {
     value
         .parse::<SocketAddr>()
         .map_err(|e| format!("invalid {} {}: {}", label, value, e))
 }
 
-fn proxy_connection(mut inbound: TcpStream, target: SocketAddr) -> io::Result<()> {
-    let mut outbound = TcpStream::connect(target)?;
-    let _ = inbound.set_nodelay(true);
-    let _ = outbound.set_nodelay(true);
-
-    let mut inbound_clone = inbound.try_clone()?;
-    let mut outbound_clone = outbound.try_clone()?;
-
-    let t1 = thread::spawn(move || io::copy(&mut inbound_clone, &mut outbound));
-    let t2 = thread::spawn(move || io::copy(&mut outbound_clone, &mut inbound));
+fn join_addrs(addrs: &[SocketAddr]) -> String {
+    let addrs: Vec<String> = addrs.iter().map(SocketAddr::to_string).collect();
+    addrs.join(", ")
+}
 
-    let _ = t1.join();
-    let _ = t2.join();
-    Ok(())
+/// `[gw]` from `cfg` with command-line overrides applied. Without its own
+/// `max_datagram_size`, the gateway uses the config's effective datagram limit.
+fn gateway_config(cfg: Config, quic_listen: Option<String>) -> GatewayConfig {
+    let datagram_limit = (cfg.max_datagram_size.is_some() || cfg.mtu.is_some())
+        .then(|| cfg.effective_max_datagram_size());
+    let mut gw = cfg.gw.unwrap_or_default();
+    if quic_listen.is_some() {
+        gw.quic_listen = quic_listen;
+    }
+    gw.max_datagram_size = gw.max_datagram_size.or(datagram_limit);
+    gw
 }
 
-fn proxy_once(inbound: TcpStream, target: SocketAddr) -> io::Result<()> {
-    let _ = inbound.set_nodelay(true);
-    let outbound = TcpStream::connect(target)?;
-    let _ = outbound.set_nodelay(true);
-    Ok(())
+/// `toppy-gw` installed next to this binary, else whatever is on `PATH`.
+fn gateway_binary() -> PathBuf {
+    std::env::current_exe()
+        .map(|exe| exe.with_file_name(format!("toppy-gw{}", std::env::consts::EXE_SUFFIX)))
+        .ok()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from("toppy-gw"))
 }
 
 fn main() {
     let cli = Cli::parse();
+    if let Some(profile) = &cli.profile {
+        // Config is loaded deep inside core (e.g. doctor), which reads the env.
+        std::env::set_var("TOPPY_PROFILE", profile);
+    }
     match cli.command {
-        Some(Commands::Doctor { json }) => {
+        Some(Commands::Doctor { json, fix }) => {
+            if fix {
+                // Reported on stderr so `--json` output stays parseable.
+                match toppy_core::doctor::doctor_fix() {
+                    Ok(fixes) if fixes.is_empty() => eprintln!("fix: nothing to change"),
+                    Ok(fixes) => {
+                        for fix in fixes {
+                            eprintln!("fix: {}: {}", fix.id, fix.action);
+                        }
+                    }
+                    Err(err) => eprintln!("fix failed: {}", err),
+                }
+            }
             // Invoke the doctor checks from toppy_core and print JSON
             let report = toppy_core::doctor::doctor_check();
             if json {
@@ -77,16 +201,24 @@ fn main() {
                 }
             } else {
                 println!("doctor: {}", report.overall);
+                println!("mode: {}", report.overall_mode);
                 println!("version: {}", report.version);
+                let (pass, warn, fail) = report.status_counts();
                 for check in report.checks {
                     println!("- [{}] {}: {}", check.status, check.id, check.summary);
+                    if let Some(hint) = &check.hint {
+                        println!("  hint: {}", hint);
+                    }
                 }
+                println!("summary: {} pass, {} warn, {} fail", pass, warn, fail);
             }
         }
         Some(Commands::Up {
             target,
             listen,
             once,
+            retry_connect,
+            dry_run,
         }) => {
             let (cfg, path) = match toppy_core::config::load_config() {
                 Ok((cfg, path)) => (cfg, path),
@@ -99,9 +231,13 @@ fn main() {
                 eprintln!("Config validation failed ({}): {}", path.display(), err);
                 std::process::exit(1);
             }
+            if let Err(err) = logging::init(&cfg) {
+                eprintln!("Invalid log settings: {}", err);
+                std::process::exit(1);
+            }
 
-            let target_addr = match parse_socket_addr("target", &target) {
-                Ok(addr) => addr,
+            let (target_host, target_port) = match split_host_port(&target) {
+                Ok(parts) => parts,
                 Err(err) => {
                     eprintln!("{}", err);
                     std::process::exit(1);
@@ -115,7 +251,14 @@ fn main() {
                 }
             };
 
-            let policy = match cfg.policy.as_ref() {
+            let policy_cfg = match load_policy(&cfg) {
+                Ok(policy_cfg) => policy_cfg,
+                Err(err) => {
+                    eprintln!("Failed to load policy: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let policy = match policy_cfg.as_ref() {
                 Some(policy_cfg) => match Policy::from_config(policy_cfg) {
                     Ok(policy) => policy,
                     Err(err) => {
@@ -123,18 +266,53 @@ fn main() {
                         std::process::exit(1);
                     }
                 },
-                None => Policy { allow: Vec::new() },
+                None => Policy {
+                    allow: Vec::new(),
+                    deny: Vec::new(),
+                    allow_diagnostics: false,
+                },
             };
-            let target_policy = Target {
-                ip: target_addr.ip(),
-                port: target_addr.port(),
+            // Resolution and policy are evaluated together so a name only
+            // yields addresses the policy allows; whichever of them wins the
+            // dual-stack connect race is therefore allowed too.
+            let (target_addrs, matched_rules): (Arc<[SocketAddr]>, String) =
+                match resolve_allowed_rules(&target_host, target_port, &policy) {
+                    Ok(allowed) => {
+                        let addrs: Vec<SocketAddr> =
+                            allowed.iter().map(|(addr, _)| *addr).collect();
+                        let rules =
+                            policy.describe_rules(allowed.iter().filter_map(|(_, rule)| *rule));
+                        (Arc::from(proxy::order_addrs(&addrs)), rules)
+                    }
+                    Err(reason) => {
+                        if dry_run {
+                            println!("dry-run: deny {}: {}", target, reason);
+                        } else {
+                            eprintln!("Policy denied: {}", reason);
+                        }
+                        std::process::exit(2);
+                    }
+                };
+            let allowed_by = if matched_rules.is_empty() {
+                String::new()
+            } else {
+                format!(" by {}", matched_rules)
             };
-            match policy.evaluate(&target_policy) {
-                Decision::Allow => {}
-                Decision::Deny { reason } => {
-                    eprintln!("Policy denied: {}", reason);
-                    std::process::exit(2);
-                }
+            if dry_run {
+                println!(
+                    "dry-run: allow {} via {}{} (listen {})",
+                    target,
+                    join_addrs(&target_addrs),
+                    allowed_by,
+                    listen_addr
+                );
+                std::process::exit(0);
+            }
+            if !matched_rules.is_empty() {
+                logging::log(
+                    LogLevel::Info,
+                    &format!("policy allowed {} by {}", target, matched_rules),
+                );
             }
 
             let listener = match TcpListener::bind(listen_addr) {
@@ -151,26 +329,53 @@ fn main() {
                     std::process::exit(1);
                 }
             };
-            println!("toppy up listening on {} -> {}", local_addr, target_addr);
+            let pool = match cfg.proxy_max_workers {
+                Some(max_workers) => match ProxyPool::new(max_workers) {
+                    Ok(pool) => Some(pool),
+                    Err(err) => {
+                        eprintln!("Failed to start proxy workers: {}", err);
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            println!(
+                "toppy up listening on {} -> {}",
+                local_addr,
+                join_addrs(&target_addrs)
+            );
+            if let Some(pool) = &pool {
+                println!("toppy up using {} proxy workers", pool.num_workers());
+            }
 
             for stream in listener.incoming() {
                 match stream {
                     Ok(inbound) => {
                         if once {
-                            if let Err(err) = proxy_once(inbound, target_addr) {
-                                eprintln!("proxy connection failed: {}", err);
+                            if let Err(err) = proxy_once(inbound, &target_addrs, retry_connect) {
+                                logging::log(
+                                    LogLevel::Warn,
+                                    &format!("proxy connection failed: {}", err),
+                                );
                             }
                             break;
                         }
-                        let target = target_addr;
+                        if let Some(pool) = &pool {
+                            pool.spawn(inbound, target_addrs.clone(), retry_connect);
+                            continue;
+                        }
+                        let targets = target_addrs.clone();
                         thread::spawn(move || {
-                            if let Err(err) = proxy_connection(inbound, target) {
-                                eprintln!("proxy connection failed: {}", err);
+                            if let Err(err) = proxy_connection(inbound, &targets, retry_connect) {
+                                logging::log(
+                                    LogLevel::Warn,
+                                    &format!("proxy connection failed: {}", err),
+                                );
                             }
                         });
                     }
                     Err(err) => {
-                        eprintln!("accept failed: {}", err);
+                        logging::log(LogLevel::Error, &format!("accept failed: {}", err));
                         if once {
                             break;
                         }
@@ -178,8 +383,262 @@ fn main() {
                 }
             }
         }
+        Some(Commands::Gw {
+            config,
+            quic_listen,
+        }) => {
+            if let Some(path) = &config {
+                std::env::set_var("TOPPY_CONFIG", path);
+            }
+            let (cfg, path) = match toppy_core::config::load_config() {
+                Ok((cfg, path)) => (cfg, path),
+                Err(err) => {
+                    eprintln!("Failed to load config: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(err) = cfg.validate() {
+                eprintln!("Config validation failed ({}): {}", path.display(), err);
+                std::process::exit(1);
+            }
+            let gw = gateway_config(cfg, quic_listen);
+            let binary = gateway_binary();
+            match Command::new(&binary).envs(gw.env_vars()).status() {
+                Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+                Err(err) => {
+                    eprintln!("Failed to start {}: {}", binary.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Token {
+            command: TokenCommands::Inspect { token },
+        }) => {
+            let cfg = match toppy_core::config::load_config() {
+                Ok((cfg, _)) => cfg,
+                // An explicit token can still be decoded without a config.
+                Err(_) if token.is_some() => Config::default(),
+                Err(err) => {
+                    eprintln!("Failed to load config: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let Some(token) = token.or_else(|| cfg.auth_token.clone()) else {
+                eprintln!("No token given and no auth_token configured");
+                std::process::exit(1);
+            };
+            let inspection = match inspect_jwt(&token, &token_check_config(&cfg)) {
+                Ok(inspection) => inspection,
+                Err(err) => {
+                    eprintln!("Token inspection failed: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            match serde_json::to_string_pretty(&inspection.claims) {
+                Ok(claims) => println!("{}", claims),
+                Err(err) => {
+                    eprintln!("Failed to render claims: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            for warning in &inspection.warnings {
+                eprintln!("- [warn] {}", warning);
+            }
+        }
+        Some(Commands::Bench { iterations }) => {
+            for result in run_benches(iterations) {
+                println!(
+                    "{}: {:.0} ops/sec ({} iterations in {:?})",
+                    result.name,
+                    result.ops_per_sec(),
+                    result.iterations,
+                    result.elapsed
+                );
+            }
+        }
+        Some(Commands::Audit {
+            command:
+                AuditCommands::Tail {
+                    follow,
+                    from_seq,
+                    file,
+                },
+        }) => {
+            let path = match file {
+                Some(path) => path,
+                None => match toppy_core::config::load_config() {
+                    Ok((cfg, path)) => match cfg.audit_path {
+                        Some(audit_path) => PathBuf::from(audit_path),
+                        None => {
+                            eprintln!("No audit_path configured in {}", path.display());
+                            std::process::exit(1);
+                        }
+                    },
+                    Err(err) => {
+                        eprintln!("Failed to load config: {}", err);
+                        std::process::exit(1);
+                    }
+                },
+            };
+            let print = |entry: &audit::AuditEntry| {
+                println!("{}", serde_json::to_string(entry).unwrap_or_default());
+                true
+            };
+            let result = if follow {
+                audit::follow(&path, from_seq, print)
+            } else {
+                audit::query_time_range(&path, 0, u64::MAX).map(|entries| {
+                    entries
+                        .iter()
+                        .filter(|entry| entry.seq > from_seq)
+                        .for_each(|entry| {
+                            print(entry);
+                        })
+                })
+            };
+            if let Err(err) = result {
+                eprintln!("Audit log {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Policy {
+            command: PolicyCommands::Lint { file },
+        }) => {
+            let policy_cfg = match file {
+                Some(path) => match load_policy_config(&path) {
+                    Ok(policy_cfg) => policy_cfg,
+                    Err(err) => {
+                        eprintln!("Failed to load policy: {}", err);
+                        std::process::exit(1);
+                    }
+                },
+                None => match toppy_core::config::load_config() {
+                    Ok((cfg, path)) => match load_policy(&cfg) {
+                        Ok(Some(policy_cfg)) => policy_cfg,
+                        Ok(None) => {
+                            eprintln!("No policy configured in {}", path.display());
+                            std::process::exit(1);
+                        }
+                        Err(err) => {
+                            eprintln!("Failed to load policy: {}", err);
+                            std::process::exit(1);
+                        }
+                    },
+                    Err(err) => {
+                        eprintln!("Failed to load config: {}", err);
+                        std::process::exit(1);
+                    }
+                },
+            };
+            let policy = match Policy::from_config(&policy_cfg) {
+                Ok(policy) => policy,
+                Err(err) => {
+                    eprintln!("Policy config invalid: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let lints = policy.lint();
+            if lints.is_empty() {
+                println!(
+                    "policy lint: no findings ({} allow, {} deny rules)",
+                    policy.allow.len(),
+                    policy.deny.len()
+                );
+            }
+            for lint in lints {
+                println!("- [warn] {}", lint.message);
+            }
+        }
         None => {
             println!("No subcommand provided. Try `toppy doctor`.");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audit_tail_args_parse_follow_flag() {
+        let cli = Cli::try_parse_from(["toppy", "audit", "tail", "-f", "--from-seq", "7"])
+            .expect("parse");
+        let Some(Commands::Audit {
+            command:
+                AuditCommands::Tail {
+                    follow,
+                    from_seq,
+                    file,
+                },
+        }) = cli.command
+        else {
+            panic!("expected audit tail subcommand");
+        };
+        assert!(follow);
+        assert_eq!(from_seq, 7);
+        assert_eq!(file, None);
+    }
+
+    #[test]
+    fn gw_args_build_gateway_config() {
+        let cli = Cli::try_parse_from([
+            "toppy",
+            "gw",
+            "--config",
+            "gw.toml",
+            "--quic-listen",
+            "127.0.0.1:5000",
+        ])
+        .expect("parse");
+        let Some(Commands::Gw {
+            config,
+            quic_listen,
+        }) = cli.command
+        else {
+            panic!("expected gw subcommand");
+        };
+        assert_eq!(config, Some(PathBuf::from("gw.toml")));
+
+        let cfg = Config {
+            gw: Some(GatewayConfig {
+                quic_listen: Some("0.0.0.0:4433".to_string()),
+                cert: Some("cert.pem".to_string()),
+                source_allow: vec!["10.0.0.0/8".to_string(), "::1/128".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let gw = gateway_config(cfg, quic_listen);
+        assert_eq!(
+            gw.env_vars(),
+            vec![
+                ("TOPPY_GW_QUIC_LISTEN", "127.0.0.1:5000".to_string()),
+                ("TOPPY_GW_CERT", "cert.pem".to_string()),
+                ("TOPPY_GW_SOURCE_ALLOW", "10.0.0.0/8,::1/128".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn gateway_config_inherits_datagram_limit() {
+        let cfg = Config {
+            mtu: Some(1400),
+            ..Default::default()
+        };
+        assert_eq!(gateway_config(cfg, None).max_datagram_size, Some(1305));
+
+        let cfg = Config {
+            max_datagram_size: Some(1200),
+            gw: Some(GatewayConfig {
+                max_datagram_size: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(gateway_config(cfg, None).max_datagram_size, Some(1000));
+        assert_eq!(
+            gateway_config(Config::default(), None).max_datagram_size,
+            None
+        );
+    }
+}
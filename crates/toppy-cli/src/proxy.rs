@@ -0,0 +1,407 @@
+//! TCP forwarding used by `toppy up`.
+//!
+//! By default each connection is copied by two dedicated OS threads. When
+//! `proxy_max_workers` is configured, connections are instead driven by a
+//! shared tokio runtime with that many worker threads.
+//!
+//! Targets may resolve to several (policy-allowed) addresses; outbound
+//! connections race them Happy Eyeballs style via [`connect_dual_stack`].
+//!
+//! Every forwarder takes a `retries` count: a failed outbound connect is
+//! retried that many times (see [`connect_with_retry`]) before the inbound
+//! connection is dropped.
+
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use toppy_core::logging::{self, LogLevel};
+
+/// Head start given to each attempt before the next address is tried
+/// (RFC 8305 recommends 250ms).
+pub const FALLBACK_DELAY: Duration = Duration::from_millis(250);
+
+/// Upper bound for a single connection attempt.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first connect retry; doubles per retry up to
+/// [`RETRY_BACKOFF_MAX`].
+pub const RETRY_BACKOFF_BASE: Duration = Duration::from_millis(100);
+pub const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+/// Orders addresses IPv6 first, then alternates families, keeping the
+/// resolver's order within each family.
+pub fn order_addrs(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|addr| addr.is_ipv6());
+    let mut ordered = Vec::with_capacity(addrs.len());
+    let (mut v6, mut v4) = (v6.drain(..), v4.drain(..));
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Connects to the first address that answers. Attempts start in
+/// [`order_addrs`] order, each `delay` after the previous one (or as soon as
+/// it fails); the error of the last failed attempt is returned if none succeed.
+pub fn connect_dual_stack(addrs: &[SocketAddr], delay: Duration) -> io::Result<TcpStream> {
+    let mut pending = order_addrs(addrs).into_iter();
+    let (tx, rx) = mpsc::channel();
+    let start = |addr: SocketAddr| {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            // The receiver is gone once another attempt won; drop the stream.
+            let _ = tx.send(TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT));
+        });
+    };
+
+    let mut in_flight = 0;
+    let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no target addresses");
+    loop {
+        if in_flight == 0 {
+            match pending.next() {
+                Some(addr) => {
+                    start(addr);
+                    in_flight += 1;
+                }
+                None => return Err(last_err),
+            }
+        }
+        let result = if pending.len() > 0 {
+            rx.recv_timeout(delay)
+        } else {
+            rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+        };
+        match result {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(err)) => {
+                in_flight -= 1;
+                last_err = err;
+                // A failure hands over to the next address without waiting.
+                if let Some(addr) = pending.next() {
+                    start(addr);
+                    in_flight += 1;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Some(addr) = pending.next() {
+                    start(addr);
+                    in_flight += 1;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(last_err),
+        }
+    }
+}
+
+/// [`connect_dual_stack`], tried up to `retries` more times after a failure
+/// with backoff; the last error is returned if every attempt fails.
+pub fn connect_with_retry(addrs: &[SocketAddr], retries: u32) -> io::Result<TcpStream> {
+    retry_connect(retries, || connect_dual_stack(addrs, FALLBACK_DELAY))
+}
+
+/// Calls `connect` up to `retries` more times after a failure, with backoff.
+fn retry_connect(
+    retries: u32,
+    mut connect: impl FnMut() -> io::Result<TcpStream>,
+) -> io::Result<TcpStream> {
+    let mut delay = RETRY_BACKOFF_BASE;
+    let mut attempt = 0;
+    loop {
+        match connect() {
+            Ok(stream) => return Ok(stream),
+            Err(err) if attempt >= retries => return Err(err),
+            Err(_) => {
+                thread::sleep(delay);
+                delay = (delay * 2).min(RETRY_BACKOFF_MAX);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+pub fn proxy_connection(
+    mut inbound: TcpStream,
+    targets: &[SocketAddr],
+    retries: u32,
+) -> io::Result<()> {
+    let mut outbound = connect_with_retry(targets, retries)?;
+    let _ = inbound.set_nodelay(true);
+    let _ = outbound.set_nodelay(true);
+
+    let mut inbound_clone = inbound.try_clone()?;
+    let mut outbound_clone = outbound.try_clone()?;
+
+    let t1 = thread::spawn(move || io::copy(&mut inbound_clone, &mut outbound));
+    let t2 = thread::spawn(move || io::copy(&mut outbound_clone, &mut inbound));
+
+    let _ = t1.join();
+    let _ = t2.join();
+    Ok(())
+}
+
+pub fn proxy_once(inbound: TcpStream, targets: &[SocketAddr], retries: u32) -> io::Result<()> {
+    let _ = inbound.set_nodelay(true);
+    let outbound = connect_with_retry(targets, retries)?;
+    let _ = outbound.set_nodelay(true);
+    Ok(())
+}
+
+/// Current and peak count of something running concurrently.
+#[derive(Default)]
+struct Gauge {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Gauge {
+    fn enter(&self) {
+        let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+    }
+
+    fn exit(&self) {
+        self.current.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Default)]
+struct PoolStats {
+    /// Pool threads, workers and blocking.
+    threads: Gauge,
+    /// Connections being forwarded.
+    handlers: Gauge,
+}
+
+/// Bounded worker pool that forwards connections with async copies.
+///
+/// Outbound connects run on blocking threads, capped at the worker count too.
+pub struct ProxyPool {
+    runtime: tokio::runtime::Runtime,
+    stats: Arc<PoolStats>,
+}
+
+impl ProxyPool {
+    pub fn new(max_workers: usize) -> io::Result<Self> {
+        let max_workers = max_workers.max(1);
+        let stats = Arc::new(PoolStats::default());
+        let (started, stopped) = (stats.clone(), stats.clone());
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(max_workers)
+            .max_blocking_threads(max_workers)
+            .thread_name("toppy-proxy")
+            .on_thread_start(move || started.threads.enter())
+            .on_thread_stop(move || stopped.threads.exit())
+            .enable_io()
+            .build()?;
+        Ok(Self { runtime, stats })
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.runtime.metrics().num_workers()
+    }
+
+    /// Forwards `inbound` to `targets` on the pool without blocking the caller.
+    pub fn spawn(&self, inbound: TcpStream, targets: Arc<[SocketAddr]>, retries: u32) {
+        let stats = self.stats.clone();
+        self.runtime.spawn(async move {
+            stats.handlers.enter();
+            if let Err(err) = proxy_connection_async(inbound, targets, retries).await {
+                logging::log(LogLevel::Warn, &format!("proxy connection failed: {}", err));
+            }
+            stats.handlers.exit();
+        });
+    }
+
+    /// Most pool threads (workers and blocking) alive at once.
+    #[cfg(test)]
+    fn peak_threads(&self) -> usize {
+        self.stats.threads.peak.load(Ordering::SeqCst)
+    }
+
+    /// Connections being forwarded right now.
+    #[cfg(test)]
+    fn active_handlers(&self) -> usize {
+        self.stats.handlers.current.load(Ordering::SeqCst)
+    }
+}
+
+async fn proxy_connection_async(
+    inbound: TcpStream,
+    targets: Arc<[SocketAddr]>,
+    retries: u32,
+) -> io::Result<()> {
+    inbound.set_nonblocking(true)?;
+    let mut inbound = tokio::net::TcpStream::from_std(inbound)?;
+    let outbound = tokio::task::spawn_blocking(move || connect_with_retry(&targets, retries))
+        .await
+        .map_err(io::Error::other)??;
+    outbound.set_nonblocking(true)?;
+    let mut outbound = tokio::net::TcpStream::from_std(outbound)?;
+    let _ = inbound.set_nodelay(true);
+    let _ = outbound.set_nodelay(true);
+    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener};
+
+    fn spawn_echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind echo");
+        let addr = listener.local_addr().expect("echo addr");
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut buf = Vec::new();
+                    let _ = stream.read_to_end(&mut buf);
+                    let _ = stream.write_all(&buf);
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn pool_handles_many_short_connections_with_bounded_workers() {
+        let echo = spawn_echo_server();
+        let pool = ProxyPool::new(2).expect("pool");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind proxy");
+        let proxy_addr = listener.local_addr().expect("proxy addr");
+
+        // Every client holds its connection open until the pool forwards all
+        // of them at once.
+        let connections = 32;
+        let all_connected = Arc::new(std::sync::Barrier::new(connections + 1));
+        let clients: Vec<_> = (0..connections)
+            .map(|i| {
+                let all_connected = all_connected.clone();
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(proxy_addr).expect("connect proxy");
+                    let msg = format!("hello {}", i);
+                    stream.write_all(msg.as_bytes()).expect("write");
+                    all_connected.wait();
+                    stream.shutdown(Shutdown::Write).expect("shutdown");
+                    let mut reply = String::new();
+                    stream.read_to_string(&mut reply).expect("read");
+                    assert_eq!(reply, msg);
+                })
+            })
+            .collect();
+
+        let targets: Arc<[SocketAddr]> = Arc::from(vec![echo]);
+        for inbound in listener.incoming().take(connections) {
+            pool.spawn(inbound.expect("accept"), targets.clone(), 0);
+        }
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while pool.active_handlers() < connections {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "{} handlers",
+                pool.active_handlers()
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        all_connected.wait();
+        for client in clients {
+            client.join().expect("client");
+        }
+        // Two workers plus at most two blocking connect threads.
+        assert!(pool.peak_threads() <= 4, "{} threads", pool.peak_threads());
+    }
+
+    #[test]
+    fn order_addrs_prefers_ipv6_and_interleaves() {
+        let addrs: Vec<SocketAddr> = ["10.0.0.1:22", "10.0.0.2:22", "[::1]:22", "[::2]:22"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = order_addrs(&addrs).iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            ordered,
+            ["[::1]:22", "10.0.0.1:22", "[::2]:22", "10.0.0.2:22"]
+        );
+    }
+
+    #[test]
+    fn proxy_falls_back_to_ipv4_when_ipv6_fails() {
+        // Answers one 5-byte message; `proxy_connection` does not forward
+        // half-closes, so the exchange must not rely on EOF.
+        let server = TcpListener::bind("127.0.0.1:0").expect("bind server");
+        let echo = server.local_addr().expect("server addr");
+        thread::spawn(move || {
+            let (mut stream, _) = server.accept().expect("accept");
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).expect("read");
+            stream.write_all(&buf).expect("write");
+        });
+        // Nothing listens here (or IPv6 is unavailable); either way it fails.
+        let dead_port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .expect("free port")
+            .port();
+        let dead_v6: SocketAddr = format!("[::1]:{}", dead_port).parse().unwrap();
+        let targets = vec![echo, dead_v6];
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind proxy");
+        let proxy_addr = listener.local_addr().expect("proxy addr");
+        let proxy = thread::spawn(move || {
+            let (inbound, _) = listener.accept().expect("accept");
+            proxy_connection(inbound, &targets, 0)
+        });
+
+        let mut stream = TcpStream::connect(proxy_addr).expect("connect proxy");
+        stream.write_all(b"hello").expect("write");
+        let mut reply = [0u8; 5];
+        stream.read_exact(&mut reply).expect("read");
+        assert_eq!(&reply, b"hello");
+        drop(stream);
+        proxy.join().expect("proxy thread").expect("proxy");
+    }
+
+    #[test]
+    fn connect_retries_after_failed_attempts() {
+        let server = TcpListener::bind("127.0.0.1:0").expect("bind server");
+        let target = server.local_addr().expect("server addr");
+        // The first attempt fails as if the target were not up yet.
+        let mut attempts = 0;
+        let mut stream = retry_connect(3, || {
+            attempts += 1;
+            if attempts == 1 {
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            }
+            connect_dual_stack(&[target], FALLBACK_DELAY)
+        })
+        .expect("connect");
+        assert_eq!(attempts, 2);
+
+        let (mut accepted, _) = server.accept().expect("accept");
+        stream.write_all(b"retry").expect("write");
+        let mut buf = [0u8; 5];
+        accepted.read_exact(&mut buf).expect("read");
+        assert_eq!(&buf, b"retry");
+    }
+
+    #[test]
+    fn connect_gives_up_after_retries() {
+        let mut attempts = 0;
+        let err = retry_connect(2, || {
+            attempts += 1;
+            Err(io::ErrorKind::ConnectionRefused.into())
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(attempts, 3);
+    }
+}
@@ -0,0 +1,38 @@
+//! Tests for the `doctor` subcommand.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[test]
+fn doctor_fix_creates_missing_config() {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let dir = env::temp_dir().join(format!("toppy-doctor-fix-{nanos}"));
+    let config = dir.join("toppy").join("config.toml");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_toppy-cli"))
+        .env("TOPPY_CONFIG", &config)
+        .env("TOPPY_DOCTOR_NET", "skip")
+        .env("TOPPY_DOCTOR_TUN", "pass")
+        .args(["doctor", "--fix", "--json"])
+        .output()
+        .expect("run toppy-cli");
+
+    assert!(config.exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("wrote example config"), "stderr: {stderr}");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).expect("json report");
+    let cfg_load = report["checks"]
+        .as_array()
+        .expect("checks")
+        .iter()
+        .find(|c| c["id"] == "cfg.load")
+        .expect("cfg.load");
+    assert_eq!(cfg_load["status"], "pass");
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,98 @@
+//! Tests for the `up` subcommand.
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn unique_temp_path(prefix: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    env::temp_dir().join(format!("toppy-{prefix}-{nanos}.toml"))
+}
+
+fn write_config_with_policy(path: &PathBuf) {
+    let data = r#"gateway = "127.0.0.1"
+port = 4433
+mtu = 1350
+
+[policy]
+  [[policy.allow]]
+  cidr = "127.0.0.1/32"
+  ports = [2222]
+"#;
+    fs::write(path, data).expect("write config");
+}
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    listener.local_addr().expect("local addr").port()
+}
+
+fn run_up_dry_run(config: &PathBuf, target: &str, listen: &str) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_toppy-cli"))
+        .env("TOPPY_CONFIG", config)
+        .args(["up", "--target", target, "--listen", listen, "--dry-run"])
+        .output()
+        .expect("run toppy up")
+}
+
+#[test]
+fn up_dry_run_denied_exits_2_without_binding() {
+    let path = unique_temp_path("up-dry-run-deny");
+    write_config_with_policy(&path);
+    let listen = format!("127.0.0.1:{}", free_port());
+
+    let output = run_up_dry_run(&path, "127.0.0.1:2223", &listen);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dry-run: deny"));
+    assert!(!stdout.contains("listening"));
+    // The listen address was never bound, so it is still free.
+    TcpListener::bind(&listen).expect("listen addr still free");
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn up_dry_run_allowed_exits_0() {
+    let path = unique_temp_path("up-dry-run-allow");
+    write_config_with_policy(&path);
+    let listen = format!("127.0.0.1:{}", free_port());
+
+    let output = run_up_dry_run(&path, "127.0.0.1:2222", &listen);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("dry-run: allow"));
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn up_dry_run_accepts_bracketed_ipv6_target() {
+    let path = unique_temp_path("up-dry-run-v6");
+    let data = r#"gateway = "127.0.0.1"
+port = 4433
+mtu = 1350
+
+[policy]
+  [[policy.allow]]
+  cidr = "::1/128"
+  ports = [2222]
+"#;
+    fs::write(&path, data).expect("write config");
+    let listen = format!("127.0.0.1:{}", free_port());
+
+    let output = run_up_dry_run(&path, "[::1]:2222", &listen);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("dry-run: allow"));
+
+    let output = run_up_dry_run(&path, "::1:2222", &listen);
+    assert_ne!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be bracketed"));
+
+    let _ = fs::remove_file(&path);
+}
@@ -1,8 +1,9 @@
-use ring::digest;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum AuditError {
@@ -44,6 +45,10 @@ pub struct AuditEvent {
     pub allowed: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
+    /// Extra context (request id, latency, bytes, ...). Covered by the entry
+    /// hash; omitted when empty so older entries keep their hashes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -70,10 +75,24 @@ struct AuditEntryUnsigned<'a> {
     prev_hash: Option<&'a str>,
 }
 
+#[cfg(feature = "ring")]
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let digest = ring::digest::digest(&ring::digest::SHA256, bytes);
+    let mut out = [0u8; 32];
+    out.copy_from_slice(digest.as_ref());
+    out
+}
+
+/// Without `ring` (default feature) hashes use the bundled pure-Rust SHA-256.
+#[cfg(not(feature = "ring"))]
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    crate::sha256::digest(bytes)
+}
+
 fn sha256_hex(bytes: &[u8]) -> String {
-    let digest = digest::digest(&digest::SHA256, bytes);
-    let mut out = String::with_capacity(digest.as_ref().len() * 2);
-    for b in digest.as_ref() {
+    let digest = sha256(bytes);
+    let mut out = String::with_capacity(digest.len() * 2);
+    for b in &digest {
         out.push(hex_char((b >> 4) & 0x0f));
         out.push(hex_char(b & 0x0f));
     }
@@ -106,84 +125,320 @@ fn compute_hash(
     Ok(sha256_hex(&bytes))
 }
 
-pub struct AuditChainWriter {
+/// Where an [`AuditChainWriter`] keeps its entries, one JSON line each. The
+/// chain (seq, hashes) is built by the writer; stores only hold bytes.
+pub trait AuditStore: Send {
+    /// Appends `line`: one or more newline-terminated entries. On error none
+    /// of them may be kept, so the stored chain stays valid.
+    fn append_line(&mut self, line: &[u8]) -> Result<(), AuditError>;
+
+    /// Every stored line in order, without line endings.
+    fn read_lines(&self) -> Result<Vec<String>, AuditError>;
+
+    fn last_entry(&self) -> Result<Option<AuditEntry>, AuditError> {
+        let lines = self.read_lines()?;
+        match lines.iter().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => Ok(Some(serde_json::from_str(line)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The default [`AuditStore`]: a local JSON-lines file.
+pub struct FileStore {
     path: PathBuf,
-    writer: BufWriter<File>,
+    file: File,
+}
+
+impl FileStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AuditError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `buf` with `write`, truncating the file back to its previous
+    /// length if that fails part-way.
+    fn append_with(
+        &mut self,
+        buf: &[u8],
+        write: impl FnOnce(&mut File, &[u8]) -> io::Result<()>,
+    ) -> Result<(), AuditError> {
+        let start = self.file.metadata()?.len();
+        if let Err(e) = write(&mut self.file, buf).and_then(|_| self.file.flush()) {
+            self.file.set_len(start)?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+impl AuditStore for FileStore {
+    fn append_line(&mut self, line: &[u8]) -> Result<(), AuditError> {
+        self.append_with(line, |file, buf| file.write_all(buf))
+    }
+
+    fn read_lines(&self) -> Result<Vec<String>, AuditError> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        Ok(reader.lines().collect::<Result<_, _>>()?)
+    }
+
+    fn last_entry(&self) -> Result<Option<AuditEntry>, AuditError> {
+        read_last_entry(&self.path)
+    }
+}
+
+pub struct AuditChainWriter {
+    store: Box<dyn AuditStore>,
     next_seq: u64,
     prev_hash: Option<String>,
 }
 
 impl AuditChainWriter {
+    /// Appends to the log file at `path` through a [`FileStore`].
     pub fn open(path: impl AsRef<Path>) -> Result<Self, AuditError> {
-        let path = path.as_ref().to_path_buf();
+        Self::with_store(Box::new(FileStore::open(path)?))
+    }
 
+    /// Continues the chain already in `store`, if any.
+    pub fn with_store(store: Box<dyn AuditStore>) -> Result<Self, AuditError> {
         let mut next_seq = 1u64;
         let mut prev_hash: Option<String> = None;
 
-        if path.exists() {
-            if let Some(last) = read_last_entry(&path)? {
-                // Basic sanity: verify the last entry hash is self-consistent.
-                let expected = compute_hash(
-                    last.version,
-                    last.seq,
-                    last.unix_ms,
-                    &last.event,
-                    last.prev_hash.as_deref(),
-                )?;
-                if expected != last.hash {
-                    return Err(AuditError::Invalid("last entry hash mismatch".to_string()));
-                }
-                next_seq = last.seq.saturating_add(1);
-                prev_hash = Some(last.hash);
+        if let Some(last) = store.last_entry()? {
+            // Basic sanity: verify the last entry hash is self-consistent.
+            let expected = compute_hash(
+                last.version,
+                last.seq,
+                last.unix_ms,
+                &last.event,
+                last.prev_hash.as_deref(),
+            )?;
+            if expected != last.hash {
+                return Err(AuditError::Invalid("last entry hash mismatch".to_string()));
             }
+            next_seq = last.seq.saturating_add(1);
+            prev_hash = Some(last.hash);
         }
 
-        let file = OpenOptions::new().create(true).append(true).open(&path)?;
-
         Ok(Self {
-            path,
-            writer: BufWriter::new(file),
+            store,
             next_seq,
             prev_hash,
         })
     }
 
     pub fn append(&mut self, unix_ms: u64, event: AuditEvent) -> Result<AuditEntry, AuditError> {
+        let mut entries = self.append_batch(vec![(unix_ms, event)])?;
+        Ok(entries.remove(0))
+    }
+
+    /// Appends several entries with a single store write.
+    ///
+    /// If the write fails, the store keeps none of the entries (a
+    /// [`FileStore`] truncates back to its length before the batch), so the
+    /// chain still verifies.
+    pub fn append_batch(
+        &mut self,
+        events: Vec<(u64, AuditEvent)>,
+    ) -> Result<Vec<AuditEntry>, AuditError> {
         let version = 1u32;
-        let seq = self.next_seq;
-        let prev_hash = self.prev_hash.as_deref();
-        let hash = compute_hash(version, seq, unix_ms, &event, prev_hash)?;
-
-        let entry = AuditEntry {
-            version,
-            seq,
-            unix_ms,
-            event,
-            prev_hash: self.prev_hash.clone(),
-            hash: hash.clone(),
-        };
+        let mut seq = self.next_seq;
+        let mut prev_hash = self.prev_hash.clone();
+        let mut entries = Vec::with_capacity(events.len());
+        let mut buf = Vec::new();
+        for (unix_ms, event) in events {
+            let hash = compute_hash(version, seq, unix_ms, &event, prev_hash.as_deref())?;
+            let entry = AuditEntry {
+                version,
+                seq,
+                unix_ms,
+                event,
+                prev_hash: prev_hash.replace(hash.clone()),
+                hash,
+            };
+            serde_json::to_writer(&mut buf, &entry)?;
+            buf.push(b'\n');
+            entries.push(entry);
+            seq = seq.saturating_add(1);
+        }
 
-        serde_json::to_writer(&mut self.writer, &entry)?;
-        self.writer.write_all(b"\n")?;
-        self.writer.flush()?;
+        self.store.append_line(&buf)?;
+        self.next_seq = seq;
+        self.prev_hash = prev_hash;
+        Ok(entries)
+    }
 
-        self.next_seq = self.next_seq.saturating_add(1);
-        self.prev_hash = Some(hash);
-        Ok(entry)
+    pub fn store(&self) -> &dyn AuditStore {
+        self.store.as_ref()
     }
+}
 
-    pub fn path(&self) -> &Path {
-        &self.path
+/// Verifies the chain held by `store`, like [`verify_chain_stats`] for a file.
+pub fn verify_store(store: &dyn AuditStore) -> Result<VerifyStats, AuditError> {
+    let mut chain = ChainCursor::default();
+    let mut stats = VerifyStats::default();
+    for (idx, line) in store.read_lines()?.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry = serde_json::from_str(line)?;
+        chain.verify(&entry, idx + 1)?;
+        if stats.entries == 0 {
+            stats.first_seq = entry.seq;
+        }
+        stats.entries += 1;
+        stats.last_seq = entry.seq;
+        stats.last_hash = Some(entry.hash);
     }
+    Ok(stats)
 }
 
 pub fn verify_chain(path: impl AsRef<Path>) -> Result<(), AuditError> {
-    let path = path.as_ref();
+    verify_chain_stats(path).map(|_| ())
+}
+
+/// Totals for a log that passed [`verify_chain_stats`].
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct VerifyStats {
+    pub entries: u64,
+    /// `0` when the log is empty; sequence numbers start at 1.
+    pub first_seq: u64,
+    pub last_seq: u64,
+    pub last_hash: Option<String>,
+}
+
+/// Verifies the chain like [`verify_chain`] and reports what was checked.
+pub fn verify_chain_stats(path: impl AsRef<Path>) -> Result<VerifyStats, AuditError> {
+    let mut stats = VerifyStats::default();
+    for_each_verified(path.as_ref(), |entry| {
+        if stats.entries == 0 {
+            stats.first_seq = entry.seq;
+        }
+        stats.entries += 1;
+        stats.last_seq = entry.seq;
+        stats.last_hash = Some(entry.hash.clone());
+    })?;
+    Ok(stats)
+}
+
+/// Position up to which a log has already been verified. Storing it between
+/// runs is up to the caller; the default checkpoint means "from the start".
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VerifyCheckpoint {
+    pub last_seq: u64,
+    pub last_hash: Option<String>,
+}
+
+/// Verifies only the entries after `checkpoint`, taking its hash on trust as
+/// the expected `prev_hash` of the next entry. Returns the checkpoint for the
+/// end of the log (unchanged if nothing was appended).
+pub fn verify_from_checkpoint(
+    path: impl AsRef<Path>,
+    checkpoint: &VerifyCheckpoint,
+) -> Result<VerifyCheckpoint, AuditError> {
+    let mut next = checkpoint.clone();
+    for_each_verified_from(path.as_ref(), checkpoint, |entry| {
+        next.last_seq = entry.seq;
+        next.last_hash = Some(entry.hash.clone());
+    })?;
+    Ok(next)
+}
+
+/// Returns the entries whose `unix_ms` lies in `from_ms..=to_ms`, in log order.
+/// The whole log is verified while scanning, so corruption anywhere is an
+/// error even outside the range.
+pub fn query_time_range(
+    path: impl AsRef<Path>,
+    from_ms: u64,
+    to_ms: u64,
+) -> Result<Vec<AuditEntry>, AuditError> {
+    let mut entries = Vec::new();
+    for_each_verified(path.as_ref(), |entry| {
+        if (from_ms..=to_ms).contains(&entry.unix_ms) {
+            entries.push(entry.clone());
+        }
+    })?;
+    Ok(entries)
+}
+
+/// Number of actors/targets reported by [`summarize`].
+pub const SUMMARY_TOP_N: usize = 5;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct AuditSummary {
+    pub total: u64,
+    pub allowed: u64,
+    pub denied: u64,
+    /// Most frequent actors with their counts, most frequent first.
+    pub top_actors: Vec<(String, u64)>,
+    /// Most frequent targets with their counts, most frequent first.
+    pub top_targets: Vec<(String, u64)>,
+}
+
+/// Summarizes a verified log, reporting the top [`SUMMARY_TOP_N`] actors and targets.
+pub fn summarize(path: impl AsRef<Path>) -> Result<AuditSummary, AuditError> {
+    summarize_top(path, SUMMARY_TOP_N)
+}
+
+/// Like [`summarize`], reporting up to `top_n` actors and targets.
+pub fn summarize_top(path: impl AsRef<Path>, top_n: usize) -> Result<AuditSummary, AuditError> {
+    let mut total = 0u64;
+    let mut allowed = 0u64;
+    let mut actors: HashMap<String, u64> = HashMap::new();
+    let mut targets: HashMap<String, u64> = HashMap::new();
+
+    for_each_verified(path.as_ref(), |entry| {
+        total += 1;
+        if entry.event.allowed {
+            allowed += 1;
+        }
+        *actors.entry(entry.event.actor.clone()).or_default() += 1;
+        *targets.entry(entry.event.target.clone()).or_default() += 1;
+    })?;
+
+    Ok(AuditSummary {
+        total,
+        allowed,
+        denied: total - allowed,
+        top_actors: top_counts(actors, top_n),
+        top_targets: top_counts(targets, top_n),
+    })
+}
+
+/// Sorts by count (descending), then name, and keeps the first `n`.
+fn top_counts(counts: HashMap<String, u64>, n: usize) -> Vec<(String, u64)> {
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(n);
+    counts
+}
+
+/// Reads the log line by line, verifying seq/hash linkage before handing
+/// each entry to `f`.
+fn for_each_verified(path: &Path, f: impl FnMut(&AuditEntry)) -> Result<(), AuditError> {
+    for_each_verified_from(path, &VerifyCheckpoint::default(), f)
+}
+
+/// Like [`for_each_verified`], skipping entries up to and including `start`.
+fn for_each_verified_from(
+    path: &Path,
+    start: &VerifyCheckpoint,
+    mut f: impl FnMut(&AuditEntry),
+) -> Result<(), AuditError> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
 
-    let mut expected_prev: Option<String> = None;
-    let mut expected_seq: u64 = 1;
+    let mut chain = ChainCursor {
+        expected_seq: start.last_seq.saturating_add(1),
+        expected_prev: start.last_hash.clone(),
+    };
+    let mut reached_start = start.last_seq == 0;
 
     for (idx, line_res) in reader.lines().enumerate() {
         let line = line_res?;
@@ -191,20 +446,52 @@ pub fn verify_chain(path: impl AsRef<Path>) -> Result<(), AuditError> {
             continue;
         }
         let entry: AuditEntry = serde_json::from_str(&line)?;
+        if !reached_start {
+            reached_start = entry.seq == start.last_seq;
+            continue;
+        }
+        chain.verify(&entry, idx + 1)?;
+        f(&entry);
+    }
+
+    if !reached_start {
+        return Err(AuditError::Invalid(format!(
+            "log ends before checkpoint seq {}",
+            start.last_seq
+        )));
+    }
+    Ok(())
+}
+
+/// The seq and `prev_hash` the next entry of a chain must carry.
+struct ChainCursor {
+    expected_seq: u64,
+    expected_prev: Option<String>,
+}
 
-        if entry.seq != expected_seq {
+impl Default for ChainCursor {
+    fn default() -> Self {
+        Self {
+            expected_seq: 1,
+            expected_prev: None,
+        }
+    }
+}
+
+impl ChainCursor {
+    /// Checks `entry` (found at `line`) against the chain, then advances past it.
+    fn verify(&mut self, entry: &AuditEntry, line: usize) -> Result<(), AuditError> {
+        if entry.seq != self.expected_seq {
             return Err(AuditError::Invalid(format!(
                 "seq mismatch at line {}: expected {}, got {}",
-                idx + 1,
-                expected_seq,
-                entry.seq
+                line, self.expected_seq, entry.seq
             )));
         }
 
-        if entry.prev_hash != expected_prev {
+        if entry.prev_hash != self.expected_prev {
             return Err(AuditError::Invalid(format!(
                 "prev_hash mismatch at line {}",
-                idx + 1
+                line
             )));
         }
 
@@ -218,15 +505,183 @@ pub fn verify_chain(path: impl AsRef<Path>) -> Result<(), AuditError> {
         if expected_hash != entry.hash {
             return Err(AuditError::Invalid(format!(
                 "hash mismatch at line {}",
-                idx + 1
+                line
             )));
         }
 
-        expected_prev = Some(entry.hash);
-        expected_seq = expected_seq.saturating_add(1);
+        self.expected_prev = Some(entry.hash.clone());
+        self.expected_seq = self.expected_seq.saturating_add(1);
+        Ok(())
     }
+}
 
-    Ok(())
+/// How often [`follow`] checks the log for appended entries.
+pub const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `tail -f` over the log: hands every verified entry with a seq above
+/// `from_seq` to `callback`, then waits for more. Returns once `callback`
+/// returns `false`, or with an error if the chain breaks. When the file is
+/// rotated (replaced or truncated), following restarts at the top of the new file.
+pub fn follow(
+    path: impl AsRef<Path>,
+    from_seq: u64,
+    callback: impl FnMut(&AuditEntry) -> bool,
+) -> Result<(), AuditError> {
+    follow_every(path, from_seq, FOLLOW_POLL_INTERVAL, callback)
+}
+
+/// Like [`follow`], polling every `interval`.
+pub fn follow_every(
+    path: impl AsRef<Path>,
+    from_seq: u64,
+    interval: Duration,
+    mut callback: impl FnMut(&AuditEntry) -> bool,
+) -> Result<(), AuditError> {
+    let path = path.as_ref();
+    let mut skip_through = from_seq;
+    loop {
+        let file = File::open(path)?;
+        let id = file_id(&file.metadata()?);
+        let mut reader = BufReader::new(file);
+        let mut chain = ChainCursor::default();
+        let mut line = String::new();
+        let mut line_no = 0usize;
+        let mut offset = 0u64;
+
+        loop {
+            let read = reader.read_line(&mut line)?;
+            offset += read as u64;
+            if read > 0 && line.ends_with('\n') {
+                line_no += 1;
+                if !line.trim().is_empty() {
+                    let entry: AuditEntry = serde_json::from_str(&line)?;
+                    chain.verify(&entry, line_no)?;
+                    if entry.seq > skip_through && !callback(&entry) {
+                        return Ok(());
+                    }
+                }
+                line.clear();
+                continue;
+            }
+            // At the end of the file, possibly holding a partly written line.
+            std::thread::sleep(interval);
+            if rotated(path, id, offset)? {
+                break;
+            }
+        }
+        // Everything in a rotated-in file is new.
+        skip_through = 0;
+    }
+}
+
+/// Whether `path` no longer refers to the file being read (identified by
+/// `id`, read up to `offset`). A missing path is not yet rotated.
+fn rotated(path: &Path, id: Option<u64>, offset: u64) -> Result<bool, AuditError> {
+    match std::fs::metadata(path) {
+        Ok(meta) => Ok(meta.len() < offset || file_id(&meta) != id),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+fn file_id(meta: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+/// Rotation is only detected by truncation where inodes are unavailable.
+#[cfg(not(unix))]
+fn file_id(_meta: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Rotated segments of `path` (`<name>.N` or `<name>.N.gz`, as left by
+/// logrotate), newest (lowest `N`) first.
+pub fn rotated_segments(path: impl AsRef<Path>) -> Result<Vec<PathBuf>, AuditError> {
+    let path = path.as_ref();
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.", name);
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(suffix) = file_name.to_str().and_then(|n| n.strip_prefix(&prefix)) else {
+            continue;
+        };
+        let gz = suffix.ends_with(".gz");
+        if let Ok(n) = suffix.trim_end_matches(".gz").parse::<u64>() {
+            segments.push(((n, gz), entry.path()));
+        }
+    }
+    segments.sort();
+    Ok(segments.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Deletes the oldest rotated segments of `path` until it and its segments
+/// take at most `max_total_bytes`. The live file and the newest segment are
+/// never deleted, even if they alone exceed the cap. Returns what was deleted.
+pub fn prune_segments(
+    path: impl AsRef<Path>,
+    max_total_bytes: u64,
+) -> Result<Vec<PathBuf>, AuditError> {
+    let path = path.as_ref();
+    let size = |path: &Path| std::fs::metadata(path).map_or(0, |meta| meta.len());
+    let mut segments = rotated_segments(path)?;
+    let mut total = size(path) + segments.iter().map(|s| size(s)).sum::<u64>();
+    let mut pruned = Vec::new();
+    while total > max_total_bytes && segments.len() > 1 {
+        let Some(oldest) = segments.pop() else {
+            break;
+        };
+        let len = size(&oldest);
+        std::fs::remove_file(&oldest)?;
+        total -= len;
+        pruned.push(oldest);
+    }
+    Ok(pruned)
+}
+
+/// Re-runs [`prune_segments`] whenever `path` has been rotated since the
+/// last [`check`](Self::check), so the cap holds for a long-running writer.
+pub struct SegmentPruner {
+    path: PathBuf,
+    max_total_bytes: u64,
+    id: Option<u64>,
+    len: u64,
+}
+
+impl SegmentPruner {
+    /// Starts watching `path` as it is now; nothing is pruned until it rotates.
+    pub fn new(path: impl AsRef<Path>, max_total_bytes: u64) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let meta = std::fs::metadata(&path).ok();
+        Self {
+            id: meta.as_ref().and_then(file_id),
+            len: meta.map_or(0, |meta| meta.len()),
+            path,
+            max_total_bytes,
+        }
+    }
+
+    /// Prunes if the log was rotated since the last check; returns what was deleted.
+    pub fn check(&mut self) -> Result<Vec<PathBuf>, AuditError> {
+        let was_rotated = rotated(&self.path, self.id, self.len)?;
+        if let Ok(meta) = std::fs::metadata(&self.path) {
+            self.id = file_id(&meta);
+            self.len = meta.len();
+        }
+        if !was_rotated {
+            return Ok(Vec::new());
+        }
+        prune_segments(&self.path, self.max_total_bytes)
+    }
 }
 
 fn read_last_entry(path: &Path) -> Result<Option<AuditEntry>, AuditError> {
@@ -250,12 +705,184 @@ mod tests {
     use super::*;
     use std::fs;
 
+    #[test]
+    fn fallback_sha256_matches_default_hash() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31) as u8).collect();
+        for len in [0, 1, 55, 56, 63, 64, 65, 119, 120, 1000] {
+            assert_eq!(
+                crate::sha256::digest(&data[..len]),
+                sha256(&data[..len]),
+                "len {}",
+                len
+            );
+        }
+    }
+
     fn temp_path(name: &str) -> PathBuf {
         let mut p = std::env::temp_dir();
         p.push(format!("toppy-audit-{}-{}", name, std::process::id()));
         p
     }
 
+    fn event(target: &str) -> AuditEvent {
+        AuditEvent {
+            actor: "alice".to_string(),
+            action: "connect".to_string(),
+            target: target.to_string(),
+            allowed: true,
+            reason: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn append_batch_writes_verifiable_chain() {
+        let path = temp_path("batch.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut w = AuditChainWriter::open(&path).unwrap();
+        w.append(1, event("127.0.0.1:22")).unwrap();
+        let entries = w
+            .append_batch(vec![
+                (2, event("127.0.0.1:80")),
+                (3, event("127.0.0.1:443")),
+            ])
+            .unwrap();
+        assert_eq!(entries.iter().map(|e| e.seq).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(entries[1].prev_hash.as_ref(), Some(&entries[0].hash));
+        w.append(4, event("127.0.0.1:8080")).unwrap();
+
+        verify_chain(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn query_time_range_returns_inclusive_sub_range() {
+        let path = temp_path("range.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut w = AuditChainWriter::open(&path).unwrap();
+        for (unix_ms, port) in [(100, 1), (200, 2), (300, 3), (400, 4)] {
+            w.append(unix_ms, event(&format!("127.0.0.1:{}", port)))
+                .unwrap();
+        }
+
+        let entries = query_time_range(&path, 200, 300).unwrap();
+        let seqs: Vec<u64> = entries.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [2, 3]);
+        assert_eq!(entries[0].event.target, "127.0.0.1:2");
+        assert!(query_time_range(&path, 401, 500).unwrap().is_empty());
+
+        // A tampered entry outside the range still fails the query.
+        let data = fs::read_to_string(&path).unwrap();
+        fs::write(&path, data.replacen("127.0.0.1:4", "127.0.0.1:9", 1)).unwrap();
+        assert!(matches!(
+            query_time_range(&path, 200, 300),
+            Err(AuditError::Invalid(_))
+        ));
+        let _ = fs::remove_file(&path);
+    }
+
+    /// Fails its `fail_call`-th append after writing half of it.
+    struct HalfWrite {
+        inner: FileStore,
+        calls: usize,
+        fail_call: usize,
+    }
+
+    impl AuditStore for HalfWrite {
+        fn append_line(&mut self, line: &[u8]) -> Result<(), AuditError> {
+            self.calls += 1;
+            if self.calls != self.fail_call {
+                return self.inner.append_line(line);
+            }
+            self.inner.append_with(line, |file, buf| {
+                file.write_all(&buf[..buf.len() / 2])?;
+                Err(io::Error::other("disk full"))
+            })
+        }
+
+        fn read_lines(&self) -> Result<Vec<String>, AuditError> {
+            self.inner.read_lines()
+        }
+    }
+
+    #[test]
+    fn append_batch_truncates_partial_write() {
+        let path = temp_path("batch-fail.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut w = AuditChainWriter::with_store(Box::new(HalfWrite {
+            inner: FileStore::open(&path).unwrap(),
+            calls: 0,
+            fail_call: 2,
+        }))
+        .unwrap();
+        w.append(1, event("127.0.0.1:22")).unwrap();
+        let err = w
+            .append_batch(vec![
+                (2, event("127.0.0.1:80")),
+                (3, event("127.0.0.1:443")),
+            ])
+            .unwrap_err();
+        assert!(matches!(err, AuditError::Io(_)));
+        verify_chain(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        // The writer still continues the chain from the last durable entry.
+        let entry = w.append(2, event("127.0.0.1:80")).unwrap();
+        assert_eq!(entry.seq, 2);
+        verify_chain(&path).unwrap();
+        let _ = fs::remove_file(&path);
+    }
+
+    /// Lines shared with the test so they can be inspected after the writer is gone.
+    #[derive(Clone, Default)]
+    struct MemoryStore(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl AuditStore for MemoryStore {
+        fn append_line(&mut self, line: &[u8]) -> Result<(), AuditError> {
+            let text = std::str::from_utf8(line)
+                .map_err(|e| AuditError::Invalid(format!("non-utf8 line: {}", e)))?;
+            let mut lines = self.0.lock().unwrap();
+            lines.extend(text.lines().map(str::to_string));
+            Ok(())
+        }
+
+        fn read_lines(&self) -> Result<Vec<String>, AuditError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[test]
+    fn chain_writes_and_verifies_through_custom_store() {
+        let store = MemoryStore::default();
+        let mut w = AuditChainWriter::with_store(Box::new(store.clone())).unwrap();
+        w.append(1, event("127.0.0.1:22")).unwrap();
+        w.append_batch(vec![
+            (2, event("127.0.0.1:80")),
+            (3, event("127.0.0.1:443")),
+        ])
+        .unwrap();
+        let stats = verify_store(w.store()).unwrap();
+        assert_eq!((stats.entries, stats.first_seq, stats.last_seq), (3, 1, 3));
+
+        // A new writer picks the chain up where the store ends.
+        drop(w);
+        let mut w = AuditChainWriter::with_store(Box::new(store.clone())).unwrap();
+        assert_eq!(w.append(4, event("127.0.0.1:53")).unwrap().seq, 4);
+        assert_eq!(verify_store(&store).unwrap().last_seq, 4);
+
+        let tampered = store.0.lock().unwrap()[1].replacen("127.0.0.1:80", "127.0.0.1:81", 1);
+        store.0.lock().unwrap()[1] = tampered;
+        assert!(matches!(verify_store(&store), Err(AuditError::Invalid(_))));
+    }
+
     #[test]
     fn audit_chain_roundtrip_and_verify() {
         let path = temp_path("roundtrip.jsonl");
@@ -270,6 +897,7 @@ mod tests {
                 target: "127.0.0.1:22".to_string(),
                 allowed: true,
                 reason: None,
+                metadata: BTreeMap::new(),
             },
         )
         .unwrap();
@@ -281,6 +909,7 @@ mod tests {
                 target: "127.0.0.1:23".to_string(),
                 allowed: false,
                 reason: Some("not allowed".to_string()),
+                metadata: BTreeMap::new(),
             },
         )
         .unwrap();
@@ -289,19 +918,74 @@ mod tests {
 
         // Re-open and append more.
         let mut w2 = AuditChainWriter::open(&path).unwrap();
-        w2.append(
-            3,
-            AuditEvent {
-                actor: "bob".to_string(),
-                action: "doctor".to_string(),
-                target: "cfg".to_string(),
-                allowed: true,
-                reason: None,
-            },
-        )
-        .unwrap();
+        let last = w2
+            .append(
+                3,
+                AuditEvent {
+                    actor: "bob".to_string(),
+                    action: "doctor".to_string(),
+                    target: "cfg".to_string(),
+                    allowed: true,
+                    reason: None,
+                    metadata: BTreeMap::new(),
+                },
+            )
+            .unwrap();
 
         verify_chain(&path).unwrap();
+        let stats = verify_chain_stats(&path).unwrap();
+        assert_eq!(stats.entries, 3);
+        assert_eq!((stats.first_seq, stats.last_seq), (1, 3));
+        assert_eq!(stats.last_hash, Some(last.hash));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn verify_from_checkpoint_checks_only_later_entries() {
+        let path = temp_path("checkpoint.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut w = AuditChainWriter::open(&path).unwrap();
+        let mut entries = Vec::new();
+        for n in 1..=5 {
+            entries.push(w.append(n, event(&format!("127.0.0.1:{}", n))).unwrap());
+        }
+        let checkpoint = VerifyCheckpoint {
+            last_seq: 3,
+            last_hash: Some(entries[2].hash.clone()),
+        };
+        let end = verify_from_checkpoint(&path, &checkpoint).unwrap();
+        assert_eq!(end.last_seq, 5);
+        assert_eq!(end.last_hash, Some(entries[4].hash.clone()));
+        // Nothing new since `end`.
+        assert_eq!(verify_from_checkpoint(&path, &end).unwrap(), end);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let tamper_line = |n: usize| {
+            let lines: Vec<String> = contents
+                .lines()
+                .enumerate()
+                .map(|(i, line)| {
+                    if i == n {
+                        line.replace("\"allowed\":true", "\"allowed\":false")
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect();
+            fs::write(&path, lines.join("\n") + "\n").unwrap();
+        };
+
+        // Entries before the checkpoint are trusted...
+        tamper_line(1);
+        assert_eq!(verify_from_checkpoint(&path, &checkpoint).unwrap(), end);
+        // ...while those after it are still verified.
+        tamper_line(3);
+        assert!(matches!(
+            verify_from_checkpoint(&path, &checkpoint),
+            Err(AuditError::Invalid(_))
+        ));
 
         let _ = fs::remove_file(&path);
     }
@@ -321,6 +1005,7 @@ mod tests {
                     target: "127.0.0.1:22".to_string(),
                     allowed: true,
                     reason: None,
+                    metadata: BTreeMap::new(),
                 },
             )
             .unwrap();
@@ -342,4 +1027,214 @@ mod tests {
 
         let _ = fs::remove_file(&path);
     }
+
+    #[test]
+    fn audit_metadata_roundtrips_and_is_hashed() {
+        let path = temp_path("metadata.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut with_meta = event("127.0.0.1:22");
+        with_meta.metadata = BTreeMap::from([
+            ("bytes".to_string(), "512".to_string()),
+            ("request_id".to_string(), "r-1".to_string()),
+        ]);
+        {
+            let mut w = AuditChainWriter::open(&path).unwrap();
+            w.append(1, event("127.0.0.1:22")).unwrap();
+            w.append(2, with_meta.clone()).unwrap();
+        }
+        let entries = query_time_range(&path, 0, u64::MAX).unwrap();
+        assert_eq!(entries[1].event, with_meta);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert!(!lines[0].contains("metadata"));
+        assert!(lines[1].contains(r#""metadata":{"bytes":"512","request_id":"r-1"}"#));
+
+        let tampered = contents.replace(r#""bytes":"512""#, r#""bytes":"513""#);
+        fs::write(&path, tampered).unwrap();
+        assert!(matches!(verify_chain(&path), Err(AuditError::Invalid(_))));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn audit_summarize_counts_and_top_n() {
+        let path = temp_path("summary.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut w = AuditChainWriter::open(&path).unwrap();
+        let events = [
+            ("alice", "10.0.0.1:22", true),
+            ("alice", "10.0.0.1:22", true),
+            ("bob", "10.0.0.2:443", false),
+            ("alice", "10.0.0.3:80", false),
+            ("carol", "10.0.0.1:22", true),
+        ];
+        for (i, (actor, target, allowed)) in events.iter().enumerate() {
+            w.append(
+                i as u64,
+                AuditEvent {
+                    actor: actor.to_string(),
+                    action: "connect".to_string(),
+                    target: target.to_string(),
+                    allowed: *allowed,
+                    reason: None,
+                    metadata: BTreeMap::new(),
+                },
+            )
+            .unwrap();
+        }
+
+        let summary = summarize_top(&path, 2).unwrap();
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.allowed, 3);
+        assert_eq!(summary.denied, 2);
+        assert_eq!(
+            summary.top_actors,
+            vec![("alice".to_string(), 3), ("bob".to_string(), 1)]
+        );
+        assert_eq!(
+            summary.top_targets,
+            vec![
+                ("10.0.0.1:22".to_string(), 3),
+                ("10.0.0.2:443".to_string(), 1)
+            ]
+        );
+
+        // Corruption is reported instead of a partial summary.
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(
+            &path,
+            contents.replace("\"allowed\":false", "\"allowed\":true"),
+        )
+        .unwrap();
+        assert!(matches!(summarize(&path), Err(AuditError::Invalid(_))));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn follow_delivers_appended_entries_in_order_across_rotation() {
+        let path = temp_path("follow.jsonl");
+        let rotated_path = temp_path("follow.jsonl.1");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated_path);
+
+        let mut w = AuditChainWriter::open(&path).unwrap();
+        w.append(1, event("127.0.0.1:1")).unwrap();
+        w.append(2, event("127.0.0.1:2")).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let follower = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let mut remaining = 4;
+                follow_every(&path, 1, Duration::from_millis(10), |entry| {
+                    tx.send((entry.seq, entry.event.target.clone())).unwrap();
+                    remaining -= 1;
+                    remaining > 0
+                })
+            })
+        };
+        let recv = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(recv(), (2, "127.0.0.1:2".to_string()));
+
+        w.append(3, event("127.0.0.1:3")).unwrap();
+        w.append(4, event("127.0.0.1:4")).unwrap();
+        assert_eq!(recv(), (3, "127.0.0.1:3".to_string()));
+        assert_eq!(recv(), (4, "127.0.0.1:4".to_string()));
+
+        // Rotation: the new file starts its own chain at seq 1.
+        fs::rename(&path, &rotated_path).unwrap();
+        let mut w = AuditChainWriter::open(&path).unwrap();
+        w.append(5, event("127.0.0.1:5")).unwrap();
+        assert_eq!(recv(), (1, "127.0.0.1:5".to_string()));
+
+        follower.join().unwrap().unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated_path);
+    }
+
+    #[test]
+    fn prune_segments_drops_oldest_until_under_cap() {
+        let dir = temp_path("prune");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        for (name, len) in [
+            ("audit.jsonl", 100),
+            ("audit.jsonl.1", 100),
+            ("audit.jsonl.2.gz", 100),
+            ("audit.jsonl.3.gz", 100),
+            ("audit.jsonl.10", 100),
+            ("audit.jsonl.bak", 100),
+        ] {
+            fs::write(dir.join(name), vec![b'x'; len]).unwrap();
+        }
+        assert_eq!(
+            rotated_segments(&path).unwrap(),
+            [
+                "audit.jsonl.1",
+                "audit.jsonl.2.gz",
+                "audit.jsonl.3.gz",
+                "audit.jsonl.10"
+            ]
+            .map(|name| dir.join(name))
+        );
+
+        let pruned = prune_segments(&path, 350).unwrap();
+        assert_eq!(
+            pruned,
+            [dir.join("audit.jsonl.10"), dir.join("audit.jsonl.3.gz")]
+        );
+        assert!(dir.join("audit.jsonl.2.gz").exists());
+        assert!(dir.join("audit.jsonl.bak").exists());
+
+        // The live file and newest segment stay even over the cap.
+        prune_segments(&path, 0).unwrap();
+        assert_eq!(
+            rotated_segments(&path).unwrap(),
+            [dir.join("audit.jsonl.1")]
+        );
+        assert!(path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn segment_pruner_prunes_after_each_rotation() {
+        let dir = temp_path("pruner");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let rotate = || {
+            for n in (1..10).rev() {
+                let from = dir.join(format!("audit.jsonl.{}", n));
+                if from.exists() {
+                    fs::rename(&from, dir.join(format!("audit.jsonl.{}", n + 1))).unwrap();
+                }
+            }
+            fs::rename(&path, dir.join("audit.jsonl.1")).unwrap();
+            fs::write(&path, vec![b'x'; 100]).unwrap();
+        };
+        fs::write(&path, vec![b'x'; 100]).unwrap();
+        let mut pruner = SegmentPruner::new(&path, 250);
+
+        // Appends alone are not a rotation.
+        fs::write(&path, vec![b'x'; 200]).unwrap();
+        assert!(pruner.check().unwrap().is_empty());
+
+        rotate();
+        assert!(pruner.check().unwrap().is_empty());
+        rotate();
+        assert_eq!(pruner.check().unwrap(), [dir.join("audit.jsonl.2")]);
+        assert!(pruner.check().unwrap().is_empty());
+        rotate();
+        assert_eq!(pruner.check().unwrap(), [dir.join("audit.jsonl.2")]);
+        assert_eq!(
+            rotated_segments(&path).unwrap(),
+            [dir.join("audit.jsonl.1")]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -1,15 +1,100 @@
 use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct JwtConfig {
     pub secret: String,
     pub issuer: Option<String>,
     pub audience: Option<String>,
+    /// Reject tokens without an `exp` claim.
+    ///
+    /// Setting this to `false` admits legacy tokens that omit `exp`, but only
+    /// when `max_token_age_secs` is set and the token carries an `iat` within
+    /// that window. This trades revocation-by-expiry for a lifetime computed
+    /// on the gateway, so a leaked exp-less token stays valid for up to
+    /// `max_token_age_secs` after issuance and cannot be shortened by the
+    /// issuer. Keep the window short.
+    pub require_exp: bool,
+    /// Maximum accepted age (now - `iat`) for tokens without `exp`.
+    pub max_token_age_secs: Option<u64>,
+}
+
+impl JwtConfig {
+    /// Rejects configs that could never validate a token, so misconfiguration
+    /// surfaces at startup rather than on the first request.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret.trim().is_empty() {
+            return Err("jwt secret must not be empty".to_string());
+        }
+        for (name, value) in [("issuer", &self.issuer), ("audience", &self.audience)] {
+            if let Some(value) = value {
+                if value.trim().is_empty() {
+                    return Err(format!("jwt {} must not be empty when set", name));
+                }
+                if value.trim() != value || value.chars().any(char::is_control) {
+                    return Err(format!(
+                        "jwt {} {:?} contains whitespace or control characters",
+                        name, value
+                    ));
+                }
+            }
+        }
+        if self.max_token_age_secs == Some(0) {
+            return Err("jwt max token age must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Clock skew tolerated on `exp`/`iat`, in seconds.
+pub const JWT_LEEWAY_SECS: u64 = 60;
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Enforces `max_token_age_secs` for tokens that omit `exp`.
+fn check_token_age(claims: &serde_json::Value, cfg: &JwtConfig, leeway: u64) -> Result<(), String> {
+    if claims.get("exp").is_some() {
+        return Ok(());
+    }
+    let max_age = cfg
+        .max_token_age_secs
+        .ok_or_else(|| "jwt validation failed: missing exp and no max token age".to_string())?;
+    let iat = claims
+        .get("iat")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| "jwt validation failed: missing exp and iat".to_string())?;
+    let now = now_secs();
+    if iat > now.saturating_add(leeway) {
+        return Err("jwt validation failed: iat is in the future".to_string());
+    }
+    let age = now.saturating_sub(iat);
+    if age > max_age.saturating_add(leeway) {
+        return Err(format!(
+            "jwt validation failed: token age {}s exceeds max {}s",
+            age, max_age
+        ));
+    }
+    Ok(())
 }
 
 pub fn validate_jwt_hs256(token: &str, cfg: &JwtConfig) -> Result<(), String> {
+    validate_jwt_hs256_exp(token, cfg).map(|_| ())
+}
+
+/// Like [`validate_jwt_hs256`], but also returns the token's `exp` claim so
+/// callers can end long-lived sessions once it passes.
+pub fn validate_jwt_hs256_exp(token: &str, cfg: &JwtConfig) -> Result<Option<u64>, String> {
     let mut validation = Validation::new(Algorithm::HS256);
+    validation.leeway = JWT_LEEWAY_SECS;
     validation.validate_exp = true;
+    if !cfg.require_exp {
+        validation.required_spec_claims.remove("exp");
+    }
     if let Some(issuer) = cfg.issuer.as_deref() {
         validation.set_issuer(&[issuer]);
     }
@@ -17,13 +102,85 @@ pub fn validate_jwt_hs256(token: &str, cfg: &JwtConfig) -> Result<(), String> {
         validation.set_audience(&[audience]);
     }
 
-    decode::<serde_json::Value>(
+    let data = decode::<serde_json::Value>(
         token,
         &DecodingKey::from_secret(cfg.secret.as_bytes()),
         &validation,
     )
-    .map(|_| ())
-    .map_err(|e| format!("jwt validation failed: {}", e))
+    .map_err(|e| format!("jwt validation failed: {}", e))?;
+    check_token_age(&data.claims, cfg, validation.leeway)?;
+    Ok(data.claims.get("exp").and_then(|v| v.as_u64()))
+}
+
+fn decode_unverified(token: &str) -> Result<serde_json::Value, String> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+    decode::<serde_json::Value>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map(|data| data.claims)
+        .map_err(|e| format!("jwt decode failed: {}", e))
+}
+
+/// The `sub` claim of a JWT, read without checking the signature; `None` for
+/// anything that is not a JWT. Only fit for labelling, e.g. rejected requests.
+pub fn unverified_subject(token: &str) -> Option<String> {
+    decode_unverified(token)
+        .ok()?
+        .get("sub")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Claims of an unverified token plus the checks from [`JwtConfig`] it fails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtInspection {
+    pub claims: serde_json::Value,
+    pub warnings: Vec<String>,
+}
+
+/// Decodes `token` without checking its signature and reports `iss`/`aud`/`exp`
+/// mismatches against `cfg` (`secret` is unused). For diagnostics only: never
+/// treat an inspected token as authenticated.
+pub fn inspect_jwt(token: &str, cfg: &JwtConfig) -> Result<JwtInspection, String> {
+    let claims = decode_unverified(token)?;
+
+    let mut warnings = Vec::new();
+    if let Some(issuer) = cfg.issuer.as_deref() {
+        match claims.get("iss").and_then(|v| v.as_str()) {
+            Some(iss) if iss == issuer => {}
+            Some(iss) => warnings.push(format!("iss {} does not match expected {}", iss, issuer)),
+            None => warnings.push(format!("missing iss (expected {})", issuer)),
+        }
+    }
+    if let Some(audience) = cfg.audience.as_deref() {
+        let aud: Vec<&str> = match claims.get("aud") {
+            Some(serde_json::Value::String(aud)) => vec![aud.as_str()],
+            Some(serde_json::Value::Array(values)) => {
+                values.iter().filter_map(|v| v.as_str()).collect()
+            }
+            _ => Vec::new(),
+        };
+        if aud.is_empty() {
+            warnings.push(format!("missing aud (expected {})", audience));
+        } else if !aud.contains(&audience) {
+            warnings.push(format!(
+                "aud {} does not match expected {}",
+                aud.join(","),
+                audience
+            ));
+        }
+    }
+    match claims.get("exp").and_then(|v| v.as_u64()) {
+        Some(exp) if exp.saturating_add(JWT_LEEWAY_SECS) < now_secs() => {
+            warnings.push(format!("token expired at {}", exp))
+        }
+        Some(_) => {}
+        None if cfg.require_exp => warnings.push("missing exp".to_string()),
+        None => {}
+    }
+    Ok(JwtInspection { claims, warnings })
 }
 
 #[cfg(test)]
@@ -31,7 +188,6 @@ mod tests {
     use super::*;
     use jsonwebtoken::{encode, EncodingKey, Header};
     use serde::{Deserialize, Serialize};
-    use std::time::{SystemTime, UNIX_EPOCH};
 
     #[derive(Debug, Serialize, Deserialize)]
     struct TestClaims {
@@ -41,11 +197,33 @@ mod tests {
         exp: usize,
     }
 
-    fn now_secs() -> usize {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() as usize
+    #[derive(Debug, Serialize, Deserialize)]
+    struct LegacyClaims {
+        sub: String,
+        iat: usize,
+    }
+
+    fn legacy_cfg(max_token_age_secs: Option<u64>) -> JwtConfig {
+        JwtConfig {
+            secret: "secret".to_string(),
+            issuer: None,
+            audience: None,
+            require_exp: false,
+            max_token_age_secs,
+        }
+    }
+
+    fn legacy_token(iat: usize) -> String {
+        let claims = LegacyClaims {
+            sub: "user-123".to_string(),
+            iat,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .expect("encode")
     }
 
     #[test]
@@ -54,7 +232,7 @@ mod tests {
             sub: "user-123".to_string(),
             iss: "https://issuer.example".to_string(),
             aud: "toppy".to_string(),
-            exp: now_secs() + 60,
+            exp: now_secs() as usize + 60,
         };
         let token = encode(
             &Header::default(),
@@ -67,9 +245,15 @@ mod tests {
             secret: "secret".to_string(),
             issuer: Some("https://issuer.example".to_string()),
             audience: Some("toppy".to_string()),
+            require_exp: true,
+            max_token_age_secs: None,
         };
 
         validate_jwt_hs256(&token, &cfg).expect("valid token");
+        assert_eq!(
+            validate_jwt_hs256_exp(&token, &cfg).expect("valid token"),
+            Some(claims.exp as u64)
+        );
     }
 
     #[test]
@@ -78,7 +262,7 @@ mod tests {
             sub: "user-123".to_string(),
             iss: "https://issuer.example".to_string(),
             aud: "toppy".to_string(),
-            exp: now_secs() + 60,
+            exp: now_secs() as usize + 60,
         };
         let token = encode(
             &Header::default(),
@@ -91,6 +275,8 @@ mod tests {
             secret: "wrong".to_string(),
             issuer: Some("https://issuer.example".to_string()),
             audience: Some("toppy".to_string()),
+            require_exp: true,
+            max_token_age_secs: None,
         };
 
         let err = validate_jwt_hs256(&token, &cfg).unwrap_err();
@@ -103,7 +289,7 @@ mod tests {
             sub: "user-123".to_string(),
             iss: "https://issuer.example".to_string(),
             aud: "toppy".to_string(),
-            exp: now_secs().saturating_sub(3600),
+            exp: (now_secs() as usize).saturating_sub(3600),
         };
         let token = encode(
             &Header::default(),
@@ -116,9 +302,77 @@ mod tests {
             secret: "secret".to_string(),
             issuer: Some("https://issuer.example".to_string()),
             audience: Some("toppy".to_string()),
+            require_exp: true,
+            max_token_age_secs: None,
         };
 
         let err = validate_jwt_hs256(&token, &cfg).unwrap_err();
         assert!(err.contains("jwt validation failed"));
     }
+
+    #[test]
+    fn jwt_validation_accepts_expless_token_within_max_age() {
+        let token = legacy_token(now_secs() as usize - 30);
+        validate_jwt_hs256(&token, &legacy_cfg(Some(300))).expect("valid token");
+    }
+
+    #[test]
+    fn jwt_validation_rejects_expless_token_beyond_max_age() {
+        let token = legacy_token(now_secs() as usize - 3600);
+        let err = validate_jwt_hs256(&token, &legacy_cfg(Some(300))).unwrap_err();
+        assert!(err.contains("exceeds max"));
+    }
+
+    #[test]
+    fn jwt_validation_rejects_expless_token_without_max_age() {
+        let token = legacy_token(now_secs() as usize);
+        assert!(validate_jwt_hs256(&token, &legacy_cfg(None)).is_err());
+    }
+
+    #[test]
+    fn jwt_validation_requires_exp_by_default() {
+        let token = legacy_token(now_secs() as usize);
+        let mut cfg = legacy_cfg(Some(300));
+        cfg.require_exp = true;
+        assert!(validate_jwt_hs256(&token, &cfg).is_err());
+    }
+
+    #[test]
+    fn inspect_flags_mismatched_audience() {
+        let claims = TestClaims {
+            sub: "user-123".to_string(),
+            iss: "https://issuer.example".to_string(),
+            aud: "other".to_string(),
+            exp: now_secs() as usize + 60,
+        };
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .expect("encode");
+        let mut cfg = JwtConfig {
+            secret: String::new(),
+            issuer: None,
+            audience: Some("toppy".to_string()),
+            require_exp: true,
+            max_token_age_secs: None,
+        };
+
+        let inspection = inspect_jwt(&token, &cfg).expect("inspect");
+        assert_eq!(inspection.claims["sub"], "user-123");
+        assert_eq!(
+            inspection.warnings,
+            ["aud other does not match expected toppy"]
+        );
+
+        cfg.audience = Some("other".to_string());
+        assert!(inspect_jwt(&token, &cfg)
+            .expect("inspect")
+            .warnings
+            .is_empty());
+        assert!(inspect_jwt("not-a-jwt", &cfg).is_err());
+        assert_eq!(unverified_subject(&token).as_deref(), Some("user-123"));
+        assert_eq!(unverified_subject("dev-token"), None);
+    }
 }
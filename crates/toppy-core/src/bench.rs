@@ -0,0 +1,96 @@
+//! Quick throughput probes for hot paths, run by `toppy bench`.
+//!
+//! These are sanity checks for regressions, not rigorous benchmarks: each
+//! case runs a fixed number of iterations once and reports ops/sec.
+
+use crate::rate::TokenBucket;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use toppy_proto::masque::{decode_varint, encode_varint};
+
+/// Iterations per case when `toppy bench` is run without `--iterations`.
+pub const DEFAULT_BENCH_ITERATIONS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub iterations: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn ops_per_sec(&self) -> f64 {
+        // Clamp so a too-fast run on a coarse clock still reports a rate.
+        let secs = self.elapsed.max(Duration::from_nanos(1)).as_secs_f64();
+        self.iterations as f64 / secs
+    }
+}
+
+/// Runs every case for `iterations` iterations.
+pub fn run_benches(iterations: u64) -> Vec<BenchResult> {
+    vec![
+        bench("varint.encode", iterations, varint_encode),
+        bench("varint.decode", iterations, varint_decode),
+        bench("rate.try_take", iterations, token_bucket_take),
+    ]
+}
+
+fn bench(name: &'static str, iterations: u64, run: fn(u64)) -> BenchResult {
+    let start = Instant::now();
+    run(iterations);
+    BenchResult {
+        name,
+        iterations,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Cycles through one value per encoding length.
+const VARINT_VALUES: [u64; 4] = [37, 15_293, 494_878_333, 151_288_809_941_952_652];
+
+fn varint_encode(iterations: u64) {
+    let mut out = Vec::with_capacity(8);
+    for i in 0..iterations {
+        out.clear();
+        let value = VARINT_VALUES[(i % 4) as usize];
+        let _ = encode_varint(black_box(value), &mut out);
+        black_box(&out);
+    }
+}
+
+fn varint_decode(iterations: u64) {
+    let encoded: Vec<Vec<u8>> = VARINT_VALUES
+        .iter()
+        .map(|value| {
+            let mut out = Vec::new();
+            let _ = encode_varint(*value, &mut out);
+            out
+        })
+        .collect();
+    for i in 0..iterations {
+        let _ = black_box(decode_varint(black_box(&encoded[(i % 4) as usize])));
+    }
+}
+
+fn token_bucket_take(iterations: u64) {
+    let mut bucket = TokenBucket::new(1_000, 1_000_000);
+    for i in 0..iterations {
+        black_box(bucket.try_take(1, Duration::from_micros(i)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_bench_run_reports_nonzero_throughput() {
+        let results = run_benches(100);
+        let names: Vec<&str> = results.iter().map(|r| r.name).collect();
+        assert_eq!(names, ["varint.encode", "varint.decode", "rate.try_take"]);
+        for result in &results {
+            assert_eq!(result.iterations, 100);
+            assert!(result.ops_per_sec() > 0.0, "{}", result.name);
+        }
+    }
+}
@@ -1,21 +1,258 @@
+use crate::logging::{log, LogFormat, LogLevel};
 use crate::policy::{Policy, PolicyConfig};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use toppy_proto::masque::max_udp_payload;
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+/// Gateway port assumed when `port` is unset.
+pub const DEFAULT_PORT: u16 = 4433;
+
+/// MTU assumed when `mtu` is unset.
+pub const DEFAULT_MTU: u16 = 1350;
+
+/// Smallest `mtu` accepted: QUIC needs 1200-byte datagrams (RFC 9000).
+pub const MIN_MTU: u16 = 1200;
+
+/// Largest `mtu` accepted: a jumbo Ethernet frame.
+pub const MAX_MTU: u16 = 9000;
+
+/// Unknown keys (at the top level and in profiles) are rejected, so a typo
+/// such as `gatway` fails to load instead of being silently ignored.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub gateway: Option<String>,
     pub port: Option<u16>,
+    /// Backup gateway tried when `gateway` cannot be reached.
+    pub fallback_gateway: Option<String>,
+    /// Port of `fallback_gateway`; defaults to `port`.
+    pub fallback_port: Option<u16>,
     pub ca_cert_path: Option<String>,
+    /// Skip gateway certificate verification in doctor's client checks (local
+    /// testing only); affected checks report `warn`.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
     pub server_name: Option<String>,
     pub auth_token: Option<String>,
+    /// `aud` the CLI expects on `auth_token`; `toppy token inspect` warns otherwise.
+    pub expected_audience: Option<String>,
     pub mtu: Option<u16>,
+    /// Largest UDP payload sent in one HTTP datagram; defaults to what fits in `mtu`.
+    pub max_datagram_size: Option<usize>,
     pub policy: Option<PolicyConfig>,
+    /// Standalone policy file used instead of an inline `[policy]`; relative
+    /// paths resolve against the config file's directory. See [`load_policy`].
+    pub policy_file: Option<String>,
+    /// Worker threads shared by all `toppy up` connections; unset keeps
+    /// two dedicated threads per connection.
+    pub proxy_max_workers: Option<usize>,
+    pub doctor: Option<DoctorConfig>,
+    /// Hash-chained audit log appended to by [`crate::audit::AuditChainWriter`].
+    pub audit_path: Option<String>,
+    /// Gateway settings used by `toppy gw`.
+    pub gw: Option<GatewayConfig>,
+    /// `error`, `warn`, `info` (default) or `debug`; `TOPPY_LOG` overrides.
+    pub log_level: Option<LogLevel>,
+    /// `text` (default) or `json`; `TOPPY_LOG_FORMAT` overrides.
+    pub log_format: Option<LogFormat>,
+    /// Named overrides (`[profiles.<name>]`) merged over the top-level values
+    /// when selected via `TOPPY_PROFILE`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DoctorConfig {
+    /// `host:port` targets checked against the policy on every doctor run.
+    #[serde(default)]
+    pub targets: Vec<String>,
+    /// Percent of critical check weight that must pass; when set, the overall
+    /// status is weighted instead of the worst check status.
+    pub overall_threshold: Option<u8>,
+}
+
+/// `[gw]` section; each field maps to the `TOPPY_GW_*` variable the gateway reads.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GatewayConfig {
+    /// Health/metrics HTTP listener (`TOPPY_GW_LISTEN`).
+    pub listen: Option<String>,
+    /// QUIC listener (`TOPPY_GW_QUIC_LISTEN`).
+    pub quic_listen: Option<String>,
+    /// Separate QUIC listener for the ping protocol (`TOPPY_GW_PING_LISTEN`).
+    pub ping_listen: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub token: Option<String>,
+    pub jwt_secret: Option<String>,
+    /// Expected JWT `iss`/`aud` (`TOPPY_GW_JWT_ISS`, `TOPPY_GW_JWT_AUD`).
+    pub jwt_iss: Option<String>,
+    pub jwt_aud: Option<String>,
+    /// `false` accepts tokens without `exp` (`TOPPY_GW_JWT_REQUIRE_EXP`).
+    pub jwt_require_exp: Option<bool>,
+    /// Age limit for tokens without `exp` (`TOPPY_GW_JWT_MAX_AGE_SECS`).
+    pub jwt_max_age_secs: Option<u64>,
+    /// `TOPPY_GW_JWT_REAUTH`.
+    pub jwt_reauth: Option<bool>,
+    /// `TOPPY_GW_REQUIRE_AUTH`.
+    pub require_auth: Option<bool>,
+    pub admin_token: Option<String>,
+    /// Policy file (`TOPPY_GW_POLICY`).
+    pub policy: Option<String>,
+    #[serde(default)]
+    pub allowed_sni: Vec<String>,
+    #[serde(default)]
+    pub source_allow: Vec<String>,
+    /// `TOPPY_GW_MAX_DATAGRAM_SIZE`.
+    pub max_datagram_size: Option<usize>,
+    /// `TOPPY_GW_UDP_FLOW_IDLE_SECS`.
+    pub udp_flow_idle_secs: Option<u64>,
+    /// `TOPPY_GW_UDP_RELAY`.
+    pub udp_relay: Option<bool>,
+    /// TLS 1.3 cipher suites in preference order (`TOPPY_GW_TLS_CIPHERS`).
+    #[serde(default)]
+    pub tls_ciphers: Vec<String>,
+    /// `TOPPY_GW_ENABLE_0RTT`.
+    pub enable_0rtt: Option<bool>,
+    /// Audit log for rejected auth attempts (`TOPPY_GW_AUDIT_PATH`).
+    pub audit_path: Option<String>,
+    /// Cap on the audit log plus its rotated segments
+    /// (`TOPPY_GW_AUDIT_MAX_TOTAL_BYTES`).
+    pub audit_max_total_bytes: Option<u64>,
+}
+
+impl GatewayConfig {
+    /// Environment for the `toppy-gw` process; unset fields are omitted.
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        let values = [
+            ("TOPPY_GW_LISTEN", &self.listen),
+            ("TOPPY_GW_QUIC_LISTEN", &self.quic_listen),
+            ("TOPPY_GW_PING_LISTEN", &self.ping_listen),
+            ("TOPPY_GW_CERT", &self.cert),
+            ("TOPPY_GW_KEY", &self.key),
+            ("TOPPY_GW_TOKEN", &self.token),
+            ("TOPPY_GW_JWT_SECRET", &self.jwt_secret),
+            ("TOPPY_GW_JWT_ISS", &self.jwt_iss),
+            ("TOPPY_GW_JWT_AUD", &self.jwt_aud),
+            ("TOPPY_GW_ADMIN_TOKEN", &self.admin_token),
+            ("TOPPY_GW_POLICY", &self.policy),
+            ("TOPPY_GW_AUDIT_PATH", &self.audit_path),
+        ];
+        for (key, value) in values {
+            if let Some(value) = value {
+                vars.push((key, value.clone()));
+            }
+        }
+        if !self.allowed_sni.is_empty() {
+            vars.push(("TOPPY_GW_ALLOWED_SNI", self.allowed_sni.join(",")));
+        }
+        if !self.source_allow.is_empty() {
+            vars.push(("TOPPY_GW_SOURCE_ALLOW", self.source_allow.join(",")));
+        }
+        if !self.tls_ciphers.is_empty() {
+            vars.push(("TOPPY_GW_TLS_CIPHERS", self.tls_ciphers.join(",")));
+        }
+        let flags = [
+            ("TOPPY_GW_JWT_REQUIRE_EXP", self.jwt_require_exp),
+            ("TOPPY_GW_JWT_REAUTH", self.jwt_reauth),
+            ("TOPPY_GW_REQUIRE_AUTH", self.require_auth),
+            ("TOPPY_GW_UDP_RELAY", self.udp_relay),
+            ("TOPPY_GW_ENABLE_0RTT", self.enable_0rtt),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                vars.push((key, if value { "1" } else { "0" }.to_string()));
+            }
+        }
+        let numbers = [
+            ("TOPPY_GW_JWT_MAX_AGE_SECS", self.jwt_max_age_secs),
+            ("TOPPY_GW_UDP_FLOW_IDLE_SECS", self.udp_flow_idle_secs),
+            ("TOPPY_GW_AUDIT_MAX_TOTAL_BYTES", self.audit_max_total_bytes),
+        ];
+        if let Some(size) = self.max_datagram_size {
+            vars.push(("TOPPY_GW_MAX_DATAGRAM_SIZE", size.to_string()));
+        }
+        for (key, value) in numbers {
+            if let Some(value) = value {
+                vars.push((key, value.to_string()));
+            }
+        }
+        vars
+    }
 }
 
 impl Config {
+    /// Returns `self` with every field set in `overlay` replacing the base value.
+    pub fn merge(self, overlay: Config) -> Config {
+        // Inline and file policies replace each other as a pair.
+        let (policy, policy_file) = if overlay.policy.is_some() || overlay.policy_file.is_some() {
+            (overlay.policy, overlay.policy_file)
+        } else {
+            (self.policy, self.policy_file)
+        };
+        Config {
+            gateway: overlay.gateway.or(self.gateway),
+            port: overlay.port.or(self.port),
+            fallback_gateway: overlay.fallback_gateway.or(self.fallback_gateway),
+            fallback_port: overlay.fallback_port.or(self.fallback_port),
+            ca_cert_path: overlay.ca_cert_path.or(self.ca_cert_path),
+            // Plain bool: a profile cannot undo a top-level `insecure_skip_verify = true`.
+            insecure_skip_verify: overlay.insecure_skip_verify || self.insecure_skip_verify,
+            server_name: overlay.server_name.or(self.server_name),
+            auth_token: overlay.auth_token.or(self.auth_token),
+            expected_audience: overlay.expected_audience.or(self.expected_audience),
+            mtu: overlay.mtu.or(self.mtu),
+            max_datagram_size: overlay.max_datagram_size.or(self.max_datagram_size),
+            policy,
+            policy_file,
+            proxy_max_workers: overlay.proxy_max_workers.or(self.proxy_max_workers),
+            doctor: overlay.doctor.or(self.doctor),
+            audit_path: overlay.audit_path.or(self.audit_path),
+            gw: overlay.gw.or(self.gw),
+            log_level: overlay.log_level.or(self.log_level),
+            log_format: overlay.log_format.or(self.log_format),
+            profiles: self.profiles,
+        }
+    }
+
+    /// Gateways in the order to try them: `gateway:port` (default
+    /// `127.0.0.1:4433`), then the fallback if one is configured.
+    pub fn gateway_endpoints(&self) -> Vec<(String, u16)> {
+        let port = self.port.unwrap_or(DEFAULT_PORT);
+        let mut endpoints = vec![(
+            self.gateway
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+            port,
+        )];
+        if let Some(fallback) = &self.fallback_gateway {
+            endpoints.push((fallback.clone(), self.fallback_port.unwrap_or(port)));
+        }
+        endpoints
+    }
+
+    /// `max_datagram_size`, or the payload that fits in `mtu` (default [`DEFAULT_MTU`]).
+    pub fn effective_max_datagram_size(&self) -> usize {
+        self.max_datagram_size
+            .unwrap_or_else(|| max_udp_payload(self.mtu.unwrap_or(DEFAULT_MTU)))
+    }
+
+    /// Merges the named profile over the base config.
+    pub fn with_profile(mut self, name: &str) -> Result<Config, String> {
+        let profile = self.profiles.remove(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            format!("unknown profile {} (available: {})", name, known.join(", "))
+        })?;
+        Ok(self.merge(profile))
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if let Some(gateway) = &self.gateway {
             if gateway.trim().is_empty() {
@@ -27,10 +264,25 @@ impl Config {
                 return Err("port must be non-zero".to_string());
             }
         }
+        if let Some(fallback_gateway) = &self.fallback_gateway {
+            if fallback_gateway.trim().is_empty() {
+                return Err("fallback_gateway must not be empty".to_string());
+            }
+        }
+        match self.fallback_port {
+            Some(0) => return Err("fallback_port must be non-zero".to_string()),
+            Some(_) if self.fallback_gateway.is_none() => {
+                return Err("fallback_port requires fallback_gateway".to_string());
+            }
+            _ => {}
+        }
         if let Some(ca_cert_path) = &self.ca_cert_path {
             if ca_cert_path.trim().is_empty() {
                 return Err("ca_cert_path must not be empty".to_string());
             }
+            if !Path::new(ca_cert_path).is_file() {
+                return Err(format!("ca_cert_path {} does not exist", ca_cert_path));
+            }
         }
         if let Some(server_name) = &self.server_name {
             if server_name.trim().is_empty() {
@@ -42,45 +294,211 @@ impl Config {
                 return Err("auth_token must not be empty".to_string());
             }
         }
+        if let Some(expected_audience) = &self.expected_audience {
+            if expected_audience.trim().is_empty() {
+                return Err("expected_audience must not be empty".to_string());
+            }
+        }
         if let Some(mtu) = self.mtu {
-            if mtu == 0 {
-                return Err("mtu must be non-zero".to_string());
+            if mtu < MIN_MTU {
+                return Err(format!("mtu {} is below the QUIC minimum {}", mtu, MIN_MTU));
             }
+            if mtu > MAX_MTU {
+                return Err(format!("mtu {} is above the maximum {}", mtu, MAX_MTU));
+            }
+        }
+        if self.max_datagram_size == Some(0) {
+            return Err("max_datagram_size must be non-zero".to_string());
         }
         if let Some(policy) = &self.policy {
             Policy::from_config(policy)?;
         }
+        match &self.policy_file {
+            Some(_) if self.policy.is_some() => {
+                return Err("set either [policy] or policy_file, not both".to_string());
+            }
+            Some(path) if path.trim().is_empty() => {
+                return Err("policy_file must not be empty".to_string());
+            }
+            _ => {}
+        }
+        if let Some(proxy_max_workers) = self.proxy_max_workers {
+            if proxy_max_workers == 0 {
+                return Err("proxy_max_workers must be non-zero".to_string());
+            }
+        }
+        if let Some(audit_path) = &self.audit_path {
+            if audit_path.trim().is_empty() {
+                return Err("audit_path must not be empty".to_string());
+            }
+        }
+        if let Some(threshold) = self.doctor.as_ref().and_then(|d| d.overall_threshold) {
+            if threshold > 100 {
+                return Err(format!(
+                    "doctor.overall_threshold {} exceeds 100",
+                    threshold
+                ));
+            }
+        }
         Ok(())
     }
 }
 
+/// `$XDG_CONFIG_HOME/toppy/config.toml`, falling back to
+/// `$HOME/.config/toppy/config.toml` when `XDG_CONFIG_HOME` is unset (or, per
+/// the XDG spec, empty or relative), and to a relative `.config/toppy/config.toml`
+/// without `HOME`.
 pub fn default_config_path() -> PathBuf {
-    // Minimal: ~/.config/toppy/config.toml
-    // (XDG support can be added later)
-    if let Some(home) = env::var_os("HOME") {
-        PathBuf::from(home)
-            .join(".config")
-            .join("toppy")
-            .join("config.toml")
-    } else {
-        PathBuf::from(".config/toppy/config.toml")
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from(".config"));
+    config_home.join("toppy").join("config.toml")
+}
+
+/// Default cap for certificate/key files read with [`read_bounded`].
+pub const DEFAULT_MAX_CERT_BYTES: u64 = 1024 * 1024;
+
+/// Size cap for certificate/key files, overridable via `TOPPY_MAX_CERT_BYTES`.
+pub fn max_cert_bytes() -> Result<u64, String> {
+    match env::var("TOPPY_MAX_CERT_BYTES") {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|limit| *limit > 0)
+            .ok_or_else(|| format!("invalid TOPPY_MAX_CERT_BYTES {}", value)),
+        Err(_) => Ok(DEFAULT_MAX_CERT_BYTES),
     }
 }
 
-pub fn load_config() -> Result<(Config, PathBuf), String> {
-    let path = env::var("TOPPY_CONFIG")
+/// Reads all of `path`, failing before allocating if it exceeds `limit` bytes.
+pub fn read_bounded(path: &Path, limit: u64) -> Result<Vec<u8>, String> {
+    let file =
+        fs::File::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let too_large = || {
+        format!(
+            "{} exceeds the {} byte limit (TOPPY_MAX_CERT_BYTES)",
+            path.display(),
+            limit
+        )
+    };
+    if file.metadata().is_ok_and(|m| m.len() > limit) {
+        return Err(too_large());
+    }
+    // Metadata can lie for pipes and special files, so bound the read too.
+    let mut data = Vec::new();
+    file.take(limit + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    if data.len() as u64 > limit {
+        return Err(too_large());
+    }
+    Ok(data)
+}
+
+/// The policy in effect: the inline `[policy]`, or `policy_file` parsed
+/// with [`crate::policy::load_policy_config`]. `None` when neither is set.
+pub fn load_policy(cfg: &Config) -> Result<Option<PolicyConfig>, String> {
+    match (&cfg.policy, &cfg.policy_file) {
+        (Some(_), Some(_)) => Err("set either [policy] or policy_file, not both".to_string()),
+        (Some(policy), None) => Ok(Some(policy.clone())),
+        (None, Some(path)) => crate::policy::load_policy_config(Path::new(path)).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Config file location: `TOPPY_CONFIG` if set, else [`default_config_path`].
+pub fn config_path() -> PathBuf {
+    env::var("TOPPY_CONFIG")
         .map(PathBuf::from)
-        .unwrap_or_else(|_| default_config_path());
+        .unwrap_or_else(|_| default_config_path())
+}
+
+pub fn load_config() -> Result<(Config, PathBuf), String> {
+    let path = config_path();
+    let cfg = load_config_from(&path)?;
+    Ok((cfg, path))
+}
 
-    let data = fs::read_to_string(&path)
+/// Reads `path` and applies the `TOPPY_PROFILE` profile, if any.
+fn load_config_from(path: &Path) -> Result<Config, String> {
+    let data = fs::read_to_string(path)
         .map_err(|e| format!("failed to read config {}: {}", path.display(), e))?;
     let cfg: Config = toml::from_str(&data).map_err(|e| format!("failed to parse TOML: {}", e))?;
-    Ok((cfg, path))
+    let mut cfg = match env::var("TOPPY_PROFILE") {
+        Ok(name) => cfg.with_profile(&name)?,
+        Err(_) => cfg,
+    };
+    if let (Some(file), Some(dir)) = (&cfg.policy_file, path.parent()) {
+        cfg.policy_file = Some(dir.join(file).to_string_lossy().into_owned());
+    }
+    Ok(cfg)
+}
+
+/// Picks up edits to a config file by polling its modification time.
+///
+/// Each [`ConfigWatcher::latest`] call stats the file and reloads it when it
+/// changed. A reload that fails to read, parse or validate is logged and the
+/// last good config is kept.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    /// Modification time and length of the file `latest` was loaded from.
+    stamp: Option<(SystemTime, u64)>,
+    latest: Config,
+}
+
+impl ConfigWatcher {
+    /// Loads `path`; unlike later reloads, a bad initial config is an error.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let stamp = file_stamp(&path);
+        let latest = load_config_from(&path)?;
+        latest
+            .validate()
+            .map_err(|e| format!("config validation failed: {}", e))?;
+        Ok(Self {
+            path,
+            stamp,
+            latest,
+        })
+    }
+
+    /// The most recent config that loaded and validated.
+    pub fn latest(&mut self) -> &Config {
+        let stamp = file_stamp(&self.path);
+        if stamp.is_some() && stamp != self.stamp {
+            // Remember the stamp either way so a bad edit is reported once.
+            self.stamp = stamp;
+            match load_config_from(&self.path).and_then(|cfg| {
+                cfg.validate()
+                    .map_err(|e| format!("config validation failed: {}", e))?;
+                Ok(cfg)
+            }) {
+                Ok(cfg) => self.latest = cfg,
+                Err(e) => log(
+                    LogLevel::Warn,
+                    &format!(
+                        "keeping previous config; reload of {} failed: {}",
+                        self.path.display(),
+                        e
+                    ),
+                ),
+            }
+        }
+        &self.latest
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::policy::PolicyRuleConfig;
     use std::time::{SystemTime, UNIX_EPOCH};
 
     fn unique_temp_path(prefix: &str) -> PathBuf {
@@ -96,11 +514,24 @@ mod tests {
         let cfg = Config {
             gateway: Some("".to_string()),
             port: Some(4433),
+            fallback_gateway: None,
+            fallback_port: None,
             ca_cert_path: None,
+            insecure_skip_verify: false,
             server_name: None,
             auth_token: None,
+            expected_audience: None,
             mtu: None,
+            max_datagram_size: None,
             policy: None,
+            policy_file: None,
+            proxy_max_workers: None,
+            doctor: None,
+            audit_path: None,
+            gw: None,
+            log_level: None,
+            log_format: None,
+            profiles: BTreeMap::new(),
         };
         assert!(cfg.validate().is_err());
     }
@@ -110,37 +541,447 @@ mod tests {
         let cfg = Config {
             gateway: Some("127.0.0.1".to_string()),
             port: Some(0),
+            fallback_gateway: None,
+            fallback_port: None,
             ca_cert_path: None,
+            insecure_skip_verify: false,
             server_name: None,
             auth_token: None,
+            expected_audience: None,
             mtu: None,
+            max_datagram_size: None,
             policy: None,
+            policy_file: None,
+            proxy_max_workers: None,
+            doctor: None,
+            audit_path: None,
+            gw: None,
+            log_level: None,
+            log_format: None,
+            profiles: BTreeMap::new(),
         };
         assert!(cfg.validate().is_err());
     }
 
+    #[test]
+    fn validate_rejects_zero_proxy_max_workers() {
+        let cfg = Config {
+            proxy_max_workers: Some(0),
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn effective_max_datagram_size_derives_from_mtu() {
+        assert_eq!(Config::default().effective_max_datagram_size(), 1255);
+        let cfg = Config {
+            mtu: Some(1500),
+            ..Default::default()
+        };
+        assert_eq!(cfg.effective_max_datagram_size(), 1405);
+        let cfg = Config {
+            mtu: Some(1500),
+            max_datagram_size: Some(1200),
+            ..Default::default()
+        };
+        assert_eq!(cfg.effective_max_datagram_size(), 1200);
+        let cfg = Config {
+            max_datagram_size: Some(0),
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn gateway_endpoints_append_fallback() {
+        assert_eq!(
+            Config::default().gateway_endpoints(),
+            [("127.0.0.1".to_string(), 4433)]
+        );
+        let cfg = Config {
+            gateway: Some("gw-a.example".to_string()),
+            port: Some(8443),
+            fallback_gateway: Some("gw-b.example".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            cfg.gateway_endpoints(),
+            [
+                ("gw-a.example".to_string(), 8443),
+                ("gw-b.example".to_string(), 8443)
+            ]
+        );
+        let cfg = Config {
+            fallback_port: Some(9443),
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn read_bounded_rejects_oversized_file() {
+        let path = unique_temp_path("bounded-big");
+        fs::write(&path, vec![b'a'; 2048]).expect("write file");
+        let err = read_bounded(&path, 1024).unwrap_err();
+        assert!(err.contains("exceeds the 1024 byte limit"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn read_bounded_reads_file_within_limit() {
+        let path = unique_temp_path("bounded-ok");
+        fs::write(&path, b"-----BEGIN CERTIFICATE-----\n").expect("write file");
+        let data = read_bounded(&path, DEFAULT_MAX_CERT_BYTES).expect("read");
+        assert_eq!(data, b"-----BEGIN CERTIFICATE-----\n");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn default_config_path_prefers_xdg_config_home() {
+        let _env = crate::test_support::scoped_env(&[
+            ("XDG_CONFIG_HOME", Some("/xdg/config")),
+            ("HOME", Some("/home/alice")),
+        ]);
+        assert_eq!(
+            default_config_path(),
+            PathBuf::from("/xdg/config/toppy/config.toml")
+        );
+        drop(_env);
+
+        for xdg in [None, Some(""), Some("relative/config")] {
+            let _env = crate::test_support::scoped_env(&[
+                ("XDG_CONFIG_HOME", xdg),
+                ("HOME", Some("/home/alice")),
+            ]);
+            assert_eq!(
+                default_config_path(),
+                PathBuf::from("/home/alice/.config/toppy/config.toml"),
+                "{:?}",
+                xdg
+            );
+        }
+
+        let _env = crate::test_support::scoped_env(&[("XDG_CONFIG_HOME", None), ("HOME", None)]);
+        assert_eq!(
+            default_config_path(),
+            PathBuf::from(".config/toppy/config.toml")
+        );
+    }
+
     #[test]
     fn load_config_reads_toml() {
-        let _guard = crate::test_support::ENV_LOCK
-            .lock()
-            .unwrap_or_else(|e| e.into_inner());
         let path = unique_temp_path("config-load");
         let data = "gateway = \"127.0.0.1\"\nport = 4433\n";
         fs::write(&path, data).expect("write config");
 
-        let prev = env::var("TOPPY_CONFIG").ok();
-        env::set_var("TOPPY_CONFIG", &path);
+        let _env = crate::test_support::scoped_env(&[("TOPPY_CONFIG", path.to_str())]);
 
         let (cfg, loaded_path) = load_config().expect("load config");
         assert_eq!(loaded_path, path);
         assert_eq!(cfg.gateway.as_deref(), Some("127.0.0.1"));
         assert_eq!(cfg.port, Some(4433));
 
-        if let Some(value) = prev {
-            env::set_var("TOPPY_CONFIG", value);
-        } else {
-            env::remove_var("TOPPY_CONFIG");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_config_reads_every_field() {
+        let ca_path = unique_temp_path("full-ca");
+        fs::write(&ca_path, "-----BEGIN CERTIFICATE-----\n").expect("write ca");
+        let path = unique_temp_path("config-full");
+        let data = format!(
+            r#"
+gateway = "gw-a.example"
+port = 8443
+fallback_gateway = "gw-b.example"
+fallback_port = 9443
+ca_cert_path = "{ca}"
+insecure_skip_verify = true
+server_name = "gateway"
+auth_token = "dev-token"
+expected_audience = "toppy"
+mtu = 1400
+max_datagram_size = 1200
+proxy_max_workers = 4
+audit_path = "/var/log/toppy/audit.jsonl"
+log_level = "debug"
+log_format = "json"
+
+[policy]
+allow_diagnostics = true
+[[policy.allow]]
+cidr = "10.0.0.0/8"
+ports = [22]
+
+[doctor]
+targets = ["10.0.0.5:22"]
+overall_threshold = 80
+
+[gw]
+quic_listen = "0.0.0.0:4433"
+audit_max_total_bytes = 1048576
+
+[profiles.lab]
+port = 5443
+"#,
+            ca = ca_path.display()
+        );
+        fs::write(&path, data).expect("write config");
+        let _env = crate::test_support::scoped_env(&[("TOPPY_PROFILE", None)]);
+
+        let cfg = load_config_from(&path).expect("load config");
+        cfg.validate().expect("valid");
+        let expected = Config {
+            gateway: Some("gw-a.example".to_string()),
+            port: Some(8443),
+            fallback_gateway: Some("gw-b.example".to_string()),
+            fallback_port: Some(9443),
+            ca_cert_path: Some(ca_path.display().to_string()),
+            insecure_skip_verify: true,
+            server_name: Some("gateway".to_string()),
+            auth_token: Some("dev-token".to_string()),
+            expected_audience: Some("toppy".to_string()),
+            mtu: Some(1400),
+            max_datagram_size: Some(1200),
+            policy: Some(PolicyConfig {
+                allow_diagnostics: true,
+                allow: vec![PolicyRuleConfig {
+                    cidr: "10.0.0.0/8".to_string(),
+                    ports: vec![22],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            policy_file: None,
+            proxy_max_workers: Some(4),
+            doctor: Some(DoctorConfig {
+                targets: vec!["10.0.0.5:22".to_string()],
+                overall_threshold: Some(80),
+            }),
+            audit_path: Some("/var/log/toppy/audit.jsonl".to_string()),
+            gw: Some(GatewayConfig {
+                quic_listen: Some("0.0.0.0:4433".to_string()),
+                audit_max_total_bytes: Some(1_048_576),
+                ..Default::default()
+            }),
+            log_level: Some(LogLevel::Debug),
+            log_format: Some(LogFormat::Json),
+            profiles: BTreeMap::from([(
+                "lab".to_string(),
+                Config {
+                    port: Some(5443),
+                    ..Default::default()
+                },
+            )]),
+        };
+        assert_eq!(cfg, expected);
+
+        let missing_ca = Config {
+            ca_cert_path: Some(format!("{}.missing", ca_path.display())),
+            ..cfg.clone()
+        };
+        assert!(missing_ca
+            .validate()
+            .unwrap_err()
+            .contains("does not exist"));
+        let tiny_mtu = Config {
+            mtu: Some(576),
+            ..cfg.clone()
+        };
+        assert!(tiny_mtu.validate().unwrap_err().contains("QUIC minimum"));
+        let huge_mtu = Config {
+            mtu: Some(MAX_MTU + 1),
+            ..cfg
+        };
+        assert!(huge_mtu
+            .validate()
+            .unwrap_err()
+            .contains("above the maximum"));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&ca_path);
+    }
+
+    #[test]
+    fn load_config_rejects_unknown_keys() {
+        let path = unique_temp_path("config-unknown-key");
+        let data = r#"gatway = "gw.example"
+port = 4433
+"#;
+        fs::write(&path, data).expect("write config");
+        let _env = crate::test_support::scoped_env(&[("TOPPY_CONFIG", path.to_str())]);
+
+        let err = load_config().unwrap_err();
+        assert!(err.contains("unknown field `gatway`"), "{}", err);
+
+        let data = r#"[profiles.dev]
+mut = 1200
+"#;
+        let err = toml::from_str::<Config>(data).unwrap_err();
+        assert!(err.to_string().contains("unknown field `mut`"), "{}", err);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_config_rejects_unknown_gw_and_doctor_keys() {
+        let path = unique_temp_path("config-unknown-gw-key");
+        let _env = crate::test_support::scoped_env(&[("TOPPY_CONFIG", path.to_str())]);
+        for (data, key) in [
+            ("[gw]\nrequire_auht = true\n", "require_auht"),
+            ("[doctor]\ntarget = [\"10.0.0.5:22\"]\n", "target"),
+        ] {
+            fs::write(&path, data).expect("write config");
+            let err = load_config().unwrap_err();
+            assert!(err.contains(&format!("unknown field `{}`", key)), "{}", err);
         }
         let _ = fs::remove_file(&path);
     }
+
+    #[test]
+    fn gateway_env_vars_cover_auth_and_flow_keys() {
+        let gw: GatewayConfig = toml::from_str(
+            r#"
+jwt_secret = "secret"
+jwt_iss = "toppy"
+jwt_aud = "toppy-gw"
+jwt_require_exp = false
+jwt_max_age_secs = 3600
+jwt_reauth = true
+require_auth = true
+udp_flow_idle_secs = 45
+udp_relay = true
+tls_ciphers = ["TLS13_AES_128_GCM_SHA256", "TLS13_CHACHA20_POLY1305_SHA256"]
+enable_0rtt = false
+"#,
+        )
+        .expect("parse");
+        assert_eq!(
+            gw.env_vars(),
+            vec![
+                ("TOPPY_GW_JWT_SECRET", "secret".to_string()),
+                ("TOPPY_GW_JWT_ISS", "toppy".to_string()),
+                ("TOPPY_GW_JWT_AUD", "toppy-gw".to_string()),
+                (
+                    "TOPPY_GW_TLS_CIPHERS",
+                    "TLS13_AES_128_GCM_SHA256,TLS13_CHACHA20_POLY1305_SHA256".to_string()
+                ),
+                ("TOPPY_GW_JWT_REQUIRE_EXP", "0".to_string()),
+                ("TOPPY_GW_JWT_REAUTH", "1".to_string()),
+                ("TOPPY_GW_REQUIRE_AUTH", "1".to_string()),
+                ("TOPPY_GW_UDP_RELAY", "1".to_string()),
+                ("TOPPY_GW_ENABLE_0RTT", "0".to_string()),
+                ("TOPPY_GW_JWT_MAX_AGE_SECS", "3600".to_string()),
+                ("TOPPY_GW_UDP_FLOW_IDLE_SECS", "45".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn with_profile_overrides_selected_fields() {
+        let cfg: Config = toml::from_str(
+            r#"
+            gateway = "127.0.0.1"
+            port = 4433
+            mtu = 1350
+
+            [profiles.staging]
+            gateway = "staging.example"
+            "#,
+        )
+        .expect("parse");
+        let cfg = cfg.with_profile("staging").expect("profile");
+        assert_eq!(cfg.gateway.as_deref(), Some("staging.example"));
+        assert_eq!(cfg.port, Some(4433));
+        assert_eq!(cfg.mtu, Some(1350));
+    }
+
+    #[test]
+    fn with_profile_rejects_unknown_name() {
+        let cfg: Config = toml::from_str("[profiles.dev]\nport = 1\n").expect("parse");
+        let err = cfg.with_profile("prod").unwrap_err();
+        assert!(err.contains("unknown profile prod"));
+        assert!(err.contains("dev"));
+    }
+
+    #[test]
+    fn load_config_applies_toppy_profile() {
+        let path = unique_temp_path("config-profile");
+        let data =
+            "gateway = \"127.0.0.1\"\nport = 4433\n\n[profiles.prod]\ngateway = \"gw.example\"\n";
+        fs::write(&path, data).expect("write config");
+        let _env = crate::test_support::scoped_env(&[
+            ("TOPPY_CONFIG", path.to_str()),
+            ("TOPPY_PROFILE", Some("prod")),
+        ]);
+
+        let (cfg, _) = load_config().expect("load config");
+        assert_eq!(cfg.gateway.as_deref(), Some("gw.example"));
+        assert_eq!(cfg.port, Some(4433));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn config_watcher_reloads_edits_and_keeps_last_good() {
+        let path = unique_temp_path("config-watch");
+        let _env = crate::test_support::scoped_env(&[("TOPPY_PROFILE", None)]);
+        // Explicit mtimes so the test does not depend on timestamp granularity.
+        let write = |data: &str, secs: u64| {
+            fs::write(&path, data).expect("write config");
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|f| f.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(secs)))
+                .expect("set mtime");
+        };
+
+        write("gateway = \"127.0.0.1\"\nport = 4433\n", 1_000);
+        let mut watcher = ConfigWatcher::new(&path).expect("watcher");
+        assert_eq!(watcher.latest().port, Some(4433));
+
+        write("gateway = \"127.0.0.1\"\nport = 5544\n", 2_000);
+        assert_eq!(watcher.latest().port, Some(5544));
+
+        write("gateway = \"127.0.0.1\"\nport = \"oops\n", 3_000);
+        assert_eq!(watcher.latest().port, Some(5544));
+        write("gateway = \"127.0.0.1\"\nport = 0\n", 4_000);
+        assert_eq!(watcher.latest().port, Some(5544));
+
+        write("gateway = \"127.0.0.1\"\nport = 6655\n", 5_000);
+        assert_eq!(watcher.latest().port, Some(6655));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn policy_file_resolves_against_config_dir_and_round_trips() {
+        let dir = unique_temp_path("policy-file-dir");
+        fs::create_dir_all(dir.join("shared")).expect("create dir");
+        let policy_cfg: PolicyConfig = toml::from_str(
+            "[[allow]]\ncidr = \"10.0.0.0/8\"\nports = [22]\n\n[[deny]]\ncidr = \"10.9.0.0/16\"\nports = [22]\n",
+        )
+        .expect("policy toml");
+        let policy = Policy::from_config(&policy_cfg).expect("policy");
+        fs::write(
+            dir.join("shared/policy.toml"),
+            toml::to_string(&policy.to_config()).expect("serialize"),
+        )
+        .expect("write policy");
+        let path = dir.join("config.toml");
+        fs::write(&path, "policy_file = \"shared/policy.toml\"\n").expect("write config");
+        let _env = crate::test_support::scoped_env(&[("TOPPY_PROFILE", None)]);
+
+        let cfg = load_config_from(&path).expect("load config");
+        assert!(Path::new(cfg.policy_file.as_deref().unwrap()).starts_with(&dir));
+        let loaded = load_policy(&cfg).expect("load policy").expect("policy set");
+        assert_eq!(Policy::from_config(&loaded).expect("policy"), policy);
+
+        let both = Config {
+            policy: Some(policy_cfg),
+            ..cfg
+        };
+        let err = both.validate().unwrap_err();
+        assert!(err.contains("not both"), "{}", err);
+        assert!(load_policy(&both).is_err());
+        assert_eq!(load_policy(&Config::default()), Ok(None));
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -6,47 +6,240 @@
 //! the result. The overall status is aggregated across all checks.
 
 use crate::config;
-use crate::policy::{Decision, Policy, Target};
-use bytes::{Buf, Bytes};
-use h3::ext::Protocol;
-use h3_datagram::datagram_handler::HandleDatagramsExt;
-use quinn::crypto::rustls::QuicClientConfig;
-use quinn::{ClientConfig, Endpoint};
-use rustls::pki_types::pem::PemObject;
-use rustls::pki_types::CertificateDer;
-use rustls::RootCertStore;
+use crate::net::{resolve_allowed_rules, split_host_port};
+use crate::policy::{Policy, PolicyConfig};
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::fs::OpenOptions;
-use std::net::SocketAddr;
+use std::io::Write;
 use std::net::ToSocketAddrs;
 use std::path::Path;
-use std::sync::Arc;
-use std::time::Duration;
+use toppy_proto::masque::max_udp_payload;
+
+#[cfg(feature = "ring")]
+mod probes;
+
+/// Version of the doctor JSON layout; bump whenever fields are added,
+/// removed or change meaning. Independent of the crate version.
+pub const DOCTOR_SCHEMA_VERSION: u32 = 4;
 
 #[derive(Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct DoctorReport {
+    /// Always [`DOCTOR_SCHEMA_VERSION`].
+    pub schema_version: u32,
     pub version: String,
     pub overall: String,
+    /// How `overall` was computed: `max_severity`, or `weighted(<threshold>)`
+    /// when `doctor.overall_threshold` is configured.
+    pub overall_mode: String,
     pub checks: Vec<DoctorCheck>,
+    /// [`DoctorReport::status_counts`] of `checks` when the report was built.
+    pub counts: StatusCounts,
+}
+
+/// Number of checks with each status.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub pass: usize,
+    pub warn: usize,
+    pub fail: usize,
 }
 
 #[derive(Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct DoctorCheck {
     pub id: String,
+    /// One of `config`, `network`, `security`, `system`, derived from the id.
+    pub category: String,
     pub status: String,
     pub summary: String,
+    /// Share in [`DoctorReport::weighted_overall`]; `0` marks an advisory check.
+    pub weight: u32,
+    /// What to try next, for common failures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl DoctorReport {
+    /// Counts of `pass`, `warn` and `fail` checks, in that order.
+    pub fn status_counts(&self) -> (usize, usize, usize) {
+        let count = |status: &str| self.checks.iter().filter(|c| c.status == status).count();
+        (count("pass"), count("warn"), count("fail"))
+    }
+
+    /// Checks grouped by [`DoctorCheck::category`], keeping report order within each group.
+    pub fn by_category(&self) -> BTreeMap<&str, Vec<&DoctorCheck>> {
+        let mut groups: BTreeMap<&str, Vec<&DoctorCheck>> = BTreeMap::new();
+        for check in &self.checks {
+            groups
+                .entry(check.category.as_str())
+                .or_default()
+                .push(check);
+        }
+        groups
+    }
+
+    /// Overall status from the passing share of critical (non-zero weight)
+    /// checks: `pass` once it reaches `threshold` (0.0-1.0), otherwise `fail`
+    /// if a critical check failed and `warn` if not. Advisory checks are ignored.
+    pub fn weighted_overall(&self, threshold: f64) -> String {
+        let critical = || self.checks.iter().filter(|c| c.weight > 0);
+        let total: u32 = critical().map(|c| c.weight).sum();
+        let passed: u32 = critical()
+            .filter(|c| c.status == "pass")
+            .map(|c| c.weight)
+            .sum();
+        if total == 0 || f64::from(passed) / f64::from(total) >= threshold {
+            "pass".to_string()
+        } else if critical().any(|c| c.status == "fail") {
+            "fail".to_string()
+        } else {
+            "warn".to_string()
+        }
+    }
+
+    /// Switches `overall` to [`Self::weighted_overall`]. With `insecure_skip_verify`
+    /// it stays at `warn` or worse, since the unverified checks may be advisory
+    /// or fall under the threshold.
+    fn apply_threshold(&mut self, threshold: f64, insecure: bool) {
+        self.overall = self.weighted_overall(threshold);
+        if insecure && self.overall == "pass" {
+            self.overall = "warn".to_string();
+        }
+        self.overall_mode = format!("weighted({:.2})", threshold);
+    }
+}
+
+/// Maps a check id prefix to its category; unknown prefixes are `other`.
+fn check_category(id: &str) -> &'static str {
+    match id.split('.').next().unwrap_or_default() {
+        "cfg" => "config",
+        "net" | "h3" | "tls" | "masque" => "network",
+        "policy" | "audit" => "security",
+        "tun" | "mtu" | "sys" => "system",
+        _ => "other",
+    }
+}
+
+/// Weight of a check in [`DoctorReport::weighted_overall`]: checks the tunnel
+/// cannot work without count double, host hygiene and lint checks are advisory.
+fn check_weight(id: &str) -> u32 {
+    match id {
+        "cfg.load" | "net.dns" | "h3.connect" | "tun.perm" => 2,
+        "policy.lint" | "tls.0rtt" => 0,
+        _ if id.starts_with("sys.") => 0,
+        _ => 1,
+    }
+}
+
+/// Remediation shown with a non-passing check, if there is a usual fix.
+fn check_hint(id: &str, status: &str) -> Option<String> {
+    if status == "pass" {
+        return None;
+    }
+    let hint = match (id, status) {
+        ("cfg.load", "fail") => {
+            return Some(format!(
+                "create {} or set TOPPY_CONFIG (`toppy doctor --fix` writes an example)",
+                config::config_path().display()
+            ));
+        }
+        ("net.dns", "fail") => "check the gateway host name and the system DNS resolver",
+        ("h3.connect", "fail") => {
+            "check the gateway is running and its UDP port is reachable, then ca_cert_path and auth_token"
+        }
+        ("tun.perm", "fail") => "run with CAP_NET_ADMIN or as root",
+        ("sys.fd_limit", "warn") => "raise the open file limit (e.g. `ulimit -n 4096`)",
+        ("sys.runtime", "warn") => "free system resources (threads, file descriptors) and rerun",
+        ("audit.writable", "fail") => "create audit_path's directory or fix its permissions",
+        ("tls.sni_match", "warn") => {
+            "set server_name to one of the certificate's SANs, or reissue the gateway certificate"
+        }
+        _ => return None,
+    };
+    Some(hint.to_string())
+}
+
+/// A change made by [`doctor_fix`].
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DoctorFix {
+    pub id: String,
+    pub action: String,
+}
+
+/// Written by `doctor --fix` when no config exists. Deliberately omits
+/// credentials and CA paths, which must be set by hand.
+pub const EXAMPLE_CONFIG: &str = "\
+# Created by `toppy doctor --fix`; edit to match your gateway.
+gateway = \"127.0.0.1\"
+port = 4433
+mtu = 1350
+";
+
+/// Applies safe local remediations for whitelisted checks.
+///
+/// Only `cfg.load` is handled: a missing config file (and its directory) is
+/// created from [`EXAMPLE_CONFIG`]. Existing files are never modified, and
+/// nothing network- or security-related is touched.
+pub fn doctor_fix() -> Result<Vec<DoctorFix>, String> {
+    let mut fixes = Vec::new();
+    let path = config::config_path();
+    if !path.exists() {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            if !dir.exists() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+                fixes.push(DoctorFix {
+                    id: "cfg.load".to_string(),
+                    action: format!("created config directory {}", dir.display()),
+                });
+            }
+        }
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(EXAMPLE_CONFIG.as_bytes()))
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        fixes.push(DoctorFix {
+            id: "cfg.load".to_string(),
+            action: format!("wrote example config {}", path.display()),
+        });
+    }
+    Ok(fixes)
 }
 
 fn mk(id: &str, status: &str, summary: impl Into<String>) -> DoctorCheck {
     DoctorCheck {
         id: id.to_string(),
+        category: check_category(id).to_string(),
         status: status.to_string(),
         summary: summary.into(),
+        weight: check_weight(id),
+        hint: check_hint(id, status),
     }
 }
 
+/// Report order by category (the id prefix before the first `.`); ids within
+/// a category sort lexically and unknown categories go last.
+const CHECK_CATEGORY_ORDER: &[&str] = &[
+    "cfg", "net", "h3", "tls", "masque", "tun", "mtu", "sys", "policy", "audit",
+];
+
+fn sort_checks(checks: &mut [DoctorCheck]) {
+    checks.sort_by(|a, b| {
+        let rank = |check: &DoctorCheck| {
+            let category = check.id.split('.').next().unwrap_or_default();
+            CHECK_CATEGORY_ORDER
+                .iter()
+                .position(|c| *c == category)
+                .unwrap_or(CHECK_CATEGORY_ORDER.len())
+        };
+        rank(a).cmp(&rank(b)).then_with(|| a.id.cmp(&b.id))
+    });
+}
+
 fn aggregate_overall(checks: &[DoctorCheck]) -> String {
     // fail > warn > pass
     if checks.iter().any(|c| c.status == "fail") {
@@ -58,6 +251,16 @@ fn aggregate_overall(checks: &[DoctorCheck]) -> String {
     }
 }
 
+/// Network checks run by the QUIC/TLS probes (`ring` feature only).
+const RUNTIME_CHECK_IDS: &[&str] = &[
+    "h3.connect",
+    "tls.alpn",
+    "tls.0rtt",
+    "tls.sni_match",
+    "masque.connect_udp",
+    "masque.connect_udp.datagram",
+];
+
 fn dns_check(host: &str, port: u16) -> Result<usize, String> {
     let addr = format!("{}:{}", host, port);
     let addrs: Vec<_> = addr
@@ -146,9 +349,9 @@ fn macos_utun_check() -> Result<(), String> {
 }
 
 fn mtu_sanity_check(mtu: Option<u16>) -> DoctorCheck {
-    let recommended = 1350u16;
-    let min_reasonable = 1200u16;
-    let max_reasonable = 9000u16;
+    let recommended = config::DEFAULT_MTU;
+    let min_reasonable = config::MIN_MTU;
+    let max_reasonable = config::MAX_MTU;
     match mtu {
         Some(value) if value < min_reasonable => mk(
             "mtu.sanity",
@@ -179,378 +382,223 @@ fn mtu_sanity_check(mtu: Option<u16>) -> DoctorCheck {
     }
 }
 
-fn parse_policy_target(value: &str) -> Result<Target, String> {
-    let addr: SocketAddr = value
-        .parse()
-        .map_err(|e| format!("invalid target {}: {}", value, e))?;
-    Ok(Target {
-        ip: addr.ip(),
-        port: addr.port(),
-    })
+/// Reports the effective HTTP datagram payload limit derived from the config.
+fn datagram_size_check(cfg: &config::Config) -> DoctorCheck {
+    let mtu = cfg.mtu.unwrap_or(config::DEFAULT_MTU);
+    let fits = max_udp_payload(mtu);
+    let effective = cfg.effective_max_datagram_size();
+    if effective > fits {
+        return mk(
+            "mtu.datagram",
+            "warn",
+            format!(
+                "max_datagram_size {} exceeds the {} bytes that fit in mtu {}",
+                effective, fits, mtu
+            ),
+        );
+    }
+    mk(
+        "mtu.datagram",
+        "pass",
+        format!("max datagram payload {} bytes (mtu {})", effective, mtu),
+    )
 }
 
-fn load_ca_certs(path: &Path) -> Result<RootCertStore, String> {
-    let data = fs::read(path)
-        .map_err(|e| format!("failed to read ca_cert_path {}: {}", path.display(), e))?;
-    let certs = CertificateDer::pem_slice_iter(&data)
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| format!("failed to parse CA certs from {}: {}", path.display(), e))?;
-    if certs.is_empty() {
-        return Err(format!("no CA certificates found in {}", path.display()));
-    }
-    let mut store = RootCertStore::empty();
-    for cert in certs {
-        store
-            .add(cert)
-            .map_err(|e| format!("failed to add CA cert {}: {}", path.display(), e))?;
-    }
-    Ok(store)
+/// Below this many bits `/dev/random` consumers (cert generation, TLS) may stall.
+const ENTROPY_WARN_THRESHOLD: u32 = 256;
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn entropy_threshold_check(avail: u32) -> DoctorCheck {
+    if avail < ENTROPY_WARN_THRESHOLD {
+        mk(
+            "sys.entropy",
+            "warn",
+            format!(
+                "entropy_avail {} is low; recommended >= {}",
+                avail, ENTROPY_WARN_THRESHOLD
+            ),
+        )
+    } else {
+        mk("sys.entropy", "pass", format!("entropy_avail {}", avail))
+    }
 }
 
-fn quic_ping_check(
-    host: &str,
-    port: u16,
-    server_name: &str,
-    ca_cert_path: Option<&str>,
-    auth_token: Option<&str>,
-) -> Result<(), String> {
-    let addr = format!("{}:{}", host, port);
-    let addr = addr
-        .to_socket_addrs()
-        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
-        .next()
-        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
-
-    let ca_cert_path =
-        ca_cert_path.ok_or_else(|| "missing ca_cert_path for TLS verification".to_string())?;
-    let auth_token =
-        auth_token.ok_or_else(|| "missing auth_token for token verification".to_string())?;
-    let ca_store = load_ca_certs(Path::new(ca_cert_path))?;
-    let crypto = rustls::ClientConfig::builder()
-        .with_root_certificates(ca_store)
-        .with_no_client_auth();
-    let crypto = QuicClientConfig::try_from(crypto)
-        .map_err(|e| format!("quic client config failed: {}", e))?;
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .map_err(|e| format!("tokio init failed: {}", e))?;
-
-    let connect_timeout = Duration::from_millis(800);
-    let stream_timeout = Duration::from_millis(800);
-
-    rt.block_on(async move {
-        let mut client_config = ClientConfig::new(Arc::new(crypto));
-        client_config.transport_config(Arc::new(quinn::TransportConfig::default()));
-
-        let bind_addr = "0.0.0.0:0"
-            .parse::<std::net::SocketAddr>()
-            .map_err(|e| e.to_string())?;
-        let mut endpoint =
-            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
-        endpoint.set_default_client_config(client_config);
-
-        let connecting = endpoint
-            .connect(addr, server_name)
-            .map_err(|e| format!("quic connect setup failed: {}", e))?;
-        let connection = tokio::time::timeout(connect_timeout, connecting)
-            .await
-            .map_err(|_| "quic connect timed out".to_string())?
-            .map_err(|e| format!("quic connect failed: {}", e))?;
-
-        let (mut send, mut recv) = tokio::time::timeout(stream_timeout, connection.open_bi())
-            .await
-            .map_err(|_| "quic open stream timed out".to_string())?
-            .map_err(|e| format!("quic open stream failed: {}", e))?;
-
-        let payload = format!("ping {}", auth_token);
-        send.write_all(payload.as_bytes())
-            .await
-            .map_err(|e| format!("quic send failed: {}", e))?;
-        send.finish()
-            .map_err(|e| format!("quic finish failed: {}", e))?;
-
-        let data = tokio::time::timeout(stream_timeout, recv.read_to_end(16))
-            .await
-            .map_err(|_| "quic read timed out".to_string())?
-            .map_err(|e| format!("quic read failed: {}", e))?;
-
-        connection.close(0u32.into(), b"done");
-        endpoint.wait_idle().await;
-
-        if data == b"pong" {
-            Ok(())
-        } else if data == b"unauthorized" {
-            Err("token rejected by gateway".to_string())
-        } else {
-            Err(format!("unexpected response: {:?}", data))
+fn entropy_check() -> DoctorCheck {
+    #[cfg(target_os = "linux")]
+    {
+        let path = "/proc/sys/kernel/random/entropy_avail";
+        match fs::read_to_string(path) {
+            Ok(data) => match data.trim().parse::<u32>() {
+                Ok(avail) => entropy_threshold_check(avail),
+                Err(e) => mk(
+                    "sys.entropy",
+                    "warn",
+                    format!("cannot parse {}: {}", path, e),
+                ),
+            },
+            Err(e) => mk(
+                "sys.entropy",
+                "warn",
+                format!("cannot read {}: {}", path, e),
+            ),
         }
-    })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        mk(
+            "sys.entropy",
+            "warn",
+            "entropy check not supported on this OS",
+        )
+    }
 }
 
-fn connect_udp_handshake_check(
-    host: &str,
-    port: u16,
-    server_name: &str,
-    ca_cert_path: Option<&str>,
-    auth_token: Option<&str>,
-) -> Result<(), String> {
-    let addr = format!("{}:{}", host, port);
-    let addr = addr
-        .to_socket_addrs()
-        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
-        .next()
-        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
-
-    let ca_cert_path =
-        ca_cert_path.ok_or_else(|| "missing ca_cert_path for TLS verification".to_string())?;
-    let auth_token =
-        auth_token.ok_or_else(|| "missing auth_token for token verification".to_string())?;
-
-    let ca_store = load_ca_certs(Path::new(ca_cert_path))?;
-    let mut crypto = rustls::ClientConfig::builder()
-        .with_root_certificates(ca_store)
-        .with_no_client_auth();
-    crypto.alpn_protocols = vec![b"h3".to_vec()];
-    let crypto = QuicClientConfig::try_from(crypto)
-        .map_err(|e| format!("quic client config failed: {}", e))?;
-
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .map_err(|e| format!("tokio init failed: {}", e))?;
-
-    let connect_timeout = Duration::from_millis(1200);
-    let request_timeout = Duration::from_millis(1200);
-
-    rt.block_on(async move {
-        let mut client_config = ClientConfig::new(Arc::new(crypto));
-        client_config.transport_config(Arc::new(quinn::TransportConfig::default()));
-
-        let bind_addr = "0.0.0.0:0"
-            .parse::<std::net::SocketAddr>()
-            .map_err(|e| e.to_string())?;
-        let mut endpoint =
-            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
-        endpoint.set_default_client_config(client_config);
-
-        let connecting = endpoint
-            .connect(addr, server_name)
-            .map_err(|e| format!("quic connect setup failed: {}", e))?;
-        let connection = tokio::time::timeout(connect_timeout, connecting)
-            .await
-            .map_err(|_| "quic connect timed out".to_string())?
-            .map_err(|e| format!("quic connect failed: {}", e))?;
-
-        // Best-effort sanity check: ensure ALPN negotiated to h3.
-        let is_h3 = connection
-            .handshake_data()
-            .and_then(|any| any.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
-            .and_then(|hs| hs.protocol)
-            .as_deref()
-            == Some(b"h3");
-        if !is_h3 {
-            connection.close(0u32.into(), b"no-h3");
-            endpoint.wait_idle().await;
-            return Err("gateway did not negotiate ALPN h3".to_string());
-        }
-
-        let quinn_conn = h3_quinn::Connection::new(connection);
-        let (mut h3_conn, mut sender) = h3::client::builder()
-            .enable_extended_connect(true)
-            .enable_datagram(true)
-            .build::<_, _, Bytes>(quinn_conn)
-            .await
-            .map_err(|e| format!("h3 client init failed: {e:?}"))?;
-
-        let uri: http::Uri = format!("https://{}/.well-known/masque/udp/127.0.0.1/9/", host)
-            .parse()
-            .map_err(|e| format!("invalid uri: {e}"))?;
-
-        let mut req = http::Request::builder()
-            .method(http::Method::CONNECT)
-            .uri(uri)
-            .header("authorization", format!("Bearer {}", auth_token))
-            .body(())
-            .map_err(|e| format!("request build failed: {e}"))?;
-        req.extensions_mut().insert(Protocol::CONNECT_UDP);
-
-        let mut stream = tokio::time::timeout(request_timeout, sender.send_request(req))
-            .await
-            .map_err(|_| "h3 send_request timed out".to_string())?
-            .map_err(|e| format!("h3 send_request failed: {e:?}"))?;
-
-        let resp = tokio::time::timeout(request_timeout, stream.recv_response())
-            .await
-            .map_err(|_| "h3 recv_response timed out".to_string())?
-            .map_err(|e| format!("h3 recv_response failed: {e:?}"))?;
-
-        // Close stream and connection.
-        let _ = stream.finish().await;
-        let _ = h3_conn.shutdown(0).await;
-        let _ = h3_conn.wait_idle().await;
-        endpoint.wait_idle().await;
-
-        if resp.status() == http::StatusCode::OK {
-            Ok(())
-        } else if resp.status() == http::StatusCode::UNAUTHORIZED {
-            Err("connect-udp unauthorized".to_string())
-        } else {
-            Err(format!("connect-udp unexpected status: {}", resp.status()))
+/// Soft `RLIMIT_NOFILE` below which the gateway may run out of sockets.
+const FD_LIMIT_WARN_THRESHOLD: u64 = 1024;
+
+/// `None` means unlimited (`RLIM_INFINITY`).
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+fn fd_limit_threshold_check(soft: Option<u64>) -> DoctorCheck {
+    match soft {
+        Some(soft) if soft < FD_LIMIT_WARN_THRESHOLD => mk(
+            "sys.fd_limit",
+            "warn",
+            format!(
+                "open file limit {} is low; recommended >= {} (raise with ulimit -n)",
+                soft, FD_LIMIT_WARN_THRESHOLD
+            ),
+        ),
+        Some(soft) => mk("sys.fd_limit", "pass", format!("open file limit {}", soft)),
+        None => mk("sys.fd_limit", "pass", "open file limit unlimited"),
+    }
+}
+
+fn fd_limit_check() -> DoctorCheck {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: getrlimit only writes to the provided struct.
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+            return mk(
+                "sys.fd_limit",
+                "warn",
+                format!("getrlimit failed: {}", std::io::Error::last_os_error()),
+            );
         }
-    })
+        let soft = (limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur);
+        fd_limit_threshold_check(soft)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        mk(
+            "sys.fd_limit",
+            "warn",
+            "fd limit check not supported on this OS",
+        )
+    }
 }
 
-fn connect_udp_datagram_echo_check(
-    host: &str,
-    port: u16,
-    server_name: &str,
-    ca_cert_path: Option<&str>,
-    auth_token: Option<&str>,
-) -> Result<(), String> {
-    let addr = format!("{}:{}", host, port);
-    let addr = addr
-        .to_socket_addrs()
-        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
-        .next()
-        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
-
-    let ca_cert_path =
-        ca_cert_path.ok_or_else(|| "missing ca_cert_path for TLS verification".to_string())?;
-    let auth_token =
-        auth_token.ok_or_else(|| "missing auth_token for token verification".to_string())?;
-
-    let ca_store = load_ca_certs(Path::new(ca_cert_path))?;
-    let mut crypto = rustls::ClientConfig::builder()
-        .with_root_certificates(ca_store)
-        .with_no_client_auth();
-    crypto.alpn_protocols = vec![b"h3".to_vec()];
-    let crypto = QuicClientConfig::try_from(crypto)
-        .map_err(|e| format!("quic client config failed: {}", e))?;
-
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .map_err(|e| format!("tokio init failed: {}", e))?;
-
-    let connect_timeout = Duration::from_millis(1200);
-    let request_timeout = Duration::from_millis(1200);
-    let datagram_timeout = Duration::from_millis(1200);
-
-    rt.block_on(async move {
-        let mut client_config = ClientConfig::new(Arc::new(crypto));
-        client_config.transport_config(Arc::new(quinn::TransportConfig::default()));
-
-        let bind_addr = "0.0.0.0:0"
-            .parse::<std::net::SocketAddr>()
-            .map_err(|e| e.to_string())?;
-        let mut endpoint =
-            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
-        endpoint.set_default_client_config(client_config);
-
-        let connecting = endpoint
-            .connect(addr, server_name)
-            .map_err(|e| format!("quic connect setup failed: {}", e))?;
-        let connection = tokio::time::timeout(connect_timeout, connecting)
-            .await
-            .map_err(|_| "quic connect timed out".to_string())?
-            .map_err(|e| format!("quic connect failed: {}", e))?;
-
-        let is_h3 = connection
-            .handshake_data()
-            .and_then(|any| any.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
-            .and_then(|hs| hs.protocol)
-            .as_deref()
-            == Some(b"h3");
-        if !is_h3 {
-            connection.close(0u32.into(), b"no-h3");
-            endpoint.wait_idle().await;
-            return Err("gateway did not negotiate ALPN h3".to_string());
-        }
-
-        let quinn_conn = h3_quinn::Connection::new(connection);
-        let (mut h3_conn, mut sender) = h3::client::builder()
-            .enable_extended_connect(true)
-            .enable_datagram(true)
-            .build::<_, _, Bytes>(quinn_conn)
-            .await
-            .map_err(|e| format!("h3 client init failed: {e:?}"))?;
-
-        let uri: http::Uri = format!("https://{}/.well-known/masque/udp/127.0.0.1/9/", host)
-            .parse()
-            .map_err(|e| format!("invalid uri: {e}"))?;
-
-        let mut req = http::Request::builder()
-            .method(http::Method::CONNECT)
-            .uri(uri)
-            .header("authorization", format!("Bearer {}", auth_token))
-            .body(())
-            .map_err(|e| format!("request build failed: {e}"))?;
-        req.extensions_mut().insert(Protocol::CONNECT_UDP);
-
-        let mut stream = tokio::time::timeout(request_timeout, sender.send_request(req))
-            .await
-            .map_err(|_| "h3 send_request timed out".to_string())?
-            .map_err(|e| format!("h3 send_request failed: {e:?}"))?;
-
-        let resp = tokio::time::timeout(request_timeout, stream.recv_response())
-            .await
-            .map_err(|_| "h3 recv_response timed out".to_string())?
-            .map_err(|e| format!("h3 recv_response failed: {e:?}"))?;
-
-        if resp.status() != http::StatusCode::OK {
-            let _ = stream.finish().await;
-            let _ = h3_conn.shutdown(0).await;
-            let _ = h3_conn.wait_idle().await;
-            endpoint.wait_idle().await;
-            return Err(format!("connect-udp unexpected status: {}", resp.status()));
-        }
-
-        let stream_id = stream.id();
-        let mut dg_sender = h3_conn.get_datagram_sender(stream_id);
-        let mut dg_reader = h3_conn.get_datagram_reader();
-
-        // For CONNECT-UDP, datagram payload is: varint(context_id) || payload.
-        // Context ID 0 encodes to a single 0x00 byte.
-        let probe = Bytes::from_static(b"\x00toppy-connect-udp-echo");
-        dg_sender
-            .send_datagram(probe.clone())
-            .map_err(|e| format!("send datagram failed: {e}"))?;
-
-        let echoed = tokio::time::timeout(datagram_timeout, async {
-            loop {
-                let dg = dg_reader
-                    .read_datagram()
-                    .await
-                    .map_err(|e| format!("read datagram failed: {e:?}"))?;
-                if dg.stream_id() != stream_id {
-                    continue;
-                }
-                let mut payload = dg.into_payload();
-                let bytes = payload.copy_to_bytes(payload.remaining());
-                return Ok::<Bytes, String>(bytes);
+/// Network checks against one gateway endpoint.
+#[cfg_attr(not(feature = "ring"), allow(unused_variables))]
+fn endpoint_checks(cfg: &config::Config, host: &str, port: u16) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    let dns_ok = match dns_check(host, port) {
+        Ok(count) => {
+            checks.push(mk(
+                "net.dns",
+                "pass",
+                format!("resolved {}:{} to {} addr(s)", host, port, count),
+            ));
+            true
+        }
+        Err(e) => {
+            checks.push(mk("net.dns", "fail", e));
+            false
+        }
+    };
+
+    match env::var("TOPPY_DOCTOR_NET").as_deref() {
+        Ok("pass") => {
+            for id in RUNTIME_CHECK_IDS {
+                checks.push(mk(id, "pass", "forced pass via TOPPY_DOCTOR_NET"));
             }
-        })
-        .await
-        .map_err(|_| "datagram echo timed out".to_string())??;
+        }
+        Ok("fail") => {
+            for id in RUNTIME_CHECK_IDS {
+                checks.push(mk(id, "fail", "forced fail via TOPPY_DOCTOR_NET"));
+            }
+        }
+        Ok("skip") => {
+            for id in RUNTIME_CHECK_IDS {
+                checks.push(mk(id, "warn", "skipped via TOPPY_DOCTOR_NET"));
+            }
+        }
+        _ if !dns_ok => {
+            for id in RUNTIME_CHECK_IDS {
+                checks.push(mk(id, "warn", "skipped because net.dns failed"));
+            }
+        }
+        #[cfg(feature = "ring")]
+        _ => checks.extend(probes::endpoint_probes(cfg, host, port)),
+        #[cfg(not(feature = "ring"))]
+        _ => {
+            for id in RUNTIME_CHECK_IDS {
+                checks.push(mk(id, "warn", "skipped: built without the ring feature"));
+            }
+        }
+    }
+    checks
+}
 
-        let _ = stream.finish().await;
-        let _ = h3_conn.shutdown(0).await;
-        let _ = h3_conn.wait_idle().await;
-        endpoint.wait_idle().await;
+/// Whether an endpoint's checks show it unreachable, so the fallback is tried.
+fn endpoint_failed(checks: &[DoctorCheck]) -> bool {
+    checks
+        .iter()
+        .any(|c| matches!(c.id.as_str(), "net.dns" | "h3.connect") && c.status == "fail")
+}
 
-        if echoed == probe {
-            Ok(())
-        } else {
-            Err("datagram echo mismatch".to_string())
+/// Probes `endpoints` in order until one is reachable, returning that
+/// endpoint's checks. With a fallback configured every summary names the
+/// endpoint it ran against, and a fallback that had to be used downgrades a
+/// passing `h3.connect` to `warn`, naming the primary that failed.
+fn probe_with_fallback(
+    endpoints: &[(String, u16)],
+    mut probe: impl FnMut(&str, u16) -> Vec<DoctorCheck>,
+) -> Vec<DoctorCheck> {
+    let mut failed: Vec<String> = Vec::new();
+    let mut checks = Vec::new();
+    for (i, (host, port)) in endpoints.iter().enumerate() {
+        checks = probe(host, *port);
+        if endpoints.len() == 1 {
+            return checks;
+        }
+        let role = if i == 0 { "primary" } else { "fallback" };
+        for check in &mut checks {
+            check.summary = format!("[{} {}:{}] {}", role, host, port, check.summary);
+        }
+        if !endpoint_failed(&checks) || i + 1 == endpoints.len() {
+            break;
+        }
+        failed.push(format!("{}:{}", host, port));
+    }
+    if !failed.is_empty() {
+        if let Some(check) = checks.iter_mut().find(|c| c.id == "h3.connect") {
+            if check.status == "pass" {
+                check.status = "warn".to_string();
+            }
+            check.summary = format!("{}; primary {} failed", check.summary, failed.join(", "));
         }
-    })
+    }
+    checks
 }
 
 /// Runs a set of diagnostics and returns a report.
 ///
 /// Dynamic implementation:
-/// - Loads config from `TOPPY_CONFIG` or `~/.config/toppy/config.toml`
+/// - Loads config from `TOPPY_CONFIG` or [`config::default_config_path`]
 /// - Checks DNS resolution and minimal QUIC ping for `gateway:port` with TLS and token validation
 pub fn doctor_check() -> DoctorReport {
     let mut checks: Vec<DoctorCheck> = Vec::new();
@@ -579,150 +627,20 @@ pub fn doctor_check() -> DoctorReport {
     // 2) network reachability (basic)
     match cfg_res.as_ref() {
         Ok((cfg, _path)) => {
-            let host = cfg
-                .gateway
-                .clone()
-                .unwrap_or_else(|| "127.0.0.1".to_string());
-            let port = cfg.port.unwrap_or(4433);
-            let server_name = cfg.server_name.clone().unwrap_or_else(|| host.clone());
-            let dns_ok = match dns_check(&host, port) {
-                Ok(count) => {
-                    checks.push(mk(
-                        "net.dns",
-                        "pass",
-                        format!("resolved {}:{} to {} addr(s)", host, port, count),
-                    ));
-                    true
-                }
-                Err(e) => {
-                    checks.push(mk("net.dns", "fail", e));
-                    false
-                }
-            };
-
-            match env::var("TOPPY_DOCTOR_NET").as_deref() {
-                Ok("pass") => {
-                    checks.push(mk("h3.connect", "pass", "forced pass via TOPPY_DOCTOR_NET"));
-                    checks.push(mk(
-                        "masque.connect_udp",
-                        "pass",
-                        "forced pass via TOPPY_DOCTOR_NET",
-                    ));
-                    checks.push(mk(
-                        "masque.connect_udp.datagram",
-                        "pass",
-                        "forced pass via TOPPY_DOCTOR_NET",
-                    ));
-                }
-                Ok("fail") => {
-                    checks.push(mk("h3.connect", "fail", "forced fail via TOPPY_DOCTOR_NET"));
-                    checks.push(mk(
-                        "masque.connect_udp",
-                        "fail",
-                        "forced fail via TOPPY_DOCTOR_NET",
-                    ));
-                    checks.push(mk(
-                        "masque.connect_udp.datagram",
-                        "fail",
-                        "forced fail via TOPPY_DOCTOR_NET",
-                    ));
-                }
-                Ok("skip") => {
-                    checks.push(mk("h3.connect", "warn", "skipped via TOPPY_DOCTOR_NET"));
-                    checks.push(mk(
-                        "masque.connect_udp",
-                        "warn",
-                        "skipped via TOPPY_DOCTOR_NET",
-                    ));
-                    checks.push(mk(
-                        "masque.connect_udp.datagram",
-                        "warn",
-                        "skipped via TOPPY_DOCTOR_NET",
-                    ));
-                }
-                _ if !dns_ok => {
-                    checks.push(mk("h3.connect", "warn", "skipped because net.dns failed"));
-                    checks.push(mk(
-                        "masque.connect_udp",
-                        "warn",
-                        "skipped because net.dns failed",
-                    ));
-                    checks.push(mk(
-                        "masque.connect_udp.datagram",
-                        "warn",
-                        "skipped because net.dns failed",
-                    ));
-                }
-                _ => {
-                    match quic_ping_check(
-                        &host,
-                        port,
-                        &server_name,
-                        cfg.ca_cert_path.as_deref(),
-                        cfg.auth_token.as_deref(),
-                    ) {
-                        Ok(()) => checks.push(mk(
-                            "h3.connect",
-                            "pass",
-                            format!("quic ping ok {}:{}", host, port),
-                        )),
-                        Err(e) => checks.push(mk("h3.connect", "fail", e)),
-                    }
-
-                    match connect_udp_handshake_check(
-                        &host,
-                        port,
-                        &server_name,
-                        cfg.ca_cert_path.as_deref(),
-                        cfg.auth_token.as_deref(),
-                    ) {
-                        Ok(()) => checks.push(mk(
-                            "masque.connect_udp",
-                            "pass",
-                            format!("connect-udp handshake ok {}:{}", host, port),
-                        )),
-                        Err(e) => checks.push(mk("masque.connect_udp", "fail", e)),
-                    }
-
-                    match connect_udp_datagram_echo_check(
-                        &host,
-                        port,
-                        &server_name,
-                        cfg.ca_cert_path.as_deref(),
-                        cfg.auth_token.as_deref(),
-                    ) {
-                        Ok(()) => checks.push(mk(
-                            "masque.connect_udp.datagram",
-                            "pass",
-                            format!("connect-udp datagram echo ok {}:{}", host, port),
-                        )),
-                        Err(e) => checks.push(mk("masque.connect_udp.datagram", "fail", e)),
-                    }
-                }
-            }
+            checks.extend(probe_with_fallback(
+                &cfg.gateway_endpoints(),
+                |host, port| endpoint_checks(cfg, host, port),
+            ));
         }
         Err(_) => {
             // config が無いならネットチェックは “warn (skip)” にする
-            checks.push(mk(
-                "net.dns",
-                "warn",
-                "skipped because config load failed (set TOPPY_CONFIG or create ~/.config/toppy/config.toml)",
-            ));
-            checks.push(mk(
-                "h3.connect",
-                "warn",
-                "skipped because config load failed (set TOPPY_CONFIG or create ~/.config/toppy/config.toml)",
-            ));
-            checks.push(mk(
-                "masque.connect_udp",
-                "warn",
-                "skipped because config load failed (set TOPPY_CONFIG or create ~/.config/toppy/config.toml)",
-            ));
-            checks.push(mk(
-                "masque.connect_udp.datagram",
-                "warn",
-                "skipped because config load failed (set TOPPY_CONFIG or create ~/.config/toppy/config.toml)",
-            ));
+            let summary = format!(
+                "skipped because config load failed (set TOPPY_CONFIG or create {})",
+                config::config_path().display()
+            );
+            for id in std::iter::once(&"net.dns").chain(RUNTIME_CHECK_IDS) {
+                checks.push(mk(id, "warn", summary.as_str()));
+            }
         }
     }
 
@@ -733,30 +651,40 @@ pub fn doctor_check() -> DoctorReport {
         _ => checks.push(tun_perm_check()),
     }
     checks.push(mtu_sanity_check(mtu_value));
+    if let Ok((cfg, _)) = cfg_res.as_ref() {
+        checks.push(datagram_size_check(cfg));
+    }
+    checks.push(entropy_check());
+    checks.push(fd_limit_check());
+
+    let policy_cfg = cfg_res
+        .as_ref()
+        .map_err(|_| "config load failed".to_string())
+        .and_then(|(cfg, _)| config::load_policy(cfg));
+    if let Some(policy) = policy_cfg
+        .as_ref()
+        .ok()
+        .and_then(Option::as_ref)
+        .and_then(|policy_cfg| Policy::from_config(policy_cfg).ok())
+    {
+        checks.push(policy_lint_check(&policy));
+    }
+
+    if let Some(audit_path) = cfg_res
+        .as_ref()
+        .ok()
+        .and_then(|(cfg, _)| cfg.audit_path.as_deref())
+    {
+        checks.push(audit_writable_check(Path::new(audit_path)));
+    }
 
     if let Ok(target_spec) = env::var("TOPPY_DOCTOR_TARGET") {
         match &cfg_res {
-            Ok((cfg, _)) => match parse_policy_target(&target_spec) {
-                Ok(target) => match cfg.policy.as_ref() {
-                    Some(policy_cfg) => match Policy::from_config(policy_cfg) {
-                        Ok(policy) => match policy.evaluate(&target) {
-                            Decision::Allow => checks.push(mk(
-                                "policy.denied",
-                                "pass",
-                                format!("target {}:{} allowed", target.ip, target.port),
-                            )),
-                            Decision::Deny { reason } => {
-                                checks.push(mk("policy.denied", "fail", reason))
-                            }
-                        },
-                        Err(err) => checks.push(mk("policy.denied", "fail", err)),
-                    },
-                    None => checks.push(mk("policy.denied", "warn", "policy not configured")),
-                },
-                Err(err) => {
-                    checks.push(mk("policy.denied", "fail", err));
-                }
-            },
+            Ok(_) => checks.push(policy_target_check(
+                "policy.denied",
+                &target_spec,
+                &policy_cfg,
+            )),
             Err(_) => checks.push(mk(
                 "policy.denied",
                 "warn",
@@ -765,10 +693,436 @@ pub fn doctor_check() -> DoctorReport {
         }
     }
 
-    let overall = aggregate_overall(&checks);
-    DoctorReport {
+    if let Ok((cfg, _)) = &cfg_res {
+        let targets = cfg.doctor.as_ref().map(|d| d.targets.as_slice());
+        for target_spec in targets.unwrap_or_default() {
+            checks.push(policy_target_check(
+                &format!("policy.target[{}]", target_spec),
+                target_spec,
+                &policy_cfg,
+            ));
+        }
+    }
+
+    sort_checks(&mut checks);
+    let threshold = cfg_res
+        .as_ref()
+        .ok()
+        .and_then(|(cfg, _)| cfg.doctor.as_ref()?.overall_threshold);
+    let insecure = cfg_res
+        .as_ref()
+        .is_ok_and(|(cfg, _)| cfg.insecure_skip_verify);
+    let mut report = DoctorReport {
+        schema_version: DOCTOR_SCHEMA_VERSION,
         version: env!("CARGO_PKG_VERSION").to_string(),
-        overall,
+        overall: aggregate_overall(&checks),
+        overall_mode: "max_severity".to_string(),
         checks,
+        counts: StatusCounts::default(),
+    };
+    let (pass, warn, fail) = report.status_counts();
+    report.counts = StatusCounts { pass, warn, fail };
+    if let Some(percent) = threshold {
+        report.apply_threshold(f64::from(percent) / 100.0, insecure);
+    }
+    report
+}
+
+/// Resolves `target_spec` and evaluates it against the configured policy.
+fn policy_target_check(
+    id: &str,
+    target_spec: &str,
+    policy_cfg: &Result<Option<PolicyConfig>, String>,
+) -> DoctorCheck {
+    let (host, port) = match split_host_port(target_spec) {
+        Ok(parts) => parts,
+        Err(err) => return mk(id, "fail", err),
+    };
+    let policy = match policy_cfg
+        .as_ref()
+        .map(|cfg| cfg.as_ref().map(Policy::from_config))
+    {
+        Ok(Some(Ok(policy))) => policy,
+        Ok(Some(Err(err))) => return mk(id, "fail", err),
+        Ok(None) => return mk(id, "warn", "policy not configured"),
+        Err(err) => return mk(id, "fail", err.clone()),
+    };
+    match resolve_allowed_rules(&host, port, &policy) {
+        Ok(allowed) => {
+            let rules = policy.describe_rules(allowed.iter().filter_map(|(_, rule)| *rule));
+            mk(
+                id,
+                "pass",
+                format!(
+                    "target {} allowed ({} addr(s)) by {}",
+                    target_spec,
+                    allowed.len(),
+                    rules
+                ),
+            )
+        }
+        Err(reason) => mk(id, "fail", reason),
+    }
+}
+
+/// Opens the audit log for append (creating it empty if missing) without
+/// writing an entry, so permission problems surface before the first event.
+fn audit_writable_check(path: &Path) -> DoctorCheck {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return mk(
+                "audit.writable",
+                "warn",
+                format!("cannot create audit dir {}: {}", dir.display(), e),
+            );
+        }
+    }
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(_) => mk(
+            "audit.writable",
+            "pass",
+            format!("audit log {} is writable", path.display()),
+        ),
+        Err(e) => mk(
+            "audit.writable",
+            "fail",
+            format!("cannot open audit log {} for append: {}", path.display(), e),
+        ),
+    }
+}
+
+/// Advisory only: overlapping rules are legal, so findings never fail.
+fn policy_lint_check(policy: &Policy) -> DoctorCheck {
+    let lints = policy.lint();
+    match lints.first() {
+        None => mk("policy.lint", "pass", "no overlapping rules"),
+        Some(first) => mk(
+            "policy.lint",
+            "warn",
+            format!("{} finding(s): {}", lints.len(), first.message),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyRule;
+
+    #[test]
+    fn policy_lint_check_warns_on_shadowed_rule() {
+        let policy = Policy {
+            allow: vec![
+                PolicyRule::parse("10.0.0.0/16", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.1.0/24", vec![22]).expect("rule"),
+            ],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let check = policy_lint_check(&policy);
+        assert_eq!(check.id, "policy.lint");
+        assert_eq!(check.status, "warn");
+        assert!(check.summary.contains("shadowed"));
+    }
+
+    #[test]
+    fn audit_writable_check_passes_for_new_path() {
+        let dir = std::env::temp_dir().join(format!("toppy-audit-ok-{}", std::process::id()));
+        let path = dir.join("logs").join("audit.jsonl");
+        let check = audit_writable_check(&path);
+        assert_eq!(check.status, "pass", "{}", check.summary);
+        assert_eq!(fs::metadata(&path).expect("audit file").len(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn audit_writable_check_fails_when_path_is_a_directory() {
+        let dir = std::env::temp_dir().join(format!("toppy-audit-dir-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create dir");
+        let check = audit_writable_check(&dir);
+        assert_eq!(check.id, "audit.writable");
+        assert_eq!(check.status, "fail", "{}", check.summary);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn datagram_size_check_reports_effective_size() {
+        let check = datagram_size_check(&config::Config::default());
+        assert_eq!(check.status, "pass");
+        assert_eq!(check.summary, "max datagram payload 1255 bytes (mtu 1350)");
+
+        let cfg = config::Config {
+            mtu: Some(1280),
+            max_datagram_size: Some(1400),
+            ..Default::default()
+        };
+        let check = datagram_size_check(&cfg);
+        assert_eq!(check.status, "warn", "{}", check.summary);
+    }
+
+    #[test]
+    fn checks_sort_into_canonical_order() {
+        let mut checks: Vec<DoctorCheck> = [
+            "policy.lint",
+            "zz.custom",
+            "sys.entropy",
+            "masque.connect_udp.datagram",
+            "policy.denied",
+            "mtu.sanity",
+            "net.dns",
+            "tun.perm",
+            "masque.connect_udp",
+            "h3.connect",
+            "cfg.load",
+        ]
+        .iter()
+        .map(|id| mk(id, "pass", ""))
+        .collect();
+        sort_checks(&mut checks);
+        let ids: Vec<&str> = checks.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "cfg.load",
+                "net.dns",
+                "h3.connect",
+                "masque.connect_udp",
+                "masque.connect_udp.datagram",
+                "tun.perm",
+                "mtu.sanity",
+                "sys.entropy",
+                "policy.denied",
+                "policy.lint",
+                "zz.custom",
+            ]
+        );
+    }
+
+    #[test]
+    fn builtin_checks_have_expected_categories() {
+        let expected = [
+            ("cfg.load", "config"),
+            ("net.dns", "network"),
+            ("h3.connect", "network"),
+            ("tls.alpn", "network"),
+            ("masque.connect_udp", "network"),
+            ("masque.connect_udp.datagram", "network"),
+            ("tls.0rtt", "network"),
+            ("tls.sni_match", "network"),
+            ("tun.perm", "system"),
+            ("mtu.sanity", "system"),
+            ("mtu.datagram", "system"),
+            ("sys.entropy", "system"),
+            ("sys.fd_limit", "system"),
+            ("sys.runtime", "system"),
+            ("policy.denied", "security"),
+            ("policy.lint", "security"),
+            ("policy.target[10.0.0.5:22]", "security"),
+            ("audit.writable", "security"),
+        ];
+        for (id, category) in expected {
+            assert_eq!(mk(id, "pass", "").category, category, "{}", id);
+        }
+
+        let report = DoctorReport {
+            schema_version: DOCTOR_SCHEMA_VERSION,
+            version: String::new(),
+            overall: "pass".to_string(),
+            overall_mode: "max_severity".to_string(),
+            checks: expected.iter().map(|(id, _)| mk(id, "pass", "")).collect(),
+            counts: StatusCounts::default(),
+        };
+        let groups = report.by_category();
+        assert_eq!(groups["network"].len(), 7);
+        assert_eq!(report.status_counts(), (expected.len(), 0, 0));
+        assert_eq!(groups["security"][0].id, "policy.denied");
+    }
+
+    #[test]
+    fn status_counts_tally_each_status() {
+        let report = DoctorReport {
+            schema_version: DOCTOR_SCHEMA_VERSION,
+            version: String::new(),
+            overall: "fail".to_string(),
+            overall_mode: "max_severity".to_string(),
+            checks: vec![
+                mk("cfg.load", "pass", ""),
+                mk("net.dns", "pass", ""),
+                mk("h3.connect", "warn", ""),
+                mk("masque.connect_udp", "fail", ""),
+                mk("tun.perm", "pass", ""),
+            ],
+            counts: StatusCounts::default(),
+        };
+        assert_eq!(report.status_counts(), (3, 1, 1));
+    }
+
+    #[test]
+    fn weighted_overall_ignores_advisory_warn_unlike_max_severity() {
+        let mut checks = vec![
+            mk("cfg.load", "pass", ""),
+            mk("net.dns", "pass", ""),
+            mk("h3.connect", "pass", ""),
+            mk("masque.connect_udp", "pass", ""),
+            mk("sys.entropy", "warn", ""),
+        ];
+        let report = |checks: &[DoctorCheck]| DoctorReport {
+            schema_version: DOCTOR_SCHEMA_VERSION,
+            version: String::new(),
+            overall: aggregate_overall(checks),
+            overall_mode: "max_severity".to_string(),
+            checks: checks.to_vec(),
+            counts: StatusCounts::default(),
+        };
+        let advisory_warn = report(&checks);
+        assert_eq!(advisory_warn.overall, "warn");
+        assert_eq!(advisory_warn.weighted_overall(1.0), "pass");
+
+        // 4 of 7 critical weight passes.
+        checks[3] = mk("masque.connect_udp", "fail", "");
+        checks[1] = mk("net.dns", "warn", "");
+        let degraded = report(&checks);
+        assert_eq!(degraded.overall, "fail");
+        assert_eq!(degraded.weighted_overall(0.5), "pass");
+        assert_eq!(degraded.weighted_overall(0.6), "fail");
+
+        checks[3] = mk("masque.connect_udp", "pass", "");
+        assert_eq!(report(&checks).weighted_overall(0.9), "warn");
+    }
+
+    #[test]
+    fn weighted_overall_is_at_most_warn_without_tls_verification() {
+        let checks = vec![
+            mk("cfg.load", "pass", ""),
+            mk("net.dns", "pass", ""),
+            mk(
+                "h3.connect",
+                "warn",
+                "TLS verification disabled (insecure_skip_verify)",
+            ),
+            mk("masque.connect_udp", "pass", ""),
+        ];
+        let report = || DoctorReport {
+            schema_version: DOCTOR_SCHEMA_VERSION,
+            version: String::new(),
+            overall: aggregate_overall(&checks),
+            overall_mode: "max_severity".to_string(),
+            checks: checks.clone(),
+            counts: StatusCounts::default(),
+        };
+
+        let mut verified = report();
+        verified.apply_threshold(0.5, false);
+        assert_eq!(verified.overall, "pass");
+        assert_eq!(verified.overall_mode, "weighted(0.50)");
+
+        let mut unverified = report();
+        unverified.apply_threshold(0.5, true);
+        assert_eq!(unverified.overall, "warn");
+    }
+
+    #[test]
+    fn probe_with_fallback_tries_fallback_after_primary_fails() {
+        let endpoints = [
+            ("gw-a.example".to_string(), 4433),
+            ("gw-b.example".to_string(), 4433),
+        ];
+        let mut attempted = Vec::new();
+        let checks = probe_with_fallback(&endpoints, |host, _| {
+            attempted.push(host.to_string());
+            let status = if host == "gw-a.example" {
+                "fail"
+            } else {
+                "pass"
+            };
+            vec![
+                mk("net.dns", "pass", "resolved"),
+                mk("h3.connect", status, "quic ping"),
+                mk("tls.alpn", "pass", "negotiated h3"),
+            ]
+        });
+        assert_eq!(attempted, ["gw-a.example", "gw-b.example"]);
+        let h3 = checks.iter().find(|c| c.id == "h3.connect").unwrap();
+        assert_eq!(h3.status, "warn");
+        assert_eq!(
+            h3.summary,
+            "[fallback gw-b.example:4433] quic ping; primary gw-a.example:4433 failed"
+        );
+        assert!(checks
+            .iter()
+            .all(|c| c.summary.starts_with("[fallback gw-b.example:4433]")));
+
+        // A healthy primary is used as-is; without a fallback nothing is tagged.
+        let mut attempted = 0;
+        let checks = probe_with_fallback(&endpoints, |_, _| {
+            attempted += 1;
+            vec![mk("h3.connect", "pass", "quic ping")]
+        });
+        assert_eq!(attempted, 1);
+        assert_eq!(checks[0].summary, "[primary gw-a.example:4433] quic ping");
+        let checks = probe_with_fallback(&endpoints[..1], |_, _| {
+            vec![mk("h3.connect", "fail", "down")]
+        });
+        assert_eq!(checks[0].summary, "down");
+    }
+
+    #[test]
+    fn failing_checks_carry_remediation_hints() {
+        let cfg = {
+            let _env = crate::test_support::scoped_env(&[
+                ("TOPPY_CONFIG", None),
+                ("XDG_CONFIG_HOME", Some("/etc/xdg-test")),
+            ]);
+            mk("cfg.load", "fail", "config not found")
+        };
+        assert_eq!(
+            cfg.hint.as_deref(),
+            Some(
+                "create /etc/xdg-test/toppy/config.toml or set TOPPY_CONFIG (`toppy doctor --fix` writes an example)"
+            )
+        );
+        let tun = mk("tun.perm", "fail", "cannot open /dev/net/tun");
+        assert_eq!(
+            tun.hint.as_deref(),
+            Some("run with CAP_NET_ADMIN or as root")
+        );
+
+        assert_eq!(mk("tun.perm", "pass", "opened").hint, None);
+        assert_eq!(mk("policy.denied", "fail", "denied").hint, None);
+        let json = serde_json::to_value(mk("cfg.load", "pass", "")).unwrap();
+        assert!(json.get("hint").is_none());
+        let json = serde_json::to_value(&tun).unwrap();
+        assert_eq!(json["hint"], "run with CAP_NET_ADMIN or as root");
+    }
+
+    #[test]
+    fn entropy_threshold_warns_when_low() {
+        let check = entropy_threshold_check(ENTROPY_WARN_THRESHOLD - 1);
+        assert_eq!(check.id, "sys.entropy");
+        assert_eq!(check.status, "warn");
+        assert!(check.summary.contains("low"));
+    }
+
+    #[test]
+    fn fd_limit_threshold_warns_below_threshold() {
+        let check = fd_limit_threshold_check(Some(256));
+        assert_eq!(check.id, "sys.fd_limit");
+        assert_eq!(check.status, "warn");
+        assert!(check.summary.contains("256"));
+        assert_eq!(
+            fd_limit_threshold_check(Some(FD_LIMIT_WARN_THRESHOLD)).status,
+            "pass"
+        );
+        assert_eq!(fd_limit_threshold_check(Some(65_536)).status, "pass");
+        assert_eq!(fd_limit_threshold_check(None).status, "pass");
+    }
+
+    #[test]
+    fn entropy_threshold_passes_at_threshold() {
+        assert_eq!(
+            entropy_threshold_check(ENTROPY_WARN_THRESHOLD).status,
+            "pass"
+        );
+        assert_eq!(entropy_threshold_check(3500).status, "pass");
     }
 }
@@ -0,0 +1,1162 @@
+//! QUIC/TLS probes against the gateway. They need rustls, quinn and h3,
+//! which pull in ring, so they are only built with the `ring` feature.
+
+use super::{mk, DoctorCheck, RUNTIME_CHECK_IDS};
+use crate::config;
+use bytes::{Buf, Bytes};
+use h3::ext::Protocol;
+use h3_datagram::datagram_handler::HandleDatagramsExt;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Endpoint};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::RootCertStore;
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use toppy_proto::masque::{
+    UdpTarget, DIAGNOSTIC_HEADER, DIAGNOSTIC_PROBE_HOST, DIAGNOSTIC_PROBE_PORT,
+};
+use toppy_proto::{error_code, ControlMessage};
+
+/// Current-thread runtime shared by doctor's network checks.
+fn doctor_runtime() -> Result<tokio::runtime::Runtime, String> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("tokio init failed: {}", e))
+}
+
+/// Reported instead of the network checks when the runtime cannot be built,
+/// so an environmental problem is not mistaken for an unreachable gateway.
+fn runtime_unavailable_checks(err: &str) -> Vec<DoctorCheck> {
+    let mut checks = vec![mk(
+        "sys.runtime",
+        "warn",
+        format!("{}; network checks skipped", err),
+    )];
+    for id in RUNTIME_CHECK_IDS {
+        checks.push(mk(id, "warn", "skipped because sys.runtime failed"));
+    }
+    checks
+}
+
+/// Discard port on loopback: CONNECT-UDP probes only need the gateway to accept.
+fn probe_target() -> UdpTarget {
+    UdpTarget::new(DIAGNOSTIC_PROBE_HOST, DIAGNOSTIC_PROBE_PORT)
+}
+
+fn load_ca_certs(path: &Path) -> Result<RootCertStore, String> {
+    let data = config::read_bounded(path, config::max_cert_bytes()?)
+        .map_err(|e| format!("ca_cert_path: {}", e))?;
+    let certs = CertificateDer::pem_slice_iter(&data)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("failed to parse CA certs from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("no CA certificates found in {}", path.display()));
+    }
+    let mut store = RootCertStore::empty();
+    for cert in certs {
+        store
+            .add(cert)
+            .map_err(|e| format!("failed to add CA cert {}: {}", path.display(), e))?;
+    }
+    Ok(store)
+}
+
+/// How the doctor's QUIC clients verify the gateway certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TlsVerify<'a> {
+    /// Trust the PEM CAs at `ca_cert_path` (required).
+    Ca(Option<&'a str>),
+    /// `insecure_skip_verify`: accept any certificate.
+    InsecureSkipVerify,
+}
+
+impl<'a> TlsVerify<'a> {
+    fn from_config(cfg: &'a config::Config) -> Self {
+        if cfg.insecure_skip_verify {
+            TlsVerify::InsecureSkipVerify
+        } else {
+            TlsVerify::Ca(cfg.ca_cert_path.as_deref())
+        }
+    }
+
+    fn client_config(self) -> Result<rustls::ClientConfig, String> {
+        match self {
+            TlsVerify::Ca(ca_cert_path) => {
+                let ca_cert_path = ca_cert_path
+                    .ok_or_else(|| "missing ca_cert_path for TLS verification".to_string())?;
+                let ca_store = load_ca_certs(Path::new(ca_cert_path))?;
+                Ok(rustls::ClientConfig::builder()
+                    .with_root_certificates(ca_store)
+                    .with_no_client_auth())
+            }
+            TlsVerify::InsecureSkipVerify => Ok(rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipServerVerification(
+                    rustls::crypto::ring::default_provider(),
+                )))
+                .with_no_client_auth()),
+        }
+    }
+}
+
+/// Accepts any server certificate; handshake signatures are still checked so
+/// the connection itself works normally.
+#[derive(Debug)]
+struct SkipServerVerification(rustls::crypto::CryptoProvider);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Downgrades a passing check to `warn` when certificate verification is off,
+/// so an unverified gateway never reports as healthy.
+fn mark_unverified(check: &mut DoctorCheck, tls: TlsVerify<'_>) {
+    if tls == TlsVerify::InsecureSkipVerify && check.status == "pass" {
+        check.status = "warn".to_string();
+        check.summary = format!(
+            "TLS verification disabled (insecure_skip_verify): {}",
+            check.summary
+        );
+    }
+}
+
+/// ALPN the CONNECT-UDP checks offer.
+const H3_ALPN: &[u8] = b"h3";
+
+fn negotiated_alpn(connection: &quinn::Connection) -> Option<Vec<u8>> {
+    connection
+        .handshake_data()
+        .and_then(|any| any.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+        .and_then(|hs| hs.protocol)
+}
+
+/// Renders an ALPN protocol id, or `none` when nothing was negotiated.
+fn alpn_label(alpn: Option<&[u8]>) -> String {
+    match alpn {
+        Some(alpn) => String::from_utf8_lossy(alpn).into_owned(),
+        None => "none".to_string(),
+    }
+}
+
+/// `tls.alpn`: compares what was offered with the handshake's outcome.
+fn alpn_check(offered: &[&[u8]], negotiated: Result<Option<Vec<u8>>, String>) -> DoctorCheck {
+    let offered_list: Vec<String> = offered.iter().map(|p| alpn_label(Some(p))).collect();
+    let offered_list = offered_list.join(",");
+    match negotiated {
+        Err(e) => mk("tls.alpn", "fail", e),
+        Ok(None) => mk(
+            "tls.alpn",
+            "warn",
+            format!(
+                "offered {}; negotiated none (gateway serves only the plain QUIC ping)",
+                offered_list
+            ),
+        ),
+        Ok(Some(alpn)) if offered.contains(&alpn.as_slice()) => mk(
+            "tls.alpn",
+            "pass",
+            format!(
+                "offered {}; negotiated {}",
+                offered_list,
+                alpn_label(Some(&alpn))
+            ),
+        ),
+        Ok(Some(alpn)) => mk(
+            "tls.alpn",
+            "fail",
+            format!(
+                "offered {}; gateway negotiated unoffered {}",
+                offered_list,
+                alpn_label(Some(&alpn))
+            ),
+        ),
+    }
+}
+
+/// Completes a TLS handshake offering `offered` and returns the negotiated ALPN.
+fn alpn_probe(
+    rt: &tokio::runtime::Runtime,
+    host: &str,
+    port: u16,
+    server_name: &str,
+    tls: TlsVerify<'_>,
+    offered: &[&[u8]],
+) -> Result<Option<Vec<u8>>, String> {
+    let addr = format!("{}:{}", host, port);
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
+
+    let mut crypto = tls.client_config()?;
+    crypto.alpn_protocols = offered.iter().map(|p| p.to_vec()).collect();
+    let crypto = QuicClientConfig::try_from(crypto)
+        .map_err(|e| format!("quic client config failed: {}", e))?;
+
+    rt.block_on(async move {
+        let bind_addr = "0.0.0.0:0"
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| e.to_string())?;
+        let mut endpoint =
+            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let connection = tokio::time::timeout(Duration::from_millis(800), connecting)
+            .await
+            .map_err(|_| "quic connect timed out".to_string())?
+            .map_err(|e| format!("quic connect failed: {}", e))?;
+        let alpn = negotiated_alpn(&connection);
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+        Ok(alpn)
+    })
+}
+
+/// Connects twice to the ping endpoint and reports whether the gateway
+/// accepted the second connection's 0-RTT `Hello`. `false` also covers a
+/// gateway that issued no resumption ticket. No token is sent, so the probe
+/// works without `auth_token` and never shows up as a failed auth attempt.
+fn zero_rtt_probe(
+    rt: &tokio::runtime::Runtime,
+    host: &str,
+    port: u16,
+    server_name: &str,
+    tls: TlsVerify<'_>,
+) -> Result<bool, String> {
+    let addr = format!("{}:{}", host, port);
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
+
+    let mut crypto = tls.client_config()?;
+    crypto.enable_early_data = true;
+    let crypto = QuicClientConfig::try_from(crypto)
+        .map_err(|e| format!("quic client config failed: {}", e))?;
+    let timeout = Duration::from_millis(800);
+    let hello = ControlMessage::hello().encode();
+
+    rt.block_on(async move {
+        let bind_addr = "0.0.0.0:0"
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| e.to_string())?;
+        let mut endpoint =
+            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
+        // Both connections share the client config, and with it the ticket store.
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+
+        // The first round trip gives the gateway time to send its ticket.
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let first = tokio::time::timeout(timeout, connecting)
+            .await
+            .map_err(|_| "quic connect timed out".to_string())?
+            .map_err(|e| format!("quic connect failed: {}", e))?;
+        let round_trip = async {
+            let (mut send, mut recv) = first
+                .open_bi()
+                .await
+                .map_err(|e| format!("quic open stream failed: {}", e))?;
+            send.write_all(&hello)
+                .await
+                .map_err(|e| format!("quic send failed: {}", e))?;
+            send.finish()
+                .map_err(|e| format!("quic finish failed: {}", e))?;
+            recv.read_to_end(PING_REPLY_LIMIT)
+                .await
+                .map_err(|e| reply_read_error(e, PING_REPLY_LIMIT))
+        };
+        tokio::time::timeout(timeout, round_trip)
+            .await
+            .map_err(|_| "quic read timed out".to_string())??;
+        first.close(0u32.into(), b"done");
+
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let accepted = match connecting.into_0rtt() {
+            Ok((second, accepted)) => {
+                // The reply doesn't matter; only whether the early data was accepted.
+                if let Ok((mut send, _recv)) = second.open_bi().await {
+                    let _ = send.write_all(&hello).await;
+                    let _ = send.finish();
+                }
+                let accepted = tokio::time::timeout(timeout, accepted)
+                    .await
+                    .map_err(|_| "quic handshake timed out".to_string())?;
+                second.close(0u32.into(), b"done");
+                accepted
+            }
+            Err(connecting) => {
+                if let Ok(Ok(second)) = tokio::time::timeout(timeout, connecting).await {
+                    second.close(0u32.into(), b"done");
+                }
+                false
+            }
+        };
+        endpoint.wait_idle().await;
+        Ok(accepted)
+    })
+}
+
+fn zero_rtt_check(result: Result<bool, String>) -> DoctorCheck {
+    match result {
+        Ok(true) => mk("tls.0rtt", "pass", "0-RTT data accepted on resumption"),
+        // Informational: 0-RTT is off unless the gateway sets TOPPY_GW_ENABLE_0RTT.
+        Ok(false) => mk(
+            "tls.0rtt",
+            "pass",
+            "0-RTT not accepted; resumed connections use a full handshake",
+        ),
+        Err(e) => mk("tls.0rtt", "fail", e),
+    }
+}
+
+/// Handshakes without verifying the certificate, so a name mismatch does not
+/// abort it, and returns the gateway's leaf certificate.
+fn peer_cert_probe(
+    rt: &tokio::runtime::Runtime,
+    host: &str,
+    port: u16,
+    server_name: &str,
+) -> Result<CertificateDer<'static>, String> {
+    let addr = format!("{}:{}", host, port);
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
+
+    let crypto = QuicClientConfig::try_from(TlsVerify::InsecureSkipVerify.client_config()?)
+        .map_err(|e| format!("quic client config failed: {}", e))?;
+
+    rt.block_on(async move {
+        let bind_addr = "0.0.0.0:0"
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| e.to_string())?;
+        let mut endpoint =
+            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let connection = tokio::time::timeout(Duration::from_millis(800), connecting)
+            .await
+            .map_err(|_| "quic connect timed out".to_string())?
+            .map_err(|e| format!("quic connect failed: {}", e))?;
+        let leaf = connection
+            .peer_identity()
+            .and_then(|id| id.downcast::<Vec<CertificateDer<'static>>>().ok())
+            .and_then(|certs| certs.first().cloned());
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+        leaf.ok_or_else(|| "gateway presented no certificate".to_string())
+    })
+}
+
+/// Whether `cert`'s SANs cover `server_name`, wildcards and IP addresses included.
+fn cert_covers_name(cert: &CertificateDer<'_>, server_name: &str) -> Result<bool, String> {
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|e| format!("parse gateway certificate failed: {:?}", e))?;
+    let name = ServerName::try_from(server_name)
+        .map_err(|e| format!("invalid server_name {:?}: {}", server_name, e))?;
+    Ok(cert.verify_is_valid_for_subject_name(&name).is_ok())
+}
+
+/// `tls.sni_match`: warns with the certificate's DNS SANs when `server_name`
+/// is not among them, the usual cause of a verification failure on connect.
+fn sni_match_check(
+    server_name: &str,
+    cert: Result<CertificateDer<'static>, String>,
+) -> DoctorCheck {
+    let cert = match cert {
+        Ok(cert) => cert,
+        Err(e) => return mk("tls.sni_match", "fail", e),
+    };
+    match cert_covers_name(&cert, server_name) {
+        Err(e) => mk("tls.sni_match", "fail", e),
+        Ok(true) => mk(
+            "tls.sni_match",
+            "pass",
+            format!("server_name {} is covered by the certificate", server_name),
+        ),
+        Ok(false) => {
+            let sans: Vec<&str> = webpki::EndEntityCert::try_from(&cert)
+                .map(|cert| cert.valid_dns_names().collect())
+                .unwrap_or_default();
+            let sans = if sans.is_empty() {
+                "none".to_string()
+            } else {
+                sans.join(", ")
+            };
+            mk(
+                "tls.sni_match",
+                "warn",
+                format!(
+                    "server_name {} is not in the certificate SANs (DNS SANs: {})",
+                    server_name, sans
+                ),
+            )
+        }
+    }
+}
+
+/// Largest control reply doctor reads; structured errors carry a message.
+const PING_REPLY_LIMIT: usize = 256;
+
+/// Names an oversize reply instead of reporting a generic read failure.
+fn reply_read_error(err: quinn::ReadToEndError, limit: usize) -> String {
+    match err {
+        quinn::ReadToEndError::TooLong => format!("response too large (>{} bytes)", limit),
+        err => format!("quic read failed: {}", err),
+    }
+}
+
+/// Describes a non-`Pong` reply to `ping`.
+fn ping_rejection(reply: ControlMessage) -> String {
+    match reply {
+        ControlMessage::Error { code, message } if code == error_code::UNAUTHORIZED => {
+            format!("token rejected by gateway: {}", message)
+        }
+        ControlMessage::Error { code, message } => {
+            format!("ping refused by gateway: {} (code {})", message, code)
+        }
+        other => format!("unexpected ping reply: {:?}", other),
+    }
+}
+
+/// Outcome of a successful [`quic_ping_check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct QuicPing {
+    version: u16,
+    /// ALPN the gateway negotiated (the ping client offers none).
+    alpn: Option<Vec<u8>>,
+}
+
+fn quic_ping_check(
+    rt: &tokio::runtime::Runtime,
+    host: &str,
+    port: u16,
+    server_name: &str,
+    tls: TlsVerify<'_>,
+    auth_token: Option<&str>,
+    reply_limit: usize,
+) -> Result<QuicPing, String> {
+    let addr = format!("{}:{}", host, port);
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
+
+    let auth_token =
+        auth_token.ok_or_else(|| "missing auth_token for token verification".to_string())?;
+    let crypto = tls.client_config()?;
+    let crypto = QuicClientConfig::try_from(crypto)
+        .map_err(|e| format!("quic client config failed: {}", e))?;
+
+    let connect_timeout = Duration::from_millis(800);
+    let stream_timeout = Duration::from_millis(800);
+
+    rt.block_on(async move {
+        let mut client_config = ClientConfig::new(Arc::new(crypto));
+        client_config.transport_config(Arc::new(quinn::TransportConfig::default()));
+
+        let bind_addr = "0.0.0.0:0"
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| e.to_string())?;
+        let mut endpoint =
+            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
+        endpoint.set_default_client_config(client_config);
+
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let connection = tokio::time::timeout(connect_timeout, connecting)
+            .await
+            .map_err(|_| "quic connect timed out".to_string())?
+            .map_err(|e| format!("quic connect failed: {}", e))?;
+
+        let exchange = |payload: Vec<u8>, limit: usize| {
+            let connection = connection.clone();
+            async move {
+                let (mut send, mut recv) =
+                    tokio::time::timeout(stream_timeout, connection.open_bi())
+                        .await
+                        .map_err(|_| "quic open stream timed out".to_string())?
+                        .map_err(|e| format!("quic open stream failed: {}", e))?;
+                send.write_all(&payload)
+                    .await
+                    .map_err(|e| format!("quic send failed: {}", e))?;
+                send.finish()
+                    .map_err(|e| format!("quic finish failed: {}", e))?;
+                tokio::time::timeout(stream_timeout, recv.read_to_end(limit))
+                    .await
+                    .map_err(|_| "quic read timed out".to_string())?
+                    .map_err(|e| reply_read_error(e, limit))
+            }
+        };
+
+        let alpn = negotiated_alpn(&connection);
+        let hello = exchange(ControlMessage::hello().encode(), reply_limit).await;
+        let data = exchange(format!("ping {}", auth_token).into_bytes(), reply_limit).await;
+
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+
+        let version = match ControlMessage::decode(&hello?) {
+            Ok((ControlMessage::HelloAck { version }, _)) => version,
+            Ok((ControlMessage::Error { message, .. }, _)) => {
+                return Err(format!("version negotiation failed: {}", message))
+            }
+            Ok((other, _)) => return Err(format!("unexpected hello reply: {:?}", other)),
+            Err(e) => return Err(format!("invalid hello reply: {}", e)),
+        };
+        let data = data?;
+        match ControlMessage::decode_ping_reply(&data) {
+            Ok(ControlMessage::Pong) => Ok(QuicPing { version, alpn }),
+            Err(_) => Err(format!("unexpected response: {:?}", data)),
+            Ok(reply) => Err(ping_rejection(reply)),
+        }
+    })
+}
+
+fn connect_udp_handshake_check(
+    rt: &tokio::runtime::Runtime,
+    host: &str,
+    port: u16,
+    server_name: &str,
+    tls: TlsVerify<'_>,
+    auth_token: Option<&str>,
+) -> Result<(), String> {
+    let addr = format!("{}:{}", host, port);
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
+
+    let auth_token =
+        auth_token.ok_or_else(|| "missing auth_token for token verification".to_string())?;
+
+    let mut crypto = tls.client_config()?;
+    crypto.alpn_protocols = vec![H3_ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(crypto)
+        .map_err(|e| format!("quic client config failed: {}", e))?;
+
+    let connect_timeout = Duration::from_millis(1200);
+    let request_timeout = Duration::from_millis(1200);
+
+    rt.block_on(async move {
+        let mut client_config = ClientConfig::new(Arc::new(crypto));
+        client_config.transport_config(Arc::new(quinn::TransportConfig::default()));
+
+        let bind_addr = "0.0.0.0:0"
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| e.to_string())?;
+        let mut endpoint =
+            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
+        endpoint.set_default_client_config(client_config);
+
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let connection = tokio::time::timeout(connect_timeout, connecting)
+            .await
+            .map_err(|_| "quic connect timed out".to_string())?
+            .map_err(|e| format!("quic connect failed: {}", e))?;
+
+        // Best-effort sanity check: ensure ALPN negotiated to h3.
+        let is_h3 = negotiated_alpn(&connection).as_deref() == Some(H3_ALPN);
+        if !is_h3 {
+            connection.close(0u32.into(), b"no-h3");
+            endpoint.wait_idle().await;
+            return Err("gateway did not negotiate ALPN h3".to_string());
+        }
+
+        let quinn_conn = h3_quinn::Connection::new(connection);
+        let (mut h3_conn, mut sender) = h3::client::builder()
+            .enable_extended_connect(true)
+            .enable_datagram(true)
+            .build::<_, _, Bytes>(quinn_conn)
+            .await
+            .map_err(|e| format!("h3 client init failed: {e:?}"))?;
+
+        let uri: http::Uri = format!("https://{}{}", host, probe_target().to_path())
+            .parse()
+            .map_err(|e| format!("invalid uri: {e}"))?;
+
+        let mut req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", auth_token))
+            .header(DIAGNOSTIC_HEADER, "1")
+            .body(())
+            .map_err(|e| format!("request build failed: {e}"))?;
+        req.extensions_mut().insert(Protocol::CONNECT_UDP);
+
+        let mut stream = tokio::time::timeout(request_timeout, sender.send_request(req))
+            .await
+            .map_err(|_| "h3 send_request timed out".to_string())?
+            .map_err(|e| format!("h3 send_request failed: {e:?}"))?;
+
+        let resp = tokio::time::timeout(request_timeout, stream.recv_response())
+            .await
+            .map_err(|_| "h3 recv_response timed out".to_string())?
+            .map_err(|e| format!("h3 recv_response failed: {e:?}"))?;
+
+        // Close stream and connection.
+        let _ = stream.finish().await;
+        let _ = h3_conn.shutdown(0).await;
+        let _ = h3_conn.wait_idle().await;
+        endpoint.wait_idle().await;
+
+        if resp.status() == http::StatusCode::OK {
+            Ok(())
+        } else if resp.status() == http::StatusCode::UNAUTHORIZED {
+            Err("connect-udp unauthorized".to_string())
+        } else {
+            Err(format!("connect-udp unexpected status: {}", resp.status()))
+        }
+    })
+}
+
+fn connect_udp_datagram_echo_check(
+    rt: &tokio::runtime::Runtime,
+    host: &str,
+    port: u16,
+    server_name: &str,
+    tls: TlsVerify<'_>,
+    auth_token: Option<&str>,
+) -> Result<(), String> {
+    let addr = format!("{}:{}", host, port);
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve {} failed: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("resolve {} returned no addresses", addr))?;
+
+    let auth_token =
+        auth_token.ok_or_else(|| "missing auth_token for token verification".to_string())?;
+
+    let mut crypto = tls.client_config()?;
+    crypto.alpn_protocols = vec![H3_ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(crypto)
+        .map_err(|e| format!("quic client config failed: {}", e))?;
+
+    let connect_timeout = Duration::from_millis(1200);
+    let request_timeout = Duration::from_millis(1200);
+    let datagram_timeout = Duration::from_millis(1200);
+
+    rt.block_on(async move {
+        let mut client_config = ClientConfig::new(Arc::new(crypto));
+        client_config.transport_config(Arc::new(quinn::TransportConfig::default()));
+
+        let bind_addr = "0.0.0.0:0"
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| e.to_string())?;
+        let mut endpoint =
+            Endpoint::client(bind_addr).map_err(|e| format!("quic client setup failed: {}", e))?;
+        endpoint.set_default_client_config(client_config);
+
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(|e| format!("quic connect setup failed: {}", e))?;
+        let connection = tokio::time::timeout(connect_timeout, connecting)
+            .await
+            .map_err(|_| "quic connect timed out".to_string())?
+            .map_err(|e| format!("quic connect failed: {}", e))?;
+
+        let is_h3 = negotiated_alpn(&connection).as_deref() == Some(H3_ALPN);
+        if !is_h3 {
+            connection.close(0u32.into(), b"no-h3");
+            endpoint.wait_idle().await;
+            return Err("gateway did not negotiate ALPN h3".to_string());
+        }
+
+        let quinn_conn = h3_quinn::Connection::new(connection);
+        let (mut h3_conn, mut sender) = h3::client::builder()
+            .enable_extended_connect(true)
+            .enable_datagram(true)
+            .build::<_, _, Bytes>(quinn_conn)
+            .await
+            .map_err(|e| format!("h3 client init failed: {e:?}"))?;
+
+        let uri: http::Uri = format!("https://{}{}", host, probe_target().to_path())
+            .parse()
+            .map_err(|e| format!("invalid uri: {e}"))?;
+
+        let mut req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", auth_token))
+            .header(DIAGNOSTIC_HEADER, "1")
+            .body(())
+            .map_err(|e| format!("request build failed: {e}"))?;
+        req.extensions_mut().insert(Protocol::CONNECT_UDP);
+
+        let mut stream = tokio::time::timeout(request_timeout, sender.send_request(req))
+            .await
+            .map_err(|_| "h3 send_request timed out".to_string())?
+            .map_err(|e| format!("h3 send_request failed: {e:?}"))?;
+
+        let resp = tokio::time::timeout(request_timeout, stream.recv_response())
+            .await
+            .map_err(|_| "h3 recv_response timed out".to_string())?
+            .map_err(|e| format!("h3 recv_response failed: {e:?}"))?;
+
+        if resp.status() != http::StatusCode::OK {
+            let _ = stream.finish().await;
+            let _ = h3_conn.shutdown(0).await;
+            let _ = h3_conn.wait_idle().await;
+            endpoint.wait_idle().await;
+            return Err(format!("connect-udp unexpected status: {}", resp.status()));
+        }
+
+        let stream_id = stream.id();
+        let mut dg_sender = h3_conn.get_datagram_sender(stream_id);
+        let mut dg_reader = h3_conn.get_datagram_reader();
+
+        // For CONNECT-UDP, datagram payload is: varint(context_id) || payload.
+        // Context ID 0 encodes to a single 0x00 byte.
+        let probe = Bytes::from_static(b"\x00toppy-connect-udp-echo");
+        dg_sender
+            .send_datagram(probe.clone())
+            .map_err(|e| format!("send datagram failed: {e}"))?;
+
+        let echoed = tokio::time::timeout(datagram_timeout, async {
+            loop {
+                let dg = dg_reader
+                    .read_datagram()
+                    .await
+                    .map_err(|e| format!("read datagram failed: {e:?}"))?;
+                if dg.stream_id() != stream_id {
+                    continue;
+                }
+                let mut payload = dg.into_payload();
+                let bytes = payload.copy_to_bytes(payload.remaining());
+                return Ok::<Bytes, String>(bytes);
+            }
+        })
+        .await
+        .map_err(|_| "datagram echo timed out".to_string())??;
+
+        let _ = stream.finish().await;
+        let _ = h3_conn.shutdown(0).await;
+        let _ = h3_conn.wait_idle().await;
+        endpoint.wait_idle().await;
+
+        if echoed == probe {
+            Ok(())
+        } else {
+            Err("datagram echo mismatch".to_string())
+        }
+    })
+}
+
+/// QUIC/TLS checks against one gateway endpoint whose name resolved.
+pub(super) fn endpoint_probes(cfg: &config::Config, host: &str, port: u16) -> Vec<DoctorCheck> {
+    let server_name = cfg.server_name.as_deref().unwrap_or(host);
+    let mut checks = Vec::new();
+    match doctor_runtime() {
+        Err(e) => checks.extend(runtime_unavailable_checks(&e)),
+        Ok(rt) => {
+            let tls = TlsVerify::from_config(cfg);
+            match quic_ping_check(
+                &rt,
+                host,
+                port,
+                server_name,
+                tls,
+                cfg.auth_token.as_deref(),
+                PING_REPLY_LIMIT,
+            ) {
+                Ok(ping) => checks.push(mk(
+                    "h3.connect",
+                    "pass",
+                    format!(
+                        "quic ping ok {}:{} (protocol v{}, alpn {})",
+                        host,
+                        port,
+                        ping.version,
+                        alpn_label(ping.alpn.as_deref())
+                    ),
+                )),
+                Err(e) => checks.push(mk("h3.connect", "fail", e)),
+            }
+
+            checks.push(alpn_check(
+                &[H3_ALPN],
+                alpn_probe(&rt, host, port, server_name, tls, &[H3_ALPN]),
+            ));
+            checks.push(zero_rtt_check(zero_rtt_probe(
+                &rt,
+                host,
+                port,
+                server_name,
+                tls,
+            )));
+            checks.push(sni_match_check(
+                server_name,
+                peer_cert_probe(&rt, host, port, server_name),
+            ));
+
+            match connect_udp_handshake_check(
+                &rt,
+                host,
+                port,
+                server_name,
+                tls,
+                cfg.auth_token.as_deref(),
+            ) {
+                Ok(()) => checks.push(mk(
+                    "masque.connect_udp",
+                    "pass",
+                    format!("connect-udp handshake ok {}:{}", host, port),
+                )),
+                Err(e) => checks.push(mk("masque.connect_udp", "fail", e)),
+            }
+
+            match connect_udp_datagram_echo_check(
+                &rt,
+                host,
+                port,
+                server_name,
+                tls,
+                cfg.auth_token.as_deref(),
+            ) {
+                Ok(()) => checks.push(mk(
+                    "masque.connect_udp.datagram",
+                    "pass",
+                    format!("connect-udp datagram echo ok {}:{}", host, port),
+                )),
+                Err(e) => checks.push(mk("masque.connect_udp.datagram", "fail", e)),
+            }
+            for check in &mut checks {
+                mark_unverified(check, tls);
+            }
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeGateway, FakeResponse};
+
+    #[test]
+    fn quic_ping_check_passes_against_fake_gateway() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::Pong).expect("fake gateway");
+        let ping = quic_ping_check(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            TlsVerify::Ca(gw.ca_path.to_str()),
+            Some("dev-token"),
+            PING_REPLY_LIMIT,
+        )
+        .expect("ping");
+        assert_eq!(
+            ping,
+            QuicPing {
+                version: toppy_proto::PROTOCOL_VERSION_MAX,
+                alpn: None,
+            }
+        );
+    }
+
+    #[test]
+    fn quic_ping_check_names_oversize_reply() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::Raw(&[b'x'; 20])).expect("fake gateway");
+        let err = quic_ping_check(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            TlsVerify::Ca(gw.ca_path.to_str()),
+            Some("dev-token"),
+            16,
+        )
+        .unwrap_err();
+        assert_eq!(err, "response too large (>16 bytes)");
+    }
+
+    #[test]
+    fn insecure_skip_verify_passes_self_signed_gateway_with_warn() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::Pong).expect("fake gateway");
+        let tls = TlsVerify::InsecureSkipVerify;
+        let ping = quic_ping_check(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            tls,
+            Some("dev-token"),
+            PING_REPLY_LIMIT,
+        )
+        .expect("ping without a CA");
+        assert_eq!(ping.version, toppy_proto::PROTOCOL_VERSION_MAX);
+
+        let mut check = mk("h3.connect", "pass", "quic ping ok");
+        mark_unverified(&mut check, tls);
+        assert_eq!(check.status, "warn");
+        assert!(check.summary.starts_with("TLS verification disabled"));
+
+        let mut check = mk("h3.connect", "pass", "quic ping ok");
+        mark_unverified(&mut check, TlsVerify::Ca(None));
+        assert_eq!(check.status, "pass");
+        assert!(quic_ping_check(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            TlsVerify::Ca(None),
+            Some("dev-token"),
+            PING_REPLY_LIMIT,
+        )
+        .unwrap_err()
+        .contains("missing ca_cert_path"));
+    }
+
+    #[test]
+    fn alpn_check_reports_offered_and_negotiated() {
+        let check = alpn_check(&[H3_ALPN], Ok(Some(b"h3".to_vec())));
+        assert_eq!(check.status, "pass");
+        assert_eq!(check.summary, "offered h3; negotiated h3");
+
+        let check = alpn_check(&[H3_ALPN], Ok(None));
+        assert_eq!(check.status, "warn");
+        assert!(
+            check.summary.contains("negotiated none"),
+            "{}",
+            check.summary
+        );
+
+        let check = alpn_check(&[H3_ALPN], Ok(Some(b"toppy".to_vec())));
+        assert_eq!(check.status, "fail");
+        assert!(
+            check.summary.contains("unoffered toppy"),
+            "{}",
+            check.summary
+        );
+
+        let check = alpn_check(&[H3_ALPN], Err("quic connect timed out".to_string()));
+        assert_eq!(
+            (check.status.as_str(), check.summary.as_str()),
+            ("fail", "quic connect timed out")
+        );
+    }
+
+    #[test]
+    fn zero_rtt_check_reports_acceptance() {
+        let rt = doctor_runtime().expect("runtime");
+        let probe = |gw: &FakeGateway| {
+            zero_rtt_probe(
+                &rt,
+                "127.0.0.1",
+                gw.addr.port(),
+                "localhost",
+                TlsVerify::Ca(gw.ca_path.to_str()),
+            )
+        };
+        let gw = FakeGateway::start_0rtt(FakeResponse::Pong).expect("fake gateway");
+        let check = zero_rtt_check(probe(&gw));
+        assert_eq!(
+            (check.id.as_str(), check.status.as_str()),
+            ("tls.0rtt", "pass")
+        );
+        assert!(
+            check.summary.contains("accepted on resumption"),
+            "{}",
+            check.summary
+        );
+
+        let gw = FakeGateway::start(FakeResponse::Pong).expect("fake gateway");
+        let check = zero_rtt_check(probe(&gw));
+        assert_eq!(check.status, "pass");
+        assert!(check.summary.contains("not accepted"), "{}", check.summary);
+
+        let check = zero_rtt_check(Err("quic connect timed out".to_string()));
+        assert_eq!(check.status, "fail");
+    }
+
+    #[test]
+    fn alpn_probe_returns_h3_from_fake_gateway() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::H3Status(200)).expect("fake gateway");
+        let alpn = alpn_probe(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            TlsVerify::Ca(gw.ca_path.to_str()),
+            &[H3_ALPN],
+        )
+        .expect("probe");
+        assert_eq!(alpn.as_deref(), Some(H3_ALPN));
+    }
+
+    #[test]
+    fn cert_covers_name_matches_sans() {
+        let cert = rcgen::generate_simple_self_signed(vec![
+            "gw.example.com".to_string(),
+            "*.edge.example.com".to_string(),
+            "192.0.2.10".to_string(),
+        ])
+        .expect("cert")
+        .cert
+        .der()
+        .clone();
+        for name in ["gw.example.com", "a.edge.example.com", "192.0.2.10"] {
+            assert_eq!(cert_covers_name(&cert, name), Ok(true), "{}", name);
+        }
+        for name in ["other.example.com", "a.b.edge.example.com", "192.0.2.11"] {
+            assert_eq!(cert_covers_name(&cert, name), Ok(false), "{}", name);
+        }
+
+        let check = sni_match_check("other.example.com", Ok(cert.clone()));
+        assert_eq!(check.status, "warn");
+        assert!(
+            check
+                .summary
+                .contains("DNS SANs: gw.example.com, *.edge.example.com"),
+            "{}",
+            check.summary
+        );
+        assert_eq!(sni_match_check("gw.example.com", Ok(cert)).status, "pass");
+    }
+
+    #[test]
+    fn sni_match_check_probes_fake_gateway() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::Pong).expect("fake gateway");
+        let check = |name: &str| {
+            sni_match_check(
+                name,
+                peer_cert_probe(&rt, "127.0.0.1", gw.addr.port(), name),
+            )
+        };
+        assert_eq!(check("localhost").status, "pass");
+        let mismatch = check("gw.example.com");
+        assert_eq!(mismatch.status, "warn");
+        assert!(
+            mismatch.summary.contains("DNS SANs: localhost"),
+            "{}",
+            mismatch.summary
+        );
+    }
+
+    #[test]
+    fn quic_ping_check_reports_rejected_token() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::Unauthorized).expect("fake gateway");
+        let err = quic_ping_check(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            TlsVerify::Ca(gw.ca_path.to_str()),
+            Some("bad-token"),
+            PING_REPLY_LIMIT,
+        )
+        .unwrap_err();
+        assert_eq!(err, "token rejected by gateway: token expired");
+        assert_eq!(
+            ping_rejection(ControlMessage::unauthorized()),
+            "token rejected by gateway: unauthorized"
+        );
+        assert_eq!(
+            ping_rejection(ControlMessage::rate_limited()),
+            "ping refused by gateway: rate limited (code 4)"
+        );
+    }
+
+    #[test]
+    fn connect_udp_handshake_check_passes_against_fake_gateway() {
+        let rt = doctor_runtime().expect("runtime");
+        let gw = FakeGateway::start(FakeResponse::H3Status(200)).expect("fake gateway");
+        connect_udp_handshake_check(
+            &rt,
+            "127.0.0.1",
+            gw.addr.port(),
+            "localhost",
+            TlsVerify::Ca(gw.ca_path.to_str()),
+            Some("dev-token"),
+        )
+        .expect("handshake");
+    }
+
+    #[test]
+    fn runtime_init_failure_is_reported_as_sys_runtime() {
+        let checks = runtime_unavailable_checks("tokio init failed: Too many open files");
+        assert_eq!(checks[0].id, "sys.runtime");
+        assert_eq!(checks[0].status, "warn");
+        assert!(checks[0].summary.contains("Too many open files"));
+        for check in &checks[1..] {
+            assert_eq!(check.status, "warn", "{}", check.id);
+            assert_eq!(check.summary, "skipped because sys.runtime failed");
+        }
+        let ids: Vec<&str> = checks[1..].iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, RUNTIME_CHECK_IDS);
+        assert!(!checks.iter().any(|c| c.status == "fail"));
+    }
+}
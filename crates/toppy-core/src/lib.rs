@@ -10,9 +10,15 @@ pub fn greeting() -> &'static str {
 }
 
 pub mod audit;
+#[cfg(feature = "ring")]
 pub mod auth;
+pub mod bench;
 pub mod config;
 pub mod doctor;
+pub mod logging;
+pub mod net;
 pub mod policy;
 pub mod rate;
+#[cfg(any(not(feature = "ring"), test))]
+mod sha256;
 pub mod test_support;
@@ -0,0 +1,242 @@
+//! Log level/format shared by the CLI and gateway.
+//!
+//! `init` picks the settings from `Config` (`log_level`, `log_format`), with
+//! `TOPPY_LOG` and `TOPPY_LOG_FORMAT` taking precedence. Errors and warnings
+//! go to stderr, everything else to stdout.
+
+use crate::config::Config;
+use serde::Deserialize;
+use std::env;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("invalid log level {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogSettings {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogSettings {
+    /// Resolves settings from `cfg`, overridden by `TOPPY_LOG`/`TOPPY_LOG_FORMAT`.
+    pub fn resolve(cfg: &Config) -> Result<Self, String> {
+        let level = match env::var("TOPPY_LOG") {
+            Ok(value) => value.parse()?,
+            Err(_) => cfg.log_level.unwrap_or(LogLevel::Info),
+        };
+        let format = match env::var("TOPPY_LOG_FORMAT") {
+            Ok(value) => value.parse()?,
+            Err(_) => cfg.log_format.unwrap_or_default(),
+        };
+        Ok(Self { level, format })
+    }
+
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level <= self.level
+    }
+
+    /// Renders one log line (without the trailing newline).
+    pub fn format_line(&self, level: LogLevel, message: &str, unix_ms: u64) -> String {
+        self.format_line_with(level, message, &[], unix_ms)
+    }
+
+    /// Like [`format_line`](Self::format_line) with extra `key=value` context:
+    /// a text prefix, or top-level keys in JSON.
+    pub fn format_line_with(
+        &self,
+        level: LogLevel,
+        message: &str,
+        fields: &[(&str, &str)],
+        unix_ms: u64,
+    ) -> String {
+        match self.format {
+            LogFormat::Text => {
+                let mut line = String::new();
+                for (key, value) in fields {
+                    line.push_str(&format!("{}={} ", key, value));
+                }
+                line.push_str(message);
+                line
+            }
+            LogFormat::Json => {
+                let mut line = serde_json::json!({
+                    "unix_ms": unix_ms,
+                    "level": level.as_str(),
+                    "message": message,
+                });
+                for (key, value) in fields {
+                    line[*key] = serde_json::Value::from(*value);
+                }
+                line.to_string()
+            }
+        }
+    }
+}
+
+static SETTINGS: OnceLock<LogSettings> = OnceLock::new();
+
+/// Installs process-wide settings; later calls keep the first settings.
+pub fn init(cfg: &Config) -> Result<LogSettings, String> {
+    let settings = LogSettings::resolve(cfg)?;
+    Ok(*SETTINGS.get_or_init(|| settings))
+}
+
+fn settings() -> LogSettings {
+    SETTINGS.get().copied().unwrap_or_default()
+}
+
+pub fn log(level: LogLevel, message: &str) {
+    log_with(level, message, &[]);
+}
+
+/// Logs with extra context fields (see [`LogSettings::format_line_with`]).
+pub fn log_with(level: LogLevel, message: &str, fields: &[(&str, &str)]) {
+    let settings = settings();
+    if !settings.enabled(level) {
+        return;
+    }
+    let unix_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let line = settings.format_line_with(level, message, fields, unix_ms);
+    if level <= LogLevel::Warn {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scoped_env;
+
+    #[test]
+    fn resolve_selects_json_from_config() {
+        let _env = scoped_env(&[("TOPPY_LOG", None), ("TOPPY_LOG_FORMAT", None)]);
+        let cfg = Config {
+            log_level: Some(LogLevel::Warn),
+            log_format: Some(LogFormat::Json),
+            ..Default::default()
+        };
+        let settings = LogSettings::resolve(&cfg).expect("settings");
+        assert_eq!(settings.format, LogFormat::Json);
+        assert!(settings.enabled(LogLevel::Error));
+        assert!(!settings.enabled(LogLevel::Info));
+
+        let line: serde_json::Value =
+            serde_json::from_str(&settings.format_line(LogLevel::Warn, "hi", 7)).expect("json");
+        assert_eq!(
+            line,
+            serde_json::json!({"unix_ms": 7, "level": "warn", "message": "hi"})
+        );
+    }
+
+    #[test]
+    fn format_line_with_includes_fields() {
+        let text = LogSettings::default();
+        assert_eq!(
+            text.format_line_with(LogLevel::Info, "hi", &[("conn", "ab12")], 7),
+            "conn=ab12 hi"
+        );
+
+        let json = LogSettings {
+            format: LogFormat::Json,
+            ..LogSettings::default()
+        };
+        let line: serde_json::Value = serde_json::from_str(&json.format_line_with(
+            LogLevel::Info,
+            "hi",
+            &[("conn", "ab12")],
+            7,
+        ))
+        .expect("json");
+        assert_eq!(line["conn"], "ab12");
+        assert_eq!(line["message"], "hi");
+    }
+
+    #[test]
+    fn env_overrides_config() {
+        let _env = scoped_env(&[
+            ("TOPPY_LOG", Some("debug")),
+            ("TOPPY_LOG_FORMAT", Some("text")),
+        ]);
+        let cfg = Config {
+            log_level: Some(LogLevel::Error),
+            log_format: Some(LogFormat::Json),
+            ..Default::default()
+        };
+        let settings = LogSettings::resolve(&cfg).expect("settings");
+        assert_eq!(
+            settings,
+            LogSettings {
+                level: LogLevel::Debug,
+                format: LogFormat::Text,
+            }
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Address resolution combined with policy evaluation.
+
+use crate::policy::{Decision, Policy, Target};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+/// Well-known service names accepted in place of a port number.
+const SERVICE_PORTS: &[(&str, u16)] = &[
+    ("ssh", 22),
+    ("smtp", 25),
+    ("dns", 53),
+    ("domain", 53),
+    ("http", 80),
+    ("ntp", 123),
+    ("https", 443),
+    ("ldap", 389),
+    ("ldaps", 636),
+    ("mysql", 3306),
+    ("rdp", 3389),
+    ("postgres", 5432),
+    ("postgresql", 5432),
+    ("redis", 6379),
+];
+
+/// Parses a port number or a well-known service name (`ssh`, `https`, ...).
+pub fn resolve_port(name_or_num: &str) -> Result<u16, String> {
+    if let Ok(port) = name_or_num.parse::<u16>() {
+        return Ok(port);
+    }
+    if name_or_num.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("port {} out of range", name_or_num));
+    }
+    SERVICE_PORTS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(name_or_num))
+        .map(|(_, port)| *port)
+        .ok_or_else(|| format!("unknown port name {}", name_or_num))
+}
+
+/// Splits `host:port`, accepting bracketed IPv6 hosts (`[::1]:22`) and
+/// service names for the port (`host:ssh`, see [`resolve_port`]).
+pub fn split_host_port(value: &str) -> Result<(String, u16), String> {
+    if let Ok(target) = Target::parse_combined(value) {
+        return Ok((target.ip.to_string(), target.port));
+    }
+    let (host, port) = value
+        .rsplit_once(':')
+        .ok_or_else(|| format!("invalid target {}: missing port", value))?;
+    let host = match host.strip_prefix('[') {
+        Some(inner) => inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("invalid target {}: unclosed bracket", value))?,
+        None if host.contains(':') => {
+            return Err(format!(
+                "invalid target {}: IPv6 hosts must be bracketed",
+                value
+            ))
+        }
+        None => host,
+    };
+    if host.is_empty() {
+        return Err(format!("invalid target {}: missing host", value));
+    }
+    let port = resolve_port(port).map_err(|e| format!("invalid target {}: {}", value, e))?;
+    Ok((host.to_string(), port))
+}
+
+/// Resolves `host:port` and keeps only the addresses `policy` allows.
+///
+/// Errors if resolution fails or every resolved address is denied; the
+/// error then carries the policy's deny reason.
+pub fn resolve_allowed(host: &str, port: u16, policy: &Policy) -> Result<Vec<SocketAddr>, String> {
+    resolve_allowed_rules(host, port, policy)
+        .map(|allowed| allowed.into_iter().map(|(addr, _)| addr).collect())
+}
+
+/// Like [`resolve_allowed`], pairing each address with the index of the
+/// allow rule that matched it (see [`Decision::Allow`]).
+pub fn resolve_allowed_rules(
+    host: &str,
+    port: u16,
+    policy: &Policy,
+) -> Result<Vec<(SocketAddr, Option<usize>)>, String> {
+    let resolved: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("dns resolution failed for {}:{}: {}", host, port, e))?
+        .collect();
+    if resolved.is_empty() {
+        return Err(format!(
+            "dns resolution returned no addresses for {}:{}",
+            host, port
+        ));
+    }
+
+    let total = resolved.len();
+    let mut first_reason = None;
+    let allowed: Vec<(SocketAddr, Option<usize>)> = resolved
+        .into_iter()
+        .filter_map(|addr| match policy.evaluate(&named_target(host, addr)) {
+            Decision::Allow { rule_index } => Some((addr, rule_index)),
+            Decision::Deny { reason } => {
+                first_reason.get_or_insert(reason);
+                None
+            }
+        })
+        .collect();
+
+    if allowed.is_empty() {
+        let reason = first_reason.unwrap_or_default();
+        return Err(if total == 1 {
+            reason
+        } else {
+            format!(
+                "none of {} addresses for {}:{} allowed ({})",
+                total, host, port, reason
+            )
+        });
+    }
+    Ok(allowed)
+}
+
+/// `addr` tagged with `host` unless `host` was already an IP literal.
+fn named_target(host: &str, addr: SocketAddr) -> Target {
+    let target = Target::from(addr);
+    match host.parse::<IpAddr>() {
+        Ok(_) => target,
+        Err(_) => target.with_hostname(host),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyRule;
+
+    fn loopback_ssh_policy() -> Policy {
+        Policy {
+            allow: vec![PolicyRule::parse("127.0.0.1/32", vec![22]).expect("rule")],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        }
+    }
+
+    #[test]
+    fn resolve_allowed_keeps_allowed_address() {
+        let addrs = resolve_allowed("127.0.0.1", 22, &loopback_ssh_policy()).expect("allowed");
+        assert_eq!(addrs, vec!["127.0.0.1:22".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn resolve_allowed_rules_reports_matching_rule() {
+        let allowed =
+            resolve_allowed_rules("127.0.0.1", 22, &loopback_ssh_policy()).expect("allowed");
+        assert_eq!(
+            allowed,
+            vec![("127.0.0.1:22".parse::<SocketAddr>().unwrap(), Some(0))]
+        );
+    }
+
+    #[test]
+    fn resolve_allowed_rejects_denied_address() {
+        let err = resolve_allowed("127.0.0.1", 23, &loopback_ssh_policy()).unwrap_err();
+        assert!(err.contains("not allowed"));
+    }
+
+    #[test]
+    fn resolve_allowed_matches_host_rules_by_name() {
+        let policy = Policy {
+            allow: vec![PolicyRule::parse_with_host("", Some("localhost"), vec![22]).expect("rule")],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let addrs = resolve_allowed("localhost", 22, &policy).expect("allowed");
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+        assert!(resolve_allowed("127.0.0.1", 22, &policy).is_err());
+    }
+
+    #[test]
+    fn split_host_port_handles_ipv6_and_names() {
+        assert_eq!(
+            split_host_port("[::1]:22").unwrap(),
+            ("::1".to_string(), 22)
+        );
+        assert_eq!(
+            split_host_port("example.com:443").unwrap(),
+            ("example.com".to_string(), 443)
+        );
+        assert_eq!(
+            split_host_port("example.com:ssh").unwrap(),
+            ("example.com".to_string(), 22)
+        );
+        assert!(split_host_port("::1:22").is_err());
+        assert!(split_host_port("example.com").is_err());
+    }
+
+    #[test]
+    fn resolve_port_accepts_numbers_and_names() {
+        assert_eq!(resolve_port("ssh"), Ok(22));
+        assert_eq!(resolve_port("HTTPS"), Ok(443));
+        assert_eq!(resolve_port("443"), Ok(443));
+        assert!(resolve_port("70000").unwrap_err().contains("out of range"));
+        assert_eq!(
+            resolve_port("gopher-ish"),
+            Err("unknown port name gopher-ish".to_string())
+        );
+    }
+}
@@ -1,49 +1,289 @@
 use ipnet::IpNet;
-use serde::Deserialize;
-use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PolicyConfig {
+    // Plain values come first: TOML cannot write them after the rule tables.
+    /// Let diagnostic traffic (echo/heartbeat and doctor probes) bypass the
+    /// allow rules (deny rules still apply); it is still authenticated.
+    #[serde(default)]
+    pub allow_diagnostics: bool,
     pub allow: Vec<PolicyRuleConfig>,
+    /// Checked before `allow`; the first match denies the target.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<PolicyRuleConfig>,
+    /// Named port lists that rules can reference via `port_group`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub port_groups: BTreeMap<String, Vec<u16>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PolicyRuleConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub cidr: String,
+    /// Host name, or `*.suffix` for any name under `suffix`, matched against
+    /// the name a target was resolved from. A rule needs `cidr`, `host` or both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default)]
     pub ports: Vec<u16>,
+    /// Adds the ports of this `port_groups` entry to `ports`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_group: Option<String>,
+    /// Higher values are checked first (default 0). This only changes match
+    /// order, which cannot change the outcome of an allow-only policy.
+    #[serde(default)]
+    pub priority: i32,
+    /// Deny rules only: the reason reported when the rule matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Request headers the rule also requires, by name (case-insensitive) and
+    /// exact value, e.g. `{ x-tenant-id = "acme" }`. See [`Target::headers`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PolicyRule {
-    cidr: IpNet,
+    cidr: Option<IpNet>,
+    host: Option<HostPattern>,
     ports: Vec<u16>,
+    reason: Option<String>,
+    /// Lowercased names.
+    headers: BTreeMap<String, String>,
 }
 
 impl PolicyRule {
     pub fn parse(cidr: &str, ports: Vec<u16>) -> Result<Self, String> {
+        Self::parse_with_host(cidr, None, ports)
+    }
+
+    /// Like [`PolicyRule::parse`], also matching targets whose host name fits
+    /// `host` (see [`PolicyRuleConfig::host`]). `cidr` may then be empty.
+    pub fn parse_with_host(
+        cidr: &str,
+        host: Option<&str>,
+        ports: Vec<u16>,
+    ) -> Result<Self, String> {
         if ports.is_empty() {
             return Err("ports must not be empty".to_string());
         }
-        let cidr = cidr
-            .parse::<IpNet>()
-            .map_err(|e| format!("invalid cidr {}: {}", cidr, e))?;
-        Ok(Self { cidr, ports })
+        let host = host
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(HostPattern::parse)
+            .transpose()?;
+        let cidr = match cidr.trim() {
+            "" if host.is_none() => return Err("rule needs a cidr or a host".to_string()),
+            "" => None,
+            cidr => Some(
+                cidr.parse::<IpNet>()
+                    .map_err(|e| format!("invalid cidr {}: {}", cidr, e))?,
+            ),
+        };
+        Ok(Self {
+            cidr,
+            host,
+            ports,
+            reason: None,
+            headers: BTreeMap::new(),
+        })
+    }
+
+    /// Reason reported when this rule denies a target.
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// Also requires the target's request to carry each header with the given value.
+    pub fn with_headers(mut self, headers: &BTreeMap<String, String>) -> Result<Self, String> {
+        for (name, value) in headers {
+            let name = name.trim().to_ascii_lowercase();
+            if name.is_empty() {
+                return Err("header name must not be empty".to_string());
+            }
+            self.headers.insert(name, value.clone());
+        }
+        Ok(self)
     }
 
+    /// An IPv4-mapped IPv6 target (`::ffff:10.0.0.5`) is also checked as its
+    /// IPv4 address, so IPv4 rules cover it; IPv6 rules see the address as written.
     fn matches(&self, target: &Target) -> bool {
-        self.cidr.contains(&target.ip) && self.ports.contains(&target.port)
+        self.check(target) == RuleOutcome::Matched
+    }
+
+    fn check(&self, target: &Target) -> RuleOutcome {
+        let by_ip = self
+            .cidr
+            .is_some_and(|cidr| cidr.contains(&target.ip) || cidr.contains(&target.canonical_ip()));
+        let by_host = match (&self.host, &target.hostname) {
+            (Some(pattern), Some(name)) => pattern.matches(name),
+            _ => false,
+        };
+        if !(by_ip || by_host) {
+            RuleOutcome::CidrMiss
+        } else if !self.ports.contains(&target.port) {
+            RuleOutcome::PortMiss
+        } else if self
+            .headers
+            .iter()
+            .any(|(name, value)| target.headers.get(name) != Some(value))
+        {
+            RuleOutcome::HeaderMiss
+        } else {
+            RuleOutcome::Matched
+        }
+    }
+
+    fn covers(&self, other: &PolicyRule) -> bool {
+        let cidr_covered = match (self.cidr, other.cidr) {
+            (_, None) => true,
+            (Some(cidr), Some(other)) => cidr.contains(&other),
+            (None, Some(_)) => false,
+        };
+        let host_covered = match (&self.host, &other.host) {
+            (_, None) => true,
+            (Some(host), Some(other)) => host.covers(other),
+            (None, Some(_)) => false,
+        };
+        // Fewer required headers match more requests.
+        let headers_covered = self
+            .headers
+            .iter()
+            .all(|(name, value)| other.headers.get(name) == Some(value));
+        cidr_covered
+            && host_covered
+            && headers_covered
+            && other.ports.iter().all(|p| self.ports.contains(p))
+    }
+
+    fn overlaps(&self, other: &PolicyRule) -> bool {
+        // Prefixes either nest or are disjoint; so do host patterns.
+        let cidr_overlap = match (self.cidr, other.cidr) {
+            (Some(a), Some(b)) => a.contains(&b) || b.contains(&a),
+            _ => false,
+        };
+        let host_overlap = match (&self.host, &other.host) {
+            (Some(a), Some(b)) => a.covers(b) || b.covers(a),
+            _ => false,
+        };
+        let headers_compatible = self
+            .headers
+            .iter()
+            .all(|(name, value)| other.headers.get(name).is_none_or(|other| other == value));
+        (cidr_overlap || host_overlap)
+            && headers_compatible
+            && other.ports.iter().any(|p| self.ports.contains(p))
+    }
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.cidr, &self.host) {
+            (Some(cidr), Some(host)) => write!(f, "{} or host {}", cidr, host)?,
+            (Some(cidr), None) => write!(f, "{}", cidr)?,
+            (None, Some(host)) => write!(f, "host {}", host)?,
+            (None, None) => {}
+        }
+        write!(f, " ports {:?}", self.ports)?;
+        if !self.headers.is_empty() {
+            let headers: Vec<String> = self
+                .headers
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            write!(f, " headers [{}]", headers.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A host name, or `*.suffix` matching any name below `suffix`; compared
+/// case-insensitively and ignoring a trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HostPattern {
+    /// Lowercased name, without the `*.` of a wildcard.
+    name: String,
+    wildcard: bool,
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let normalized = pattern.trim_end_matches('.').to_ascii_lowercase();
+        let (name, wildcard) = match normalized.strip_prefix("*.") {
+            Some(suffix) => (suffix.to_string(), true),
+            None => (normalized, false),
+        };
+        if name.is_empty() || name.contains('*') {
+            return Err(format!(
+                "invalid host {}: expected a name or *.suffix",
+                pattern
+            ));
+        }
+        Ok(Self { name, wildcard })
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if !self.wildcard {
+            return host == self.name;
+        }
+        host.strip_suffix(&self.name)
+            .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.'))
+    }
+
+    /// Whether every name `other` matches is also matched by this pattern.
+    fn covers(&self, other: &HostPattern) -> bool {
+        match (self.wildcard, other.wildcard) {
+            (false, false) => self.name == other.name,
+            (false, true) => false,
+            (true, false) => self.matches(&other.name),
+            (true, true) => other.name == self.name || self.matches(&other.name),
+        }
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.wildcard {
+            f.write_str("*.")?;
+        }
+        f.write_str(&self.name)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Policy {
     pub allow: Vec<PolicyRule>,
+    /// Checked before `allow`, in order; see [`Policy::evaluate`].
+    pub deny: Vec<PolicyRule>,
+    /// See [`PolicyConfig::allow_diagnostics`].
+    pub allow_diagnostics: bool,
+}
+
+/// What a flow is for; see [`Policy::evaluate_for`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficKind {
+    /// User data, always subject to the allow rules.
+    User,
+    /// Echo/heartbeat and doctor probes.
+    Diagnostic,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Target {
     pub ip: IpAddr,
     pub port: u16,
+    /// Name `ip` was resolved from, checked against `host` rules.
+    pub hostname: Option<String>,
+    /// Request headers (lowercased names) checked against rule `headers`.
+    pub headers: BTreeMap<String, String>,
 }
 
 impl Target {
@@ -51,53 +291,897 @@ impl Target {
         let ip = ip
             .parse::<IpAddr>()
             .map_err(|e| format!("invalid ip {}: {}", ip, e))?;
-        Ok(Self { ip, port })
+        Ok(Self {
+            ip,
+            port,
+            hostname: None,
+            headers: BTreeMap::new(),
+        })
+    }
+
+    /// Parses `ip:port` or `[ipv6]:port`; host names are not accepted.
+    pub fn parse_combined(value: &str) -> Result<Self, String> {
+        if let Ok(addr) = value.parse::<SocketAddr>() {
+            return Ok(addr.into());
+        }
+        // SocketAddr only says "invalid socket address"; find out what is wrong.
+        let (ip, port) = value
+            .rsplit_once(':')
+            .ok_or_else(|| format!("invalid target {}: missing port", value))?;
+        let ip = match ip.strip_prefix('[') {
+            Some(inner) => inner
+                .strip_suffix(']')
+                .ok_or_else(|| format!("invalid target {}: unclosed bracket", value))?,
+            None if ip.contains(':') => {
+                return Err(format!(
+                    "invalid target {}: IPv6 addresses must be bracketed",
+                    value
+                ))
+            }
+            None => ip,
+        };
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| format!("invalid target {}: bad port {:?}", value, port))?;
+        Self::parse(ip, port).map_err(|e| format!("invalid target {}: {}", value, e))
+    }
+
+    pub fn from_socket_addr(addr: SocketAddr) -> Self {
+        Self {
+            ip: addr.ip(),
+            port: addr.port(),
+            hostname: None,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Records the host name this target was resolved from.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Records the headers of the request for this target; names are lowercased.
+    pub fn with_headers(mut self, headers: BTreeMap<String, String>) -> Self {
+        self.headers = headers
+            .into_iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .collect();
+        self
+    }
+
+    /// `ip` with an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) turned back
+    /// into IPv4; any other address is returned unchanged.
+    pub fn canonical_ip(&self) -> IpAddr {
+        self.ip.to_canonical()
+    }
+
+    pub fn to_socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.port)
     }
 }
 
+impl From<SocketAddr> for Target {
+    fn from(addr: SocketAddr) -> Self {
+        Self::from_socket_addr(addr)
+    }
+}
+
+/// Formats as canonical `ip:port`, bracketing IPv6 addresses (`[::1]:22`).
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_socket_addr())
+    }
+}
+
+/// Advisory finding from [`Policy::lint`]; never affects evaluation.
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyLint {
+    pub kind: PolicyLintKind,
+    /// Whether `rule` and `other` index [`Policy::deny`] rather than [`Policy::allow`].
+    pub deny: bool,
+    /// Index of the later rule.
+    pub rule: usize,
+    /// Index of the earlier rule it collides with.
+    pub other: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyLintKind {
+    /// Every target the rule matches is already matched by `other`.
+    Shadowed,
+    /// The rules share some targets.
+    Overlap,
+}
+
+/// Serializes as `{"decision":"allow","rule_index":0}` or
+/// `{"decision":"deny","reason":...}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "decision", rename_all = "lowercase")]
 pub enum Decision {
+    Allow {
+        /// Index into [`Policy::allow`] of the rule that matched; `None` when
+        /// no rule was needed, e.g. diagnostic traffic under `allow_diagnostics`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rule_index: Option<usize>,
+    },
+    Deny {
+        reason: String,
+    },
+}
+
+impl Decision {
+    /// QUIC close code and reason for a connection this decision refuses;
+    /// `None` when it is allowed.
+    pub fn to_close_reason(&self) -> Option<(u16, &str)> {
+        match self {
+            Decision::Allow { .. } => None,
+            Decision::Deny { reason } => Some((toppy_proto::error_code::FORBIDDEN, reason)),
+        }
+    }
+}
+
+/// HTTP status answering a request this decision applies to: 200 to
+/// proceed, 403 when denied.
+pub fn decision_to_http_status(decision: &Decision) -> http::StatusCode {
+    match decision {
+        Decision::Allow { .. } => http::StatusCode::OK,
+        Decision::Deny { .. } => http::StatusCode::FORBIDDEN,
+    }
+}
+
+/// Every rule [`Policy::explain`] checked, deny rules first, and the
+/// resulting decision. Displays one line per rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyExplanation {
+    pub target: Target,
+    pub rules: Vec<RuleTrace>,
+    /// Same as [`Policy::evaluate`] for `target`.
+    pub decision: Decision,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTrace {
+    pub kind: RuleKind,
+    /// Index into [`Policy::allow`] or [`Policy::deny`].
+    pub index: usize,
+    /// The rule as displayed, e.g. `10.0.0.0/8 ports [22]`.
+    pub rule: String,
+    pub outcome: RuleOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
     Allow,
-    Deny { reason: String },
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleOutcome {
+    Matched,
+    /// Neither the rule's CIDR nor its host covers the target.
+    CidrMiss,
+    /// The address matched but the port is not listed.
+    PortMiss,
+    /// Address and port matched but a required header is missing or differs.
+    HeaderMiss,
+}
+
+impl fmt::Display for PolicyExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target.hostname {
+            Some(name) => writeln!(f, "target {} ({})", self.target, name)?,
+            None => writeln!(f, "target {}", self.target)?,
+        }
+        for trace in &self.rules {
+            let kind = match trace.kind {
+                RuleKind::Allow => "allow",
+                RuleKind::Deny => "deny",
+            };
+            let outcome = match trace.outcome {
+                RuleOutcome::Matched => "matched",
+                RuleOutcome::CidrMiss => "cidr miss",
+                RuleOutcome::PortMiss => "port miss",
+                RuleOutcome::HeaderMiss => "header miss",
+            };
+            writeln!(
+                f,
+                "  {} rule {} ({}): {}",
+                kind, trace.index, trace.rule, outcome
+            )?;
+        }
+        match &self.decision {
+            Decision::Allow {
+                rule_index: Some(index),
+            } => write!(f, "decision: allow by rule {}", index),
+            Decision::Allow { rule_index: None } => write!(f, "decision: allow"),
+            Decision::Deny { reason } => write!(f, "decision: deny ({})", reason),
+        }
+    }
+}
+
+/// Aggregates from [`Policy::evaluate_batch`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub allowed: usize,
+    pub denied: usize,
+    /// Targets decided by each rule, indexed like [`Policy::allow`].
+    pub rule_hits: Vec<usize>,
 }
 
 impl Policy {
+    /// Builds the allow and deny rules, each in descending `priority` order;
+    /// ties keep file order.
     pub fn from_config(cfg: &PolicyConfig) -> Result<Self, String> {
-        let mut allow = Vec::with_capacity(cfg.allow.len());
-        for rule in &cfg.allow {
-            allow.push(PolicyRule::parse(&rule.cidr, rule.ports.clone())?);
+        if let Some(rule) = cfg.allow.iter().find(|rule| rule.reason.is_some()) {
+            let selector = rule.host.as_deref().unwrap_or(&rule.cidr);
+            return Err(format!(
+                "reason is only allowed on deny rules (allow rule {})",
+                selector
+            ));
         }
-        Ok(Self { allow })
+        Ok(Self {
+            allow: Self::rules_from_config(cfg, &cfg.allow)?,
+            deny: Self::rules_from_config(cfg, &cfg.deny)?,
+            allow_diagnostics: cfg.allow_diagnostics,
+        })
     }
 
-    pub fn evaluate(&self, target: &Target) -> Decision {
-        for rule in &self.allow {
-            if rule.matches(target) {
-                return Decision::Allow;
+    /// Allow plus deny rules.
+    pub fn rule_count(&self) -> usize {
+        self.allow.len() + self.deny.len()
+    }
+
+    fn rules_from_config(
+        cfg: &PolicyConfig,
+        rules: &[PolicyRuleConfig],
+    ) -> Result<Vec<PolicyRule>, String> {
+        let mut rules: Vec<&PolicyRuleConfig> = rules.iter().collect();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        let mut parsed = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let mut ports = rule.ports.clone();
+            if let Some(name) = &rule.port_group {
+                let group = cfg.port_groups.get(name).ok_or_else(|| {
+                    let selector = rule.host.as_deref().unwrap_or(&rule.cidr);
+                    format!("unknown port_group {} for rule {}", name, selector)
+                })?;
+                ports.extend(group.iter().filter(|p| !rule.ports.contains(p)));
+            }
+            let mut parsed_rule =
+                PolicyRule::parse_with_host(&rule.cidr, rule.host.as_deref(), ports)?
+                    .with_headers(&rule.headers)?;
+            parsed_rule.reason = rule.reason.clone();
+            parsed.push(parsed_rule);
+        }
+        Ok(parsed)
+    }
+
+    /// Config that builds this policy again. Port groups come back expanded
+    /// into `ports` and priorities as rule order (all `priority = 0`).
+    pub fn to_config(&self) -> PolicyConfig {
+        let rules = |rules: &[PolicyRule]| -> Vec<PolicyRuleConfig> {
+            rules
+                .iter()
+                .map(|rule| PolicyRuleConfig {
+                    cidr: rule.cidr.map(|cidr| cidr.to_string()).unwrap_or_default(),
+                    host: rule.host.as_ref().map(HostPattern::to_string),
+                    ports: rule.ports.clone(),
+                    port_group: None,
+                    priority: 0,
+                    reason: rule.reason.clone(),
+                    headers: rule.headers.clone(),
+                })
+                .collect()
+        };
+        PolicyConfig {
+            allow: rules(&self.allow),
+            deny: rules(&self.deny),
+            port_groups: BTreeMap::new(),
+            allow_diagnostics: self.allow_diagnostics,
+        }
+    }
+
+    /// Reports later rules that are shadowed by, or overlap with, earlier
+    /// ones of the same list, allow rules first.
+    pub fn lint(&self) -> Vec<PolicyLint> {
+        let mut lints = Vec::new();
+        Self::lint_rules(&self.allow, false, &mut lints);
+        Self::lint_rules(&self.deny, true, &mut lints);
+        lints
+    }
+
+    fn lint_rules(rules: &[PolicyRule], deny: bool, lints: &mut Vec<PolicyLint>) {
+        let label = if deny { "deny rule" } else { "rule" };
+        for (j, later) in rules.iter().enumerate() {
+            for (i, earlier) in rules[..j].iter().enumerate() {
+                let kind = if earlier.covers(later) {
+                    PolicyLintKind::Shadowed
+                } else if earlier.overlaps(later) {
+                    PolicyLintKind::Overlap
+                } else {
+                    continue;
+                };
+                let message = match kind {
+                    PolicyLintKind::Shadowed => format!(
+                        "{} {} ({}) is shadowed by {} {} ({})",
+                        label, j, later, label, i, earlier
+                    ),
+                    PolicyLintKind::Overlap => format!(
+                        "{} {} ({}) overlaps {} {} ({})",
+                        label, j, later, label, i, earlier
+                    ),
+                };
+                lints.push(PolicyLint {
+                    kind,
+                    deny,
+                    rule: j,
+                    other: i,
+                    message,
+                });
+                if kind == PolicyLintKind::Shadowed {
+                    break;
+                }
             }
         }
-        Decision::Deny {
-            reason: format!("target {}:{} not allowed", target.ip, target.port),
+    }
+
+    /// Deny rules take precedence: the first matching `deny` rule denies the
+    /// target with its `reason`, whatever the allow rules say. Otherwise the
+    /// target is allowed if any `allow` rule matches, and denied if none does.
+    pub fn evaluate(&self, target: &Target) -> Decision {
+        if let Some(reason) = self.deny_reason(target) {
+            return Decision::Deny { reason };
+        }
+        match self.matching_rule(target) {
+            Some(index) => Decision::Allow {
+                rule_index: Some(index),
+            },
+            None => Decision::Deny {
+                reason: format!("target {} not allowed", target),
+            },
+        }
+    }
+
+    /// Like [`Policy::evaluate`], but diagnostic traffic skips the allow rules
+    /// when `allow_diagnostics` is set. Deny rules still apply.
+    pub fn evaluate_for(&self, target: &Target, kind: TrafficKind) -> Decision {
+        if kind == TrafficKind::Diagnostic && self.allow_diagnostics {
+            return match self.deny_reason(target) {
+                Some(reason) => Decision::Deny { reason },
+                None => Decision::Allow { rule_index: None },
+            };
+        }
+        self.evaluate(target)
+    }
+
+    /// Evaluates every target in one pass, tallying decisions and the rule
+    /// that allowed each target.
+    pub fn evaluate_batch(&self, targets: &[Target]) -> (Vec<Decision>, BatchStats) {
+        let mut stats = BatchStats {
+            rule_hits: vec![0; self.allow.len()],
+            ..BatchStats::default()
+        };
+        let decisions = targets
+            .iter()
+            .map(|target| {
+                if let Some(reason) = self.deny_reason(target) {
+                    stats.denied += 1;
+                    return Decision::Deny { reason };
+                }
+                match self.matching_rule(target) {
+                    Some(index) => {
+                        stats.allowed += 1;
+                        stats.rule_hits[index] += 1;
+                        Decision::Allow {
+                            rule_index: Some(index),
+                        }
+                    }
+                    None => {
+                        stats.denied += 1;
+                        Decision::Deny {
+                            reason: format!("target {} not allowed", target),
+                        }
+                    }
+                }
+            })
+            .collect();
+        (decisions, stats)
+    }
+
+    /// Checks `target` against every deny and allow rule, not just up to the
+    /// first match, for dry runs such as `toppy policy test`.
+    pub fn explain(&self, target: &Target) -> PolicyExplanation {
+        let trace = |kind, rules: &[PolicyRule]| -> Vec<RuleTrace> {
+            rules
+                .iter()
+                .enumerate()
+                .map(|(index, rule)| RuleTrace {
+                    kind,
+                    index,
+                    rule: rule.to_string(),
+                    outcome: rule.check(target),
+                })
+                .collect()
+        };
+        let mut rules = trace(RuleKind::Deny, &self.deny);
+        rules.extend(trace(RuleKind::Allow, &self.allow));
+        PolicyExplanation {
+            target: target.clone(),
+            rules,
+            decision: self.evaluate(target),
         }
     }
+
+    /// `rule 0 (10.0.0.0/8 ports [22])` for each distinct index, in the order given.
+    pub fn describe_rules(&self, indices: impl IntoIterator<Item = usize>) -> String {
+        let mut seen = Vec::new();
+        for index in indices {
+            if !seen.contains(&index) {
+                seen.push(index);
+            }
+        }
+        let described: Vec<String> = seen
+            .into_iter()
+            .filter_map(|index| {
+                let rule = self.allow.get(index)?;
+                Some(format!("rule {} ({})", index, rule))
+            })
+            .collect();
+        described.join(", ")
+    }
+
+    /// Reason from the first deny rule matching `target`.
+    fn deny_reason(&self, target: &Target) -> Option<String> {
+        let rule = self.deny.iter().find(|rule| rule.matches(target))?;
+        Some(
+            rule.reason
+                .clone()
+                .unwrap_or_else(|| format!("target {} denied by rule {}", target, rule)),
+        )
+    }
+
+    /// Index of the first allow rule matching `target`.
+    fn matching_rule(&self, target: &Target) -> Option<usize> {
+        self.allow.iter().position(|rule| rule.matches(target))
+    }
+}
+
+/// Reads a standalone policy file: `.json` as JSON, anything else as TOML
+/// (`[[allow]]` tables).
+pub fn load_policy_config(path: &Path) -> Result<PolicyConfig, String> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("policy: failed to read {}: {}", path.display(), e))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    if is_json {
+        serde_json::from_str(&data)
+            .map_err(|e| format!("policy: failed to parse JSON {}: {}", path.display(), e))
+    } else {
+        toml::from_str(&data)
+            .map_err(|e| format!("policy: failed to parse TOML {}: {}", path.display(), e))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_policy_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("toppy-policy-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn to_config_round_trips_through_toml() {
+        let cfg = PolicyConfig {
+            allow: vec![
+                PolicyRuleConfig {
+                    cidr: "10.0.0.0/8".to_string(),
+                    ports: vec![22, 443],
+                    ..Default::default()
+                },
+                PolicyRuleConfig {
+                    cidr: "2001:db8::/32".to_string(),
+                    ports: vec![53],
+                    ..Default::default()
+                },
+            ],
+            port_groups: BTreeMap::new(),
+            deny: Vec::new(),
+            allow_diagnostics: true,
+        };
+        let policy = Policy::from_config(&cfg).expect("policy");
+        assert_eq!(policy.to_config(), cfg);
+
+        let toml = toml::to_string(&policy.to_config()).expect("serialize");
+        let reloaded: PolicyConfig = toml::from_str(&toml).expect("parse");
+        assert_eq!(Policy::from_config(&reloaded).expect("reload"), policy);
+
+        // Groups and priorities are folded into ports and rule order.
+        let grouped = PolicyConfig {
+            allow: vec![
+                PolicyRuleConfig {
+                    cidr: "192.0.2.0/24".to_string(),
+                    port_group: Some("web".to_string()),
+                    ..Default::default()
+                },
+                PolicyRuleConfig {
+                    cidr: "198.51.100.0/24".to_string(),
+                    ports: vec![22],
+                    priority: 5,
+                    ..Default::default()
+                },
+            ],
+            port_groups: BTreeMap::from([("web".to_string(), vec![80, 443])]),
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let policy = Policy::from_config(&grouped).expect("policy");
+        let flattened = policy.to_config();
+        assert_eq!(flattened.allow[0].cidr, "198.51.100.0/24");
+        assert_eq!(flattened.allow[1].ports, [80, 443]);
+        assert_eq!(Policy::from_config(&flattened).expect("reload"), policy);
+    }
 
     #[test]
     fn policy_allows_matching_target() {
         let rule = PolicyRule::parse("10.0.0.0/24", vec![22, 443]).expect("rule");
-        let policy = Policy { allow: vec![rule] };
+        let policy = Policy {
+            allow: vec![rule],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let target = Target::parse("10.0.0.5", 22).expect("target");
+        assert!(matches!(policy.evaluate(&target), Decision::Allow { .. }));
+    }
+
+    #[test]
+    fn allow_reports_first_matching_rule_among_overlaps() {
+        let policy = Policy {
+            allow: vec![
+                PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.0.0/8", vec![22, 443]).expect("rule"),
+                PolicyRule::parse("0.0.0.0/0", vec![443]).expect("rule"),
+            ],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let rule_for =
+            |ip: &str, port| match policy.evaluate(&Target::parse(ip, port).expect("target")) {
+                Decision::Allow { rule_index } => rule_index,
+                Decision::Deny { reason } => panic!("denied: {}", reason),
+            };
+        assert_eq!(rule_for("10.0.0.5", 22), Some(0));
+        assert_eq!(rule_for("10.1.0.5", 22), Some(1));
+        assert_eq!(rule_for("10.0.0.5", 443), Some(1));
+        assert_eq!(rule_for("192.0.2.1", 443), Some(2));
+        assert_eq!(policy.allow[1].to_string(), "10.0.0.0/8 ports [22, 443]");
+    }
+
+    #[test]
+    fn decisions_map_to_http_status_and_close_reason() {
+        let allow = Decision::Allow {
+            rule_index: Some(0),
+        };
+        let deny = Decision::Deny {
+            reason: "target 10.0.0.5:22 not allowed".to_string(),
+        };
+        assert_eq!(decision_to_http_status(&allow), http::StatusCode::OK);
+        assert_eq!(decision_to_http_status(&deny), http::StatusCode::FORBIDDEN);
+        assert_eq!(allow.to_close_reason(), None);
+        assert_eq!(
+            deny.to_close_reason(),
+            Some((
+                toppy_proto::error_code::FORBIDDEN,
+                "target 10.0.0.5:22 not allowed"
+            ))
+        );
+    }
+
+    #[test]
+    fn explain_traces_every_rule_with_the_reason_it_missed() {
+        let policy = Policy {
+            allow: vec![
+                PolicyRule::parse("192.168.0.0/16", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.0.0/8", vec![443]).expect("rule"),
+                PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule"),
+            ],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
         let target = Target::parse("10.0.0.5", 22).expect("target");
-        assert_eq!(policy.evaluate(&target), Decision::Allow);
+        let explanation = policy.explain(&target);
+
+        let outcomes: Vec<RuleOutcome> = explanation.rules.iter().map(|t| t.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                RuleOutcome::CidrMiss,
+                RuleOutcome::PortMiss,
+                RuleOutcome::Matched
+            ]
+        );
+        assert_eq!(explanation.decision, policy.evaluate(&target));
+        assert_eq!(
+            explanation.to_string(),
+            "target 10.0.0.5:22\n\
+             \x20 allow rule 0 (192.168.0.0/16 ports [22]): cidr miss\n\
+             \x20 allow rule 1 (10.0.0.0/8 ports [443]): port miss\n\
+             \x20 allow rule 2 (10.0.0.0/24 ports [22]): matched\n\
+             decision: allow by rule 2"
+        );
+    }
+
+    #[test]
+    fn header_rules_match_only_requests_carrying_the_headers() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+[[allow]]
+cidr = "10.0.0.0/8"
+ports = [22]
+headers = { X-Tenant-Id = "acme" }
+
+[[allow]]
+cidr = "10.0.0.0/8"
+ports = [22]
+"#,
+        )
+        .expect("policy toml");
+        let policy = Policy::from_config(&cfg).expect("policy");
+        let bare = Target::parse("10.0.0.5", 22).expect("target");
+        let tenant = |value: &str| {
+            bare.clone().with_headers(BTreeMap::from([(
+                "X-Tenant-Id".to_string(),
+                value.to_string(),
+            )]))
+        };
+
+        assert_eq!(
+            policy.evaluate(&tenant("acme")),
+            Decision::Allow {
+                rule_index: Some(0)
+            }
+        );
+        assert_eq!(
+            policy.evaluate(&tenant("other")),
+            Decision::Allow {
+                rule_index: Some(1)
+            }
+        );
+        assert_eq!(
+            policy.explain(&bare).rules[0].outcome,
+            RuleOutcome::HeaderMiss
+        );
+        assert_eq!(
+            policy.allow[0].to_string(),
+            "10.0.0.0/8 ports [22] headers [x-tenant-id=acme]"
+        );
+        let lints = policy.lint();
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].kind, PolicyLintKind::Overlap);
+        assert_eq!(Policy::from_config(&policy.to_config()), Ok(policy));
+    }
+
+    #[test]
+    fn evaluate_batch_tallies_decisions_and_rule_hits() {
+        let policy = Policy {
+            allow: vec![
+                PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.0.0/8", vec![22, 443]).expect("rule"),
+                PolicyRule::parse("192.168.0.0/16", vec![53]).expect("rule"),
+            ],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let targets = [
+            Target::parse("10.0.0.5", 22).expect("target"),
+            Target::parse("10.1.0.5", 22).expect("target"),
+            Target::parse("10.0.0.6", 443).expect("target"),
+            Target::parse("10.0.0.7", 80).expect("target"),
+            Target::parse("10.0.0.8", 22).expect("target"),
+        ];
+
+        let (decisions, stats) = policy.evaluate_batch(&targets);
+        let expected: Vec<Decision> = targets.iter().map(|t| policy.evaluate(t)).collect();
+        assert_eq!(decisions, expected);
+        assert!(matches!(decisions[3], Decision::Deny { .. }));
+        assert_eq!(
+            stats,
+            BatchStats {
+                allowed: 4,
+                denied: 1,
+                rule_hits: vec![2, 2, 0],
+            }
+        );
+    }
+
+    #[test]
+    fn diagnostic_traffic_is_exempt_only_when_allowed() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+allow_diagnostics = true
+
+[[allow]]
+cidr = "10.0.0.0/8"
+ports = [22]
+"#,
+        )
+        .expect("policy toml");
+        let mut policy = Policy::from_config(&cfg).expect("policy");
+        let probe = Target::parse("127.0.0.1", 9).expect("target");
+
+        assert_eq!(
+            policy.evaluate_for(&probe, TrafficKind::Diagnostic),
+            Decision::Allow { rule_index: None }
+        );
+        assert!(matches!(
+            policy.evaluate_for(&probe, TrafficKind::User),
+            Decision::Deny { .. }
+        ));
+
+        policy.allow_diagnostics = false;
+        assert!(matches!(
+            policy.evaluate_for(&probe, TrafficKind::Diagnostic),
+            Decision::Deny { .. }
+        ));
+    }
+
+    #[test]
+    fn host_rules_match_names_alongside_cidr_rules() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+[[allow]]
+host = "*.internal.example"
+ports = [22]
+
+[[allow]]
+cidr = "10.0.0.0/8"
+host = "db.example"
+ports = [5432]
+"#,
+        )
+        .expect("policy toml");
+        let policy = Policy::from_config(&cfg).expect("policy");
+        let ip = |ip: &str, port| Target::parse(ip, port).expect("target");
+
+        let named = ip("192.0.2.7", 22).with_hostname("web.Internal.Example.");
+        assert!(matches!(policy.evaluate(&named), Decision::Allow { .. }));
+        for denied in [
+            ip("192.0.2.7", 22),
+            ip("192.0.2.7", 22).with_hostname("internal.example"),
+            ip("192.0.2.7", 22).with_hostname("evilinternal.example"),
+            ip("192.0.2.7", 443).with_hostname("web.internal.example"),
+        ] {
+            assert!(
+                matches!(policy.evaluate(&denied), Decision::Deny { .. }),
+                "{:?}",
+                denied
+            );
+        }
+
+        // Either the CIDR or the host is enough.
+        assert!(matches!(
+            policy.evaluate(&ip("10.1.2.3", 5432)),
+            Decision::Allow { .. }
+        ));
+        let db = ip("192.0.2.9", 5432).with_hostname("db.example");
+        assert!(matches!(policy.evaluate(&db), Decision::Allow { .. }));
+
+        assert_eq!(
+            policy.to_config().allow[0].host.as_deref(),
+            Some("*.internal.example")
+        );
+        assert_eq!(
+            Policy::from_config(&policy.to_config()).expect("reload"),
+            policy
+        );
+    }
+
+    #[test]
+    fn deny_rules_take_precedence_over_allow_rules() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+allow_diagnostics = true
+
+[[allow]]
+cidr = "10.0.0.0/8"
+ports = [22, 443]
+
+[[deny]]
+cidr = "10.0.5.0/24"
+ports = [22]
+reason = "build hosts are off limits"
+
+[[deny]]
+cidr = "10.0.6.0/24"
+ports = [22]
+"#,
+        )
+        .expect("policy toml");
+        let policy = Policy::from_config(&cfg).expect("policy");
+        let target = |ip: &str, port| Target::parse(ip, port).expect("target");
+
+        assert!(matches!(
+            policy.evaluate(&target("10.0.4.1", 22)),
+            Decision::Allow { .. }
+        ));
+        assert_eq!(
+            policy.evaluate(&target("10.0.5.1", 22)),
+            Decision::Deny {
+                reason: "build hosts are off limits".to_string()
+            }
+        );
+        assert_eq!(
+            policy.evaluate(&target("10.0.6.1", 22)),
+            Decision::Deny {
+                reason: "target 10.0.6.1:22 denied by rule 10.0.6.0/24 ports [22]".to_string()
+            }
+        );
+        // Only the denied port is carved out.
+        assert!(matches!(
+            policy.evaluate(&target("10.0.5.1", 443)),
+            Decision::Allow { .. }
+        ));
+        // Diagnostics skip the allow rules but not the deny rules.
+        assert!(matches!(
+            policy.evaluate_for(&target("10.0.5.1", 22), TrafficKind::Diagnostic),
+            Decision::Deny { .. }
+        ));
+
+        let (decisions, stats) =
+            policy.evaluate_batch(&[target("10.0.4.1", 22), target("10.0.5.1", 22)]);
+        assert!(matches!(decisions[1], Decision::Deny { .. }));
+        assert_eq!((stats.allowed, stats.denied), (1, 1));
+
+        assert_eq!(policy.to_config(), cfg);
+    }
+
+    #[test]
+    fn ipv4_mapped_targets_match_ipv4_rules() {
+        let v4 = PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule");
+        let v6 = PolicyRule::parse("2001:db8::/32", vec![22]).expect("rule");
+        let mapped_v6 = PolicyRule::parse("::ffff:0:0/96", vec![22]).expect("rule");
+        let mapped = Target::parse("::ffff:10.0.0.5", 22).expect("target");
+
+        assert_eq!(mapped.canonical_ip(), "10.0.0.5".parse::<IpAddr>().unwrap());
+        assert!(v4.matches(&mapped));
+        assert!(!v6.matches(&mapped));
+        assert!(mapped_v6.matches(&mapped));
+        assert!(!v4.matches(&Target::parse("::ffff:10.0.1.5", 22).expect("target")));
+
+        // Plain IPv6 targets are untouched.
+        let pure = Target::parse("2001:db8::5", 22).expect("target");
+        assert_eq!(pure.canonical_ip(), pure.ip);
+        assert!(v6.matches(&pure));
+        assert!(!v4.matches(&pure));
+    }
+
+    #[test]
+    fn rule_without_cidr_or_host_is_rejected() {
+        assert_eq!(
+            PolicyRule::parse("", vec![22]).unwrap_err(),
+            "rule needs a cidr or a host"
+        );
+        assert!(PolicyRule::parse_with_host(" ", Some(""), vec![22]).is_err());
+        assert!(
+            PolicyRule::parse_with_host("", Some("a.*.example"), vec![22])
+                .unwrap_err()
+                .contains("invalid host")
+        );
     }
 
     #[test]
     fn policy_denies_unlisted_port() {
         let rule = PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule");
-        let policy = Policy { allow: vec![rule] };
+        let policy = Policy {
+            allow: vec![rule],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
         let target = Target::parse("10.0.0.5", 443).expect("target");
         assert!(matches!(policy.evaluate(&target), Decision::Deny { .. }));
     }
@@ -105,11 +1189,163 @@ mod tests {
     #[test]
     fn policy_denies_outside_cidr() {
         let rule = PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule");
-        let policy = Policy { allow: vec![rule] };
+        let policy = Policy {
+            allow: vec![rule],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
         let target = Target::parse("10.0.1.5", 22).expect("target");
         assert!(matches!(policy.evaluate(&target), Decision::Deny { .. }));
     }
 
+    #[test]
+    fn parse_combined_accepts_ipv4_and_bracketed_ipv6() {
+        assert_eq!(
+            Target::parse_combined("10.0.0.5:22").expect("v4"),
+            Target::parse("10.0.0.5", 22).expect("target")
+        );
+        assert_eq!(
+            Target::parse_combined("[2001:db8::1]:443").expect("v6"),
+            Target::parse("2001:db8::1", 443).expect("target")
+        );
+        assert_eq!(
+            Target::parse_combined("[10.0.0.5]:22").expect("bracketed v4"),
+            Target::parse("10.0.0.5", 22).expect("target")
+        );
+    }
+
+    #[test]
+    fn parse_combined_rejects_malformed_targets() {
+        for (input, expected) in [
+            ("10.0.0.5", "missing port"),
+            ("2001:db8::1:443", "must be bracketed"),
+            ("[2001:db8::1:443", "unclosed bracket"),
+            ("10.0.0.5:http", "bad port"),
+            ("10.0.0.5:70000", "bad port"),
+            ("db.internal:5432", "invalid ip"),
+        ] {
+            let err = Target::parse_combined(input).unwrap_err();
+            assert!(err.contains(expected), "{}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn target_socket_addr_roundtrip_v4() {
+        let addr: SocketAddr = "10.0.0.5:22".parse().expect("addr");
+        let target = Target::from(addr);
+        assert_eq!(target, Target::parse("10.0.0.5", 22).expect("target"));
+        assert_eq!(target.to_socket_addr(), addr);
+        assert_eq!(target.to_string(), "10.0.0.5:22");
+    }
+
+    #[test]
+    fn target_socket_addr_roundtrip_v6() {
+        let addr: SocketAddr = "[2001:db8::1]:443".parse().expect("addr");
+        let target = Target::from(addr);
+        assert_eq!(target, Target::parse("2001:db8::1", 443).expect("target"));
+        assert_eq!(target.to_socket_addr(), addr);
+        assert_eq!(target.to_string(), "[2001:db8::1]:443");
+        assert_eq!(
+            target.to_string().parse::<SocketAddr>().expect("parse"),
+            addr
+        );
+    }
+
+    #[test]
+    fn policy_lint_detects_shadowed_rule() {
+        let policy = Policy {
+            allow: vec![
+                PolicyRule::parse("10.0.0.0/16", vec![22, 443]).expect("rule"),
+                PolicyRule::parse("10.0.1.0/24", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.2.0/24", vec![22, 8080]).expect("rule"),
+            ],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        let lints = policy.lint();
+        assert_eq!(lints.len(), 2);
+        assert_eq!(lints[0].kind, PolicyLintKind::Shadowed);
+        assert_eq!((lints[0].rule, lints[0].other), (1, 0));
+        assert_eq!(lints[1].kind, PolicyLintKind::Overlap);
+        assert_eq!((lints[1].rule, lints[1].other), (2, 0));
+    }
+
+    #[test]
+    fn policy_lint_checks_deny_rules() {
+        let policy = Policy {
+            allow: vec![PolicyRule::parse("10.0.0.0/8", vec![22]).expect("rule")],
+            deny: vec![
+                PolicyRule::parse("10.0.5.0/24", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.5.7/32", vec![22])
+                    .expect("rule")
+                    .with_reason("never reported"),
+            ],
+            allow_diagnostics: false,
+        };
+        let lints = policy.lint();
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].kind, PolicyLintKind::Shadowed);
+        assert!(lints[0].deny);
+        assert_eq!((lints[0].rule, lints[0].other), (1, 0));
+        assert!(
+            lints[0].message.starts_with("deny rule 1"),
+            "{}",
+            lints[0].message
+        );
+    }
+
+    #[test]
+    fn from_config_rejects_reason_on_allow_rules() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+[[allow]]
+cidr = "10.0.0.0/8"
+ports = [22]
+reason = "ignored"
+"#,
+        )
+        .expect("parse");
+        let err = Policy::from_config(&cfg).unwrap_err();
+        assert!(err.contains("only allowed on deny rules"), "{}", err);
+    }
+
+    #[test]
+    fn policy_lint_ignores_disjoint_rules() {
+        let policy = Policy {
+            allow: vec![
+                PolicyRule::parse("10.0.0.0/24", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.1.0/24", vec![22]).expect("rule"),
+                PolicyRule::parse("10.0.0.0/24", vec![443]).expect("rule"),
+                PolicyRule::parse("::1/128", vec![22]).expect("rule"),
+            ],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        };
+        assert!(policy.lint().is_empty());
+    }
+
+    #[test]
+    fn decision_serializes_with_tag() {
+        assert_eq!(
+            serde_json::to_value(Decision::Allow {
+                rule_index: Some(1)
+            })
+            .unwrap(),
+            serde_json::json!({"decision": "allow", "rule_index": 1})
+        );
+        assert_eq!(
+            serde_json::to_value(Decision::Allow { rule_index: None }).unwrap(),
+            serde_json::json!({"decision": "allow"})
+        );
+        let deny = Decision::Deny {
+            reason: "target 10.0.0.5:22 not allowed".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(deny).unwrap(),
+            serde_json::json!({"decision": "deny", "reason": "target 10.0.0.5:22 not allowed"})
+        );
+    }
+
     #[test]
     fn policy_rejects_empty_ports() {
         let err = PolicyRule::parse("10.0.0.0/24", vec![]).unwrap_err();
@@ -122,11 +1358,13 @@ mod tests {
             allow: vec![PolicyRuleConfig {
                 cidr: "10.0.0.0/24".to_string(),
                 ports: vec![22, 443],
+                ..Default::default()
             }],
+            ..Default::default()
         };
         let policy = Policy::from_config(&cfg).expect("policy");
         let target = Target::parse("10.0.0.5", 443).expect("target");
-        assert_eq!(policy.evaluate(&target), Decision::Allow);
+        assert!(matches!(policy.evaluate(&target), Decision::Allow { .. }));
     }
 
     #[test]
@@ -135,9 +1373,144 @@ mod tests {
             allow: vec![PolicyRuleConfig {
                 cidr: "10.0.0.0/24".to_string(),
                 ports: vec![],
+                ..Default::default()
             }],
+            ..Default::default()
         };
         let err = Policy::from_config(&cfg).unwrap_err();
         assert!(err.contains("ports"));
     }
+
+    #[test]
+    fn policy_from_config_resolves_port_group() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+            [port_groups]
+            web = [80, 443]
+
+            [[allow]]
+            cidr = "10.0.0.0/24"
+            port_group = "web"
+
+            [[allow]]
+            cidr = "10.0.1.0/24"
+            ports = [22]
+            port_group = "web"
+            "#,
+        )
+        .expect("parse");
+        let policy = Policy::from_config(&cfg).expect("policy");
+        let allowed = |ip: &str, port| {
+            matches!(
+                policy.evaluate(&Target::parse(ip, port).expect("target")),
+                Decision::Allow { .. }
+            )
+        };
+        assert!(allowed("10.0.0.5", 443));
+        assert!(!allowed("10.0.0.5", 22));
+        assert!(allowed("10.0.1.5", 22));
+        assert!(allowed("10.0.1.5", 80));
+    }
+
+    #[test]
+    fn policy_from_config_orders_rules_by_priority() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+            [[allow]]
+            cidr = "10.0.0.0/8"
+            ports = [22]
+
+            [[allow]]
+            cidr = "10.0.1.0/24"
+            ports = [22]
+            priority = 10
+
+            [[allow]]
+            cidr = "10.0.2.0/24"
+            ports = [22]
+            "#,
+        )
+        .expect("parse");
+        let policy = Policy::from_config(&cfg).expect("policy");
+        let order: Vec<String> = policy.allow.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            order,
+            [
+                "10.0.1.0/24 ports [22]",
+                "10.0.0.0/8 ports [22]",
+                "10.0.2.0/24 ports [22]",
+            ]
+        );
+        // Reordering does not change what is allowed.
+        let target = Target::parse("10.0.2.5", 22).expect("target");
+        assert!(matches!(policy.evaluate(&target), Decision::Allow { .. }));
+    }
+
+    #[test]
+    fn policy_from_config_keeps_order_for_equal_priority() {
+        let cfg: PolicyConfig = toml::from_str(
+            r#"
+            [[allow]]
+            cidr = "10.0.2.0/24"
+            ports = [22]
+            priority = 5
+
+            [[allow]]
+            cidr = "10.0.1.0/24"
+            ports = [22]
+            priority = 5
+            "#,
+        )
+        .expect("parse");
+        let policy = Policy::from_config(&cfg).expect("policy");
+        assert_eq!(policy.allow[0].to_string(), "10.0.2.0/24 ports [22]");
+        assert_eq!(policy.allow[1].to_string(), "10.0.1.0/24 ports [22]");
+    }
+
+    #[test]
+    fn policy_from_config_rejects_unknown_port_group() {
+        let cfg = PolicyConfig {
+            allow: vec![PolicyRuleConfig {
+                cidr: "10.0.0.0/24".to_string(),
+                port_group: Some("db".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let err = Policy::from_config(&cfg).unwrap_err();
+        assert!(err.contains("unknown port_group db"));
+    }
+
+    #[test]
+    fn policy_file_json_and_toml_are_equivalent() {
+        let toml_path = temp_policy_path("equiv.toml");
+        let json_path = temp_policy_path("equiv.json");
+        fs::write(
+            &toml_path,
+            "[[allow]]\ncidr = \"10.0.0.0/24\"\nports = [22, 443]\n",
+        )
+        .unwrap();
+        fs::write(
+            &json_path,
+            r#"{"allow": [{"cidr": "10.0.0.0/24", "ports": [22, 443]}]}"#,
+        )
+        .unwrap();
+
+        let from_toml = Policy::from_config(&load_policy_config(&toml_path).unwrap()).unwrap();
+        let from_json = Policy::from_config(&load_policy_config(&json_path).unwrap()).unwrap();
+        assert_eq!(from_toml, from_json);
+
+        let _ = fs::remove_file(&toml_path);
+        let _ = fs::remove_file(&json_path);
+    }
+
+    #[test]
+    fn policy_file_rejects_malformed_json() {
+        let path = temp_policy_path("bad.json");
+        fs::write(&path, r#"{"allow": [{"cidr": "10.0.0.0/24", "ports": }]}"#).unwrap();
+        let err = load_policy_config(&path).unwrap_err();
+        assert!(err.contains("failed to parse JSON"));
+        assert!(err.contains("line 1"));
+        let _ = fs::remove_file(&path);
+    }
 }
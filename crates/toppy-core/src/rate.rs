@@ -1,3 +1,6 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
 use std::time::Duration;
 
 /// Simple token-bucket rate limiter.
@@ -28,7 +31,10 @@ impl TokenBucket {
 
     /// Refills tokens based on `now`.
     ///
-    /// `now` should be monotonic (e.g. time since process start).
+    /// `now` must come from a monotonic clock (e.g. time since process start).
+    /// A `now` at or before the previous refill is ignored rather than draining
+    /// the bucket. Arbitrarily large gaps (up to `Duration::MAX`) saturate and
+    /// clamp to capacity without overflowing.
     pub fn refill(&mut self, now: Duration) {
         if now <= self.last_refill {
             return;
@@ -45,7 +51,10 @@ impl TokenBucket {
         // With FP_SCALE = 1e9, the refill per nanosecond in fp-units is refill_per_sec.
         // increment_fp = elapsed_nanos * refill_per_sec
         let increment_fp = elapsed_nanos.saturating_mul(self.refill_per_sec as u128);
-        self.tokens_fp = (self.tokens_fp + increment_fp).min(self.capacity_fp);
+        self.tokens_fp = self
+            .tokens_fp
+            .saturating_add(increment_fp)
+            .min(self.capacity_fp);
         self.last_refill = now;
     }
 
@@ -67,12 +76,256 @@ impl TokenBucket {
         }
     }
 
+    /// Like [`try_take`](Self::try_take), but on refusal reports how far
+    /// short the bucket is instead of a bare `false`. Never blocks.
+    pub fn try_take_or_wait(&mut self, amount: u64, now: Duration) -> Result<(), Shortfall> {
+        self.refill(now);
+        let needed_fp = (amount as u128) * Self::FP_SCALE;
+        if self.tokens_fp >= needed_fp {
+            self.tokens_fp -= needed_fp;
+            return Ok(());
+        }
+        let deficit_fp = needed_fp - self.tokens_fp;
+        let wait = if needed_fp > self.capacity_fp || self.refill_per_sec == 0 {
+            None
+        } else {
+            // refill_per_sec fp-units arrive per nanosecond; round up.
+            let nanos = deficit_fp.div_ceil(self.refill_per_sec as u128);
+            Some(Duration::new(
+                (nanos / Self::FP_SCALE) as u64,
+                (nanos % Self::FP_SCALE) as u32,
+            ))
+        };
+        Err(Shortfall {
+            missing: deficit_fp.div_ceil(Self::FP_SCALE) as u64,
+            wait,
+        })
+    }
+
     /// Forces the bucket to be empty.
     pub fn clear(&mut self) {
         self.tokens_fp = 0;
     }
 }
 
+/// Why [`TokenBucket::try_take_or_wait`] refused a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortfall {
+    /// Whole tokens the bucket is short by (rounded up).
+    pub missing: u64,
+    /// Time until enough tokens will have refilled; `None` if that never
+    /// happens (no refill, or `amount` exceeds capacity).
+    pub wait: Option<Duration>,
+}
+
+/// One [`TokenBucket`] per key (e.g. client id), all with the same limits.
+/// Keys unseen for `idle_ttl` are dropped by [`prune`](Self::prune).
+#[derive(Debug, Clone)]
+pub struct KeyedRateLimiter<K> {
+    capacity: u64,
+    refill_per_sec: u64,
+    idle_ttl: Duration,
+    entries: HashMap<K, KeyedEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct KeyedEntry {
+    bucket: TokenBucket,
+    last_seen: Duration,
+}
+
+/// Serializable state of a [`KeyedRateLimiter`], taken at `unix_ms`.
+///
+/// Times are stored relative to the snapshot so they survive a restart,
+/// where the monotonic clock starts over.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyedSnapshot<K> {
+    pub unix_ms: u64,
+    pub entries: Vec<KeyedSnapshotEntry<K>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyedSnapshotEntry<K> {
+    pub key: K,
+    /// Tokens in fixed point (1 token = 1e9).
+    pub tokens_fp: u128,
+    /// Time since the key was last used, in milliseconds.
+    pub idle_ms: u64,
+}
+
+impl<K: Eq + Hash + Clone> KeyedRateLimiter<K> {
+    pub fn new(capacity: u64, refill_per_sec: u64, idle_ttl: Duration) -> Self {
+        Self {
+            capacity,
+            refill_per_sec,
+            idle_ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Attempts to take `amount` from `key`'s bucket, creating it full if new.
+    pub fn try_take(&mut self, key: &K, amount: u64, now: Duration) -> bool {
+        let (capacity, refill_per_sec) = (self.capacity, self.refill_per_sec);
+        let entry = self.entries.entry(key.clone()).or_insert_with(|| {
+            let mut bucket = TokenBucket::new(capacity, refill_per_sec);
+            bucket.last_refill = now;
+            KeyedEntry {
+                bucket,
+                last_seen: now,
+            }
+        });
+        entry.last_seen = entry.last_seen.max(now);
+        entry.bucket.try_take(amount, now)
+    }
+
+    /// Whole tokens left for `key` as of its last use; `None` if untracked.
+    pub fn available(&self, key: &K) -> Option<u64> {
+        self.entries.get(key).map(|entry| entry.bucket.available())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops keys not used within `idle_ttl` of `now`.
+    pub fn prune(&mut self, now: Duration) {
+        let ttl = self.idle_ttl;
+        self.entries
+            .retain(|_, entry| entry.last_seen.saturating_add(ttl) > now);
+    }
+
+    /// Captures every live key's bucket, refilled up to `now`.
+    pub fn snapshot(&mut self, now: Duration, unix_ms: u64) -> KeyedSnapshot<K> {
+        self.prune(now);
+        let entries = self
+            .entries
+            .iter_mut()
+            .map(|(key, entry)| {
+                entry.bucket.refill(now);
+                KeyedSnapshotEntry {
+                    key: key.clone(),
+                    tokens_fp: entry.bucket.tokens_fp,
+                    idle_ms: now.saturating_sub(entry.last_seen).as_millis() as u64,
+                }
+            })
+            .collect();
+        KeyedSnapshot { unix_ms, entries }
+    }
+
+    /// Replaces the current keys with `snapshot`, crediting the refill for the
+    /// wall-clock time since it was taken and dropping keys idle past `idle_ttl`.
+    pub fn restore(&mut self, snapshot: KeyedSnapshot<K>, now: Duration, unix_ms: u64) {
+        let offline = Duration::from_millis(unix_ms.saturating_sub(snapshot.unix_ms));
+        self.entries.clear();
+        for saved in snapshot.entries {
+            let idle = Duration::from_millis(saved.idle_ms).saturating_add(offline);
+            if idle >= self.idle_ttl {
+                continue;
+            }
+            let mut bucket = TokenBucket::new(self.capacity, self.refill_per_sec);
+            bucket.tokens_fp = saved.tokens_fp.min(bucket.capacity_fp);
+            bucket.refill(offline);
+            bucket.last_refill = now;
+            self.entries.insert(
+                saved.key,
+                KeyedEntry {
+                    bucket,
+                    // Clamped at zero right after a restart; the key then
+                    // just expires a little early.
+                    last_seen: now.saturating_sub(idle),
+                },
+            );
+        }
+    }
+}
+
+/// Caps the total taken within any trailing `window`.
+///
+/// Uses the same monotonic `now` convention as [`TokenBucket`].
+#[derive(Debug, Clone)]
+pub struct SlidingWindowLimiter {
+    limit: u64,
+    window: Duration,
+    used: u64,
+    events: VecDeque<(Duration, u64)>,
+}
+
+impl SlidingWindowLimiter {
+    pub fn new(limit: u64, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            used: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn expire(&mut self, now: Duration) {
+        while let Some(&(at, amount)) = self.events.front() {
+            if at.saturating_add(self.window) > now {
+                break;
+            }
+            self.used -= amount;
+            self.events.pop_front();
+        }
+    }
+
+    /// Returns whether `amount` fits in the window at `now` without taking it.
+    pub fn fits(&mut self, amount: u64, now: Duration) -> bool {
+        self.expire(now);
+        self.used.saturating_add(amount) <= self.limit
+    }
+
+    /// Attempts to take `amount` at time `now`.
+    /// Returns `true` if allowed.
+    pub fn try_take(&mut self, amount: u64, now: Duration) -> bool {
+        if !self.fits(amount, now) {
+            return false;
+        }
+        self.used += amount;
+        self.events.push_back((now, amount));
+        true
+    }
+}
+
+/// Which part of a [`CompositeLimiter`] denied a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitReason {
+    /// The token bucket (sustained rate) is exhausted.
+    Sustained,
+    /// The sliding window (burst cap) is full.
+    Burst,
+}
+
+/// Allows a request only if both a sustained-rate bucket and a burst window permit it.
+#[derive(Debug, Clone)]
+pub struct CompositeLimiter {
+    bucket: TokenBucket,
+    window: SlidingWindowLimiter,
+}
+
+impl CompositeLimiter {
+    pub fn new(bucket: TokenBucket, window: SlidingWindowLimiter) -> Self {
+        Self { bucket, window }
+    }
+
+    /// Takes `amount` from both limiters, or from neither if either denies.
+    pub fn try_take(&mut self, amount: u64, now: Duration) -> Result<(), LimitReason> {
+        if !self.window.fits(amount, now) {
+            return Err(LimitReason::Burst);
+        }
+        if !self.bucket.try_take(amount, now) {
+            return Err(LimitReason::Sustained);
+        }
+        self.window.try_take(amount, now);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,6 +352,60 @@ mod tests {
         assert_eq!(bucket.available(), 10);
     }
 
+    #[test]
+    fn try_take_or_wait_takes_when_available() {
+        let mut bucket = TokenBucket::new(10, 2);
+        assert_eq!(bucket.try_take_or_wait(4, Duration::ZERO), Ok(()));
+        assert_eq!(bucket.available(), 6);
+    }
+
+    #[test]
+    fn try_take_or_wait_reports_shortfall() {
+        let mut bucket = TokenBucket::new(10, 4);
+        bucket.clear();
+        // 0.5s at 4 tokens/sec => 2 tokens; 5 more take 1.25s.
+        assert_eq!(
+            bucket.try_take_or_wait(7, Duration::from_millis(500)),
+            Err(Shortfall {
+                missing: 5,
+                wait: Some(Duration::from_millis(1250)),
+            })
+        );
+        // Nothing was taken.
+        assert_eq!(bucket.available(), 2);
+        assert_eq!(
+            bucket.try_take_or_wait(7, Duration::from_millis(1750)),
+            Ok(())
+        );
+
+        // 187.5ms later there are 0.75 tokens: 1 token short, for 62.5ms.
+        bucket.clear();
+        let now = Duration::from_micros(1_937_500);
+        bucket.refill(now);
+        let shortfall = bucket
+            .try_take_or_wait(1, Duration::from_millis(1937))
+            .unwrap_err();
+        assert_eq!(shortfall.missing, 1);
+        assert_eq!(shortfall.wait, Some(Duration::from_micros(62_500)));
+
+        assert_eq!(
+            bucket
+                .try_take_or_wait(11, Duration::from_secs(10))
+                .unwrap_err()
+                .wait,
+            None
+        );
+        let mut stalled = TokenBucket::new(1, 0);
+        stalled.clear();
+        assert_eq!(
+            stalled.try_take_or_wait(1, Duration::from_secs(1)),
+            Err(Shortfall {
+                missing: 1,
+                wait: None,
+            })
+        );
+    }
+
     #[test]
     fn bucket_denies_when_empty() {
         let mut bucket = TokenBucket::new(1, 0);
@@ -121,4 +428,108 @@ mod tests {
         bucket.refill(Duration::from_millis(1000));
         assert_eq!(bucket.available(), 1);
     }
+
+    #[test]
+    fn bucket_refill_clamps_at_duration_max() {
+        let mut bucket = TokenBucket::new(10, u64::MAX);
+        assert!(bucket.try_take(5, Duration::from_secs(1)));
+        bucket.refill(Duration::MAX);
+        assert_eq!(bucket.available(), 10);
+        assert_eq!(bucket.tokens_fp, bucket.capacity_fp);
+    }
+
+    #[test]
+    fn bucket_refill_at_numeric_extremes() {
+        let mut bucket = TokenBucket::new(u64::MAX, u64::MAX);
+        bucket.clear();
+        bucket.refill(Duration::MAX);
+        assert_eq!(bucket.available(), u64::MAX);
+        assert!(bucket.try_take(u64::MAX, Duration::MAX));
+        assert_eq!(bucket.available(), 0);
+    }
+
+    #[test]
+    fn bucket_ignores_time_going_backwards() {
+        let mut bucket = TokenBucket::new(10, 1);
+        bucket.clear();
+        bucket.refill(Duration::from_secs(5));
+        assert_eq!(bucket.available(), 5);
+        bucket.refill(Duration::from_secs(1));
+        assert_eq!(bucket.available(), 5);
+    }
+
+    #[test]
+    fn keyed_snapshot_restore_preserves_budgets() {
+        let ttl = Duration::from_secs(60);
+        let mut limiter = KeyedRateLimiter::new(10, 1, ttl);
+        assert!(limiter.try_take(&"alice".to_string(), 7, Duration::from_secs(100)));
+        assert!(limiter.try_take(&"bob".to_string(), 2, Duration::from_secs(100)));
+
+        let snapshot = limiter.snapshot(Duration::from_secs(100), 1_000_000);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: KeyedSnapshot<String> = serde_json::from_str(&json).unwrap();
+
+        // Restored in a "new process" whose monotonic clock restarted.
+        let mut restored = KeyedRateLimiter::new(10, 1, ttl);
+        restored.restore(snapshot.clone(), Duration::from_secs(1), 1_000_000);
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.available(&"alice".to_string()), Some(3));
+        assert_eq!(restored.available(&"bob".to_string()), Some(8));
+        assert!(!restored.try_take(&"alice".to_string(), 4, Duration::from_secs(1)));
+        assert!(restored.try_take(&"alice".to_string(), 3, Duration::from_secs(1)));
+
+        // Downtime refills the buckets, and keys idle past the TTL are pruned.
+        let mut later = KeyedRateLimiter::new(10, 1, ttl);
+        later.restore(snapshot.clone(), Duration::ZERO, 1_002_000);
+        assert_eq!(later.available(&"alice".to_string()), Some(5));
+        later.restore(snapshot, Duration::ZERO, 1_060_000);
+        assert!(later.is_empty());
+    }
+
+    #[test]
+    fn keyed_prune_drops_idle_keys() {
+        let mut limiter = KeyedRateLimiter::new(5, 1, Duration::from_secs(10));
+        assert!(limiter.try_take(&1u32, 1, Duration::from_secs(0)));
+        assert!(limiter.try_take(&2u32, 1, Duration::from_secs(5)));
+        limiter.prune(Duration::from_secs(10));
+        assert_eq!(limiter.available(&1), None);
+        assert_eq!(limiter.available(&2), Some(4));
+    }
+
+    #[test]
+    fn window_expires_old_events() {
+        let mut window = SlidingWindowLimiter::new(3, Duration::from_secs(1));
+        assert!(window.try_take(3, Duration::from_millis(0)));
+        assert!(!window.try_take(1, Duration::from_millis(999)));
+        assert!(window.try_take(1, Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn composite_denies_burst_when_bucket_allows() {
+        let mut limiter = CompositeLimiter::new(
+            TokenBucket::new(100, 100),
+            SlidingWindowLimiter::new(5, Duration::from_secs(1)),
+        );
+        assert_eq!(limiter.try_take(5, Duration::ZERO), Ok(()));
+        assert_eq!(
+            limiter.try_take(1, Duration::from_millis(500)),
+            Err(LimitReason::Burst)
+        );
+        // The denied request took nothing from the bucket.
+        assert_eq!(limiter.bucket.available(), 95);
+    }
+
+    #[test]
+    fn composite_denies_sustained_when_window_allows() {
+        let mut limiter = CompositeLimiter::new(
+            TokenBucket::new(2, 0),
+            SlidingWindowLimiter::new(10, Duration::from_secs(1)),
+        );
+        assert_eq!(limiter.try_take(2, Duration::ZERO), Ok(()));
+        assert_eq!(
+            limiter.try_take(1, Duration::from_secs(5)),
+            Err(LimitReason::Sustained)
+        );
+        assert!(limiter.window.fits(10, Duration::from_secs(5)));
+    }
 }
@@ -1,4 +1,251 @@
-use std::sync::Mutex;
+use std::env;
+use std::sync::{Mutex, MutexGuard};
 
 // Shared lock for tests that touch process-wide environment variables.
 pub static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Holds [`ENV_LOCK`] and restores the variables set by [`scoped_env`] on drop,
+/// including when the test panics.
+pub struct EnvGuard {
+    saved: Vec<(String, Option<String>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+/// Locks [`ENV_LOCK`], then sets (`Some`) or removes (`None`) each variable.
+pub fn scoped_env(vars: &[(&str, Option<&str>)]) -> EnvGuard {
+    let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut saved = Vec::with_capacity(vars.len());
+    for (key, value) in vars {
+        saved.push((key.to_string(), env::var(key).ok()));
+        match value {
+            Some(value) => env::set_var(key, value),
+            None => env::remove_var(key),
+        }
+    }
+    EnvGuard { saved, _lock: lock }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        // Reverse order so a variable listed twice ends at its original value.
+        for (key, value) in self.saved.drain(..).rev() {
+            match value {
+                Some(value) => env::set_var(&key, value),
+                None => env::remove_var(&key),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ring"))]
+pub use fake_gateway::{FakeGateway, FakeResponse};
+
+/// Minimal in-process QUIC/h3 server for exercising the doctor's client checks.
+#[cfg(all(test, feature = "ring"))]
+mod fake_gateway {
+    use bytes::Bytes;
+    use quinn::crypto::rustls::QuicServerConfig;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use toppy_proto::ControlMessage;
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    /// How the fake gateway answers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FakeResponse {
+        /// Reply `Pong` to pings (legacy `pong` bytes before `Hello`).
+        Pong,
+        /// Reject pings with an `unauthorized` error.
+        Unauthorized,
+        /// Reply to pings with these raw bytes.
+        Raw(&'static [u8]),
+        /// Speak h3 (ALPN `h3`) and answer every request with this status.
+        H3Status(u16),
+    }
+
+    /// Listens on `127.0.0.1` with a fresh self-signed `localhost` cert whose
+    /// PEM is written to `ca_path`. Stops when dropped.
+    pub struct FakeGateway {
+        pub addr: SocketAddr,
+        pub ca_path: PathBuf,
+        runtime: Option<tokio::runtime::Runtime>,
+    }
+
+    impl FakeGateway {
+        pub fn start(response: FakeResponse) -> Result<Self, String> {
+            Self::start_with(response, false)
+        }
+
+        /// Like [`FakeGateway::start`], but accepts 0-RTT data from resumed clients.
+        pub fn start_0rtt(response: FakeResponse) -> Result<Self, String> {
+            Self::start_with(response, true)
+        }
+
+        fn start_with(response: FakeResponse, early_data: bool) -> Result<Self, String> {
+            let rcgen::CertifiedKey { cert, key_pair } =
+                rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+                    .map_err(|e| format!("cert generation failed: {}", e))?;
+            let ca_path = std::env::temp_dir().join(format!(
+                "toppy-fake-gw-{}-{}.pem",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::write(&ca_path, cert.pem())
+                .map_err(|e| format!("write {} failed: {}", ca_path.display(), e))?;
+
+            let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+            let mut tls = rustls::ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(vec![CertificateDer::from(cert.der().to_vec())], key)
+                .map_err(|e| e.to_string())?;
+            // rustls rejects ALPN-less QUIC clients once ALPN is configured,
+            // so only the h3 mode advertises it.
+            if let FakeResponse::H3Status(_) = response {
+                tls.alpn_protocols = vec![b"h3".to_vec()];
+            }
+            if early_data {
+                // QUIC only allows 0 or u32::MAX.
+                tls.max_early_data_size = u32::MAX;
+            }
+            let crypto = QuicServerConfig::try_from(tls).map_err(|e| e.to_string())?;
+            let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .map_err(|e| format!("tokio init failed: {}", e))?;
+            let endpoint = runtime.block_on(async {
+                quinn::Endpoint::server(server_config, ([127, 0, 0, 1], 0).into())
+                    .map_err(|e| format!("fake gateway bind failed: {}", e))
+            })?;
+            let addr = endpoint.local_addr().map_err(|e| e.to_string())?;
+            runtime.spawn(async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    tokio::spawn(async move {
+                        if let Ok(connection) = incoming.await {
+                            serve(connection, response).await;
+                        }
+                    });
+                }
+            });
+
+            Ok(Self {
+                addr,
+                ca_path,
+                runtime: Some(runtime),
+            })
+        }
+    }
+
+    async fn serve(connection: quinn::Connection, response: FakeResponse) {
+        let status = match response {
+            FakeResponse::H3Status(status) => status,
+            FakeResponse::Pong | FakeResponse::Unauthorized | FakeResponse::Raw(_) => {
+                // Like the gateway: capsule replies after `Hello`, legacy bytes before.
+                let mut negotiated = false;
+                while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                    let Ok(data) = recv.read_to_end(256).await else {
+                        continue;
+                    };
+                    let reply = match ControlMessage::decode(&data) {
+                        Ok((ControlMessage::Hello { min, max }, _)) => {
+                            match toppy_proto::negotiate_version(min, max) {
+                                Ok(version) => {
+                                    negotiated = true;
+                                    ControlMessage::HelloAck { version }.encode()
+                                }
+                                Err(error) => error.encode(),
+                            }
+                        }
+                        _ => match (response, negotiated) {
+                            (FakeResponse::Raw(bytes), _) => bytes.to_vec(),
+                            (FakeResponse::Pong, true) => ControlMessage::Pong.encode(),
+                            (_, true) => ControlMessage::Error {
+                                code: toppy_proto::error_code::UNAUTHORIZED,
+                                message: "token expired".to_string(),
+                            }
+                            .encode(),
+                            (FakeResponse::Pong, false) => toppy_proto::LEGACY_PONG.to_vec(),
+                            (_, false) => toppy_proto::LEGACY_UNAUTHORIZED.to_vec(),
+                        },
+                    };
+                    let _ = send.write_all(&reply).await;
+                    let _ = send.finish();
+                }
+                return;
+            }
+        };
+
+        let mut builder = h3::server::builder();
+        builder.enable_extended_connect(true);
+        builder.enable_datagram(true);
+        let Ok(mut conn) = builder
+            .build::<_, Bytes>(h3_quinn::Connection::new(connection))
+            .await
+        else {
+            return;
+        };
+        while let Ok(Some(resolver)) = conn.accept().await {
+            let Ok((_req, mut stream)) = resolver.resolve_request().await else {
+                continue;
+            };
+            let res = http::Response::builder()
+                .status(status)
+                .body(())
+                .expect("response");
+            let _ = stream.send_response(res).await;
+            let _ = stream.finish().await;
+        }
+    }
+
+    impl Drop for FakeGateway {
+        fn drop(&mut self) {
+            if let Some(runtime) = self.runtime.take() {
+                runtime.shutdown_background();
+            }
+            let _ = std::fs::remove_file(&self.ca_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_env_restores_set_and_unset_vars() {
+        let set_key = "TOPPY_TEST_SCOPED_ENV_SET";
+        let unset_key = "TOPPY_TEST_SCOPED_ENV_UNSET";
+        {
+            let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            env::set_var(set_key, "original");
+            env::remove_var(unset_key);
+        }
+
+        {
+            let _guard = scoped_env(&[(set_key, None), (unset_key, Some("temporary"))]);
+            assert!(env::var(set_key).is_err());
+            assert_eq!(env::var(unset_key).as_deref(), Ok("temporary"));
+        }
+
+        assert_eq!(env::var(set_key).as_deref(), Ok("original"));
+        assert!(env::var(unset_key).is_err());
+        env::remove_var(set_key);
+    }
+
+    #[test]
+    fn scoped_env_restores_after_panic() {
+        let key = "TOPPY_TEST_SCOPED_ENV_PANIC";
+        let result = std::panic::catch_unwind(|| {
+            let _guard = scoped_env(&[(key, Some("during"))]);
+            panic!("boom");
+        });
+        assert!(result.is_err());
+        assert!(env::var(key).is_err());
+    }
+}
@@ -5,7 +5,8 @@ use std::fs;
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use toppy_core::doctor::doctor_check;
+use toppy_core::doctor::{doctor_check, DOCTOR_SCHEMA_VERSION};
+use toppy_core::test_support::scoped_env;
 
 fn unique_temp_path(prefix: &str) -> PathBuf {
     let nanos = SystemTime::now()
@@ -39,17 +40,13 @@ mtu = 1350
 
 #[test]
 fn doctor_passes_when_config_and_network_ok() {
-    let _guard = toppy_core::test_support::ENV_LOCK
-        .lock()
-        .unwrap_or_else(|e| e.into_inner());
     let path = unique_temp_path("doctor-pass");
     write_config(&path, "127.0.0.1", 4433);
-    let prev = env::var("TOPPY_CONFIG").ok();
-    let prev_net = env::var("TOPPY_DOCTOR_NET").ok();
-    let prev_tun = env::var("TOPPY_DOCTOR_TUN").ok();
-    env::set_var("TOPPY_CONFIG", &path);
-    env::set_var("TOPPY_DOCTOR_NET", "pass");
-    env::set_var("TOPPY_DOCTOR_TUN", "pass");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("pass")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+    ]);
 
     let report = doctor_check();
     assert_eq!(report.overall, "pass");
@@ -66,36 +63,17 @@ fn doctor_passes_when_config_and_network_ok() {
         .iter()
         .any(|c| c.id == "h3.connect" && c.status == "pass"));
 
-    if let Some(value) = prev {
-        env::set_var("TOPPY_CONFIG", value);
-    } else {
-        env::remove_var("TOPPY_CONFIG");
-    }
-    if let Some(value) = prev_net {
-        env::set_var("TOPPY_DOCTOR_NET", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_NET");
-    }
-    if let Some(value) = prev_tun {
-        env::set_var("TOPPY_DOCTOR_TUN", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_TUN");
-    }
     let _ = fs::remove_file(&path);
 }
 
 #[test]
 fn doctor_warns_when_config_missing() {
-    let _guard = toppy_core::test_support::ENV_LOCK
-        .lock()
-        .unwrap_or_else(|e| e.into_inner());
     let path = unique_temp_path("doctor-missing");
-    let prev = env::var("TOPPY_CONFIG").ok();
-    let prev_net = env::var("TOPPY_DOCTOR_NET").ok();
-    let prev_tun = env::var("TOPPY_DOCTOR_TUN").ok();
-    env::set_var("TOPPY_CONFIG", &path);
-    env::set_var("TOPPY_DOCTOR_NET", "pass");
-    env::set_var("TOPPY_DOCTOR_TUN", "pass");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("pass")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+    ]);
 
     let report = doctor_check();
     assert_eq!(report.overall, "fail");
@@ -111,72 +89,50 @@ fn doctor_warns_when_config_missing() {
         .checks
         .iter()
         .any(|c| c.id == "h3.connect" && c.status == "warn"));
-
-    if let Some(value) = prev {
-        env::set_var("TOPPY_CONFIG", value);
-    } else {
-        env::remove_var("TOPPY_CONFIG");
-    }
-    if let Some(value) = prev_net {
-        env::set_var("TOPPY_DOCTOR_NET", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_NET");
-    }
-    if let Some(value) = prev_tun {
-        env::set_var("TOPPY_DOCTOR_TUN", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_TUN");
-    }
 }
 
 #[test]
 fn doctor_report_includes_version() {
-    let _guard = toppy_core::test_support::ENV_LOCK
-        .lock()
-        .unwrap_or_else(|e| e.into_inner());
     let path = unique_temp_path("doctor-version");
-    let prev = env::var("TOPPY_CONFIG").ok();
-    let prev_net = env::var("TOPPY_DOCTOR_NET").ok();
-    let prev_tun = env::var("TOPPY_DOCTOR_TUN").ok();
-    env::set_var("TOPPY_CONFIG", &path);
-    env::set_var("TOPPY_DOCTOR_NET", "pass");
-    env::set_var("TOPPY_DOCTOR_TUN", "pass");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("pass")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+    ]);
 
     let report = doctor_check();
     assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+}
+
+#[test]
+fn doctor_json_includes_schema_version() {
+    let path = unique_temp_path("doctor-schema");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("pass")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+    ]);
 
-    if let Some(value) = prev {
-        env::set_var("TOPPY_CONFIG", value);
-    } else {
-        env::remove_var("TOPPY_CONFIG");
-    }
-    if let Some(value) = prev_net {
-        env::set_var("TOPPY_DOCTOR_NET", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_NET");
-    }
-    if let Some(value) = prev_tun {
-        env::set_var("TOPPY_DOCTOR_TUN", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_TUN");
-    }
+    let report = doctor_check();
+    let json = serde_json::to_value(&report).expect("json");
+    assert_eq!(json["schema_version"], DOCTOR_SCHEMA_VERSION);
+    let (pass, warn, fail) = report.status_counts();
+    assert_eq!(
+        json["counts"],
+        serde_json::json!({ "pass": pass, "warn": warn, "fail": fail })
+    );
 }
 
 #[test]
 fn doctor_reports_policy_denied_reason() {
-    let _guard = toppy_core::test_support::ENV_LOCK
-        .lock()
-        .unwrap_or_else(|e| e.into_inner());
     let path = unique_temp_path("doctor-policy-denied");
     write_config_with_policy(&path);
-    let prev = env::var("TOPPY_CONFIG").ok();
-    let prev_net = env::var("TOPPY_DOCTOR_NET").ok();
-    let prev_tun = env::var("TOPPY_DOCTOR_TUN").ok();
-    let prev_target = env::var("TOPPY_DOCTOR_TARGET").ok();
-    env::set_var("TOPPY_CONFIG", &path);
-    env::set_var("TOPPY_DOCTOR_NET", "skip");
-    env::set_var("TOPPY_DOCTOR_TUN", "pass");
-    env::set_var("TOPPY_DOCTOR_TARGET", "127.0.0.1:2223");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("skip")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+        ("TOPPY_DOCTOR_TARGET", Some("127.0.0.1:2223")),
+    ]);
 
     let report = doctor_check();
     let policy_check = report.checks.iter().find(|c| c.id == "policy.denied");
@@ -185,25 +141,107 @@ fn doctor_reports_policy_denied_reason() {
     assert_eq!(policy_check.status, "fail");
     assert!(policy_check.summary.contains("not allowed"));
 
-    if let Some(value) = prev {
-        env::set_var("TOPPY_CONFIG", value);
-    } else {
-        env::remove_var("TOPPY_CONFIG");
-    }
-    if let Some(value) = prev_net {
-        env::set_var("TOPPY_DOCTOR_NET", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_NET");
-    }
-    if let Some(value) = prev_tun {
-        env::set_var("TOPPY_DOCTOR_TUN", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_TUN");
-    }
-    if let Some(value) = prev_target {
-        env::set_var("TOPPY_DOCTOR_TARGET", value);
-    } else {
-        env::remove_var("TOPPY_DOCTOR_TARGET");
-    }
     let _ = fs::remove_file(&path);
 }
+
+#[test]
+fn doctor_reports_configured_targets() {
+    let path = unique_temp_path("doctor-targets");
+    let data = r#"gateway = "127.0.0.1"
+port = 4433
+mtu = 1350
+
+[doctor]
+targets = ["127.0.0.1:2222", "127.0.0.1:2223"]
+
+[policy]
+  [[policy.allow]]
+  cidr = "127.0.0.1/32"
+  ports = [2222]
+"#;
+    fs::write(&path, data).expect("write config");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("skip")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+    ]);
+
+    let report = doctor_check();
+    let targets: Vec<_> = report
+        .checks
+        .iter()
+        .filter(|c| c.id.starts_with("policy.target["))
+        .map(|c| (c.id.as_str(), c.status.as_str()))
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            ("policy.target[127.0.0.1:2222]", "pass"),
+            ("policy.target[127.0.0.1:2223]", "fail"),
+        ]
+    );
+    assert_eq!(report.overall, "fail");
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn doctor_parses_bracketed_ipv6_targets() {
+    let path = unique_temp_path("doctor-targets-v6");
+    let data = r#"gateway = "127.0.0.1"
+port = 4433
+mtu = 1350
+
+[doctor]
+targets = ["[::1]:2222", "::1:2222"]
+
+[policy]
+  [[policy.allow]]
+  cidr = "::1/128"
+  ports = [2222]
+"#;
+    fs::write(&path, data).expect("write config");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("skip")),
+        ("TOPPY_DOCTOR_TUN", Some("pass")),
+    ]);
+
+    let report = doctor_check();
+    let check = |id: &str| {
+        report
+            .checks
+            .iter()
+            .find(|c| c.id == id)
+            .unwrap_or_else(|| panic!("missing {}", id))
+    };
+    assert_eq!(check("policy.target[[::1]:2222]").status, "pass");
+    let unbracketed = check("policy.target[::1:2222]");
+    assert_eq!(unbracketed.status, "fail");
+    assert!(
+        unbracketed.summary.contains("must be bracketed"),
+        "{}",
+        unbracketed.summary
+    );
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn doctor_uses_weighted_overall_when_threshold_configured() {
+    let path = unique_temp_path("doctor-weighted");
+    fs::write(
+        &path,
+        "gateway = \"127.0.0.1\"\nport = 4433\nmtu = 1350\n\n[doctor]\noverall_threshold = 50\n",
+    )
+    .expect("write config");
+    let _env = scoped_env(&[
+        ("TOPPY_CONFIG", path.to_str()),
+        ("TOPPY_DOCTOR_NET", Some("pass")),
+        ("TOPPY_DOCTOR_TUN", Some("fail")),
+    ]);
+
+    let report = doctor_check();
+    assert_eq!(report.overall_mode, "weighted(0.50)");
+    assert_eq!(report.overall, "pass");
+}
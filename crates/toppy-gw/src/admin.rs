@@ -0,0 +1,149 @@
+//! Admin commands received as capsules on the non-h3 control stream.
+
+use crate::GwState;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use toppy_core::policy::{load_policy_config, Policy};
+use toppy_proto::admin::{AdminCommand, AdminRequest, AdminResponse};
+use toppy_proto::Capsule;
+
+fn error_response(message: impl Into<String>) -> AdminResponse {
+    AdminResponse::Error(serde_json::json!({ "error": message.into() }).to_string())
+}
+
+fn authorize(state: &GwState, token: &str) -> Result<(), String> {
+    match state.admin_token.as_deref() {
+        None => Err("admin commands disabled (TOPPY_GW_ADMIN_TOKEN not set)".to_string()),
+        Some(expected) if constant_time_eq(expected.as_bytes(), token.as_bytes()) => Ok(()),
+        Some(_) => Err("invalid admin token".to_string()),
+    }
+}
+
+/// Compares without returning early on the first differing byte, so the
+/// time taken does not reveal how much of the token was right.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Loads the gateway policy file and installs it in `state`.
+pub fn reload_policy(state: &GwState) -> Result<usize, String> {
+    let path = state
+        .policy_path
+        .as_deref()
+        .ok_or_else(|| "no policy file configured (TOPPY_GW_POLICY)".to_string())?;
+    let policy = Policy::from_config(&load_policy_config(Path::new(path))?)?;
+    let rules = policy.rule_count();
+    *state.policy.write().unwrap_or_else(|e| e.into_inner()) = Some(policy);
+    Ok(rules)
+}
+
+pub fn handle_admin(state: &GwState, capsule: &Capsule) -> AdminResponse {
+    let command = match AdminCommand::from_capsule(capsule) {
+        Ok(command) => command,
+        Err(e) => return error_response(format!("bad admin request: {}", e)),
+    };
+    if let Err(err) = authorize(state, &command.token) {
+        return error_response(err);
+    }
+
+    match command.request {
+        AdminRequest::StatsRequest => {
+            let policy_rules = state
+                .policy
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+                .map(Policy::rule_count);
+            AdminResponse::Ok(
+                serde_json::json!({
+                    "active_connections": state.active_connections.load(Ordering::Relaxed),
+                    "total_connections": state.total_connections.load(Ordering::Relaxed),
+                    "policy_rules": policy_rules,
+                })
+                .to_string(),
+            )
+        }
+        AdminRequest::ReloadPolicy => match reload_policy(state) {
+            Ok(rules) => AdminResponse::Ok(
+                serde_json::json!({ "reloaded": true, "policy_rules": rules }).to_string(),
+            ),
+            Err(err) => error_response(err),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuthMode;
+
+    fn state_with_admin(admin_token: Option<&str>) -> GwState {
+        let mut state = GwState::new(AuthMode::SharedToken("user-token".to_string()));
+        state.admin_token = admin_token.map(str::to_string);
+        state
+    }
+
+    #[test]
+    fn admin_stats_with_admin_token() {
+        let state = state_with_admin(Some("admin-token"));
+        state.total_connections.store(3, Ordering::Relaxed);
+        let capsule = AdminCommand::new(AdminRequest::StatsRequest, "admin-token").to_capsule();
+        match handle_admin(&state, &capsule) {
+            AdminResponse::Ok(json) => {
+                let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+                assert_eq!(value["total_connections"], 3);
+            }
+            other => panic!("expected ok, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn admin_stats_count_allow_and_deny_rules() {
+        let state = state_with_admin(Some("admin-token"));
+        let cfg = serde_json::from_str(
+            r#"{"allow": [{"cidr": "10.0.0.0/8", "ports": [22]}],
+                "deny": [{"cidr": "10.0.0.5/32", "ports": [22]}]}"#,
+        )
+        .unwrap();
+        *state.policy.write().unwrap() = Some(Policy::from_config(&cfg).unwrap());
+        let capsule = AdminCommand::new(AdminRequest::StatsRequest, "admin-token").to_capsule();
+        match handle_admin(&state, &capsule) {
+            AdminResponse::Ok(json) => {
+                let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+                assert_eq!(value["policy_rules"], 2);
+            }
+            other => panic!("expected ok, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_tokens() {
+        assert!(constant_time_eq(b"admin-secret", b"admin-secret"));
+        assert!(!constant_time_eq(b"admin-secret", b"admin-secreT"));
+        assert!(!constant_time_eq(b"admin-secret", b"admin"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
+
+    #[test]
+    fn admin_rejects_non_admin_token() {
+        let state = state_with_admin(Some("admin-token"));
+        let capsule = AdminCommand::new(AdminRequest::StatsRequest, "user-token").to_capsule();
+        assert!(matches!(
+            handle_admin(&state, &capsule),
+            AdminResponse::Error(json) if json.contains("invalid admin token")
+        ));
+    }
+
+    #[test]
+    fn admin_disabled_without_admin_token() {
+        let state = state_with_admin(None);
+        let capsule = AdminCommand::new(AdminRequest::ReloadPolicy, "").to_capsule();
+        assert!(matches!(
+            handle_admin(&state, &capsule),
+            AdminResponse::Error(json) if json.contains("disabled")
+        ));
+    }
+}
@@ -0,0 +1,267 @@
+//! Durable record of rejected authentication attempts (`TOPPY_GW_AUDIT_PATH`).
+//!
+//! Each rejection becomes a deny [`AuditEvent`] with action `auth`. Repeats
+//! from the same source IP with the same reason within [`AUTH_AUDIT_WINDOW`]
+//! are only counted, whatever actor they claim; the count is noted on the
+//! next entry written for them. Entries
+//! are appended by a dedicated writer thread so async handlers never block on
+//! file I/O.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use toppy_core::audit::{AuditChainWriter, AuditEvent};
+use toppy_core::auth::unverified_subject;
+
+use crate::events;
+
+pub const AUTH_AUDIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Distinct failures tracked at once; past this the oldest is forgotten.
+const MAX_TRACKED: usize = 1024;
+
+/// Source IP and reason. The actor is left out: an unverified `sub` is
+/// chosen by the client and would let every attempt look new.
+type FailureKey = (IpAddr, String);
+
+pub struct AuthAudit {
+    window: Duration,
+    max_tracked: usize,
+    inner: Mutex<Inner>,
+}
+
+enum WriterMsg {
+    Append(u64, AuditEvent),
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+struct Inner {
+    writer: mpsc::Sender<WriterMsg>,
+    /// When each failure was last written, and how many repeats were skipped since.
+    recent: HashMap<FailureKey, (Instant, u64)>,
+}
+
+impl AuthAudit {
+    pub fn open(path: &str, window: Duration) -> Result<Self, String> {
+        let writer = AuditChainWriter::open(path)
+            .map_err(|e| format!("open audit log {} failed: {}", path, e))?;
+        let writer = spawn_writer(writer);
+        Ok(Self {
+            window,
+            max_tracked: MAX_TRACKED,
+            inner: Mutex::new(Inner {
+                writer,
+                recent: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Records a rejected `token` from `remote`; write errors are logged, not returned.
+    /// The actor is the client certificate subject when there is one, else the
+    /// token's unverified `sub`, else "anonymous".
+    pub fn record_failure(
+        &self,
+        client: Option<&str>,
+        token: Option<&str>,
+        remote: SocketAddr,
+        reason: &str,
+    ) {
+        let unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        if let Err(e) =
+            self.record_failure_at(Instant::now(), unix_ms, client, token, remote, reason)
+        {
+            events::error(format!("audit append failed: {}", e));
+        }
+    }
+
+    /// Returns whether an entry was queued (`false` when rate-limited).
+    fn record_failure_at(
+        &self,
+        now: Instant,
+        unix_ms: u64,
+        client: Option<&str>,
+        token: Option<&str>,
+        remote: SocketAddr,
+        reason: &str,
+    ) -> Result<bool, String> {
+        let actor = client
+            .map(str::to_string)
+            .or_else(|| token.and_then(unverified_subject))
+            .unwrap_or_else(|| "anonymous".to_string());
+        let key = (remote.ip(), reason.to_string());
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        let window = self.window;
+        let suppressed = match inner.recent.get_mut(&key) {
+            Some((last, suppressed)) if now.duration_since(*last) < window => {
+                *suppressed += 1;
+                return Ok(false);
+            }
+            Some((_, suppressed)) => *suppressed,
+            None => 0,
+        };
+        if !inner.recent.contains_key(&key) && inner.recent.len() >= self.max_tracked {
+            inner
+                .recent
+                .retain(|_, (last, _)| now.duration_since(*last) < window);
+            if inner.recent.len() >= self.max_tracked {
+                let oldest = inner
+                    .recent
+                    .iter()
+                    .min_by_key(|(_, (last, _))| *last)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    inner.recent.remove(&oldest);
+                }
+            }
+        }
+
+        let reason = if suppressed > 0 {
+            format!("{} ({} identical failures suppressed)", reason, suppressed)
+        } else {
+            reason.to_string()
+        };
+        let event = AuditEvent {
+            actor,
+            action: "auth".to_string(),
+            target: remote.to_string(),
+            allowed: false,
+            reason: Some(reason),
+            metadata: BTreeMap::new(),
+        };
+        inner
+            .writer
+            .send(WriterMsg::Append(unix_ms, event))
+            .map_err(|_| "audit writer stopped".to_string())?;
+        inner.recent.insert(key, (now, 0));
+        Ok(true)
+    }
+
+    /// Waits until every entry queued so far has been written.
+    #[cfg(test)]
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.writer.send(WriterMsg::Flush(done)).is_ok() {
+            drop(inner);
+            let _ = wait.recv();
+        }
+    }
+}
+
+/// Owns the audit file; exits once the [`AuthAudit`] holding the sender is dropped.
+fn spawn_writer(mut writer: AuditChainWriter) -> mpsc::Sender<WriterMsg> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for msg in rx {
+            match msg {
+                WriterMsg::Append(unix_ms, event) => {
+                    if let Err(e) = writer.append(unix_ms, event) {
+                        events::error(format!("audit append failed: {}", e));
+                    }
+                }
+                #[cfg(test)]
+                WriterMsg::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toppy_core::audit::query_time_range;
+
+    #[test]
+    fn identical_failures_are_rate_limited() {
+        let path =
+            std::env::temp_dir().join(format!("toppy-gw-auth-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let audit = AuthAudit::open(path.to_str().unwrap(), Duration::from_secs(60)).unwrap();
+        let remote: SocketAddr = "192.0.2.7:5000".parse().unwrap();
+        let other_port: SocketAddr = "192.0.2.7:5001".parse().unwrap();
+        let start = Instant::now();
+
+        let record = |secs: u64, remote: SocketAddr, reason: &str| {
+            audit
+                .record_failure_at(
+                    start + Duration::from_secs(secs),
+                    secs * 1000,
+                    None,
+                    Some("bad"),
+                    remote,
+                    reason,
+                )
+                .unwrap()
+        };
+        assert!(record(0, remote, "missing or invalid token"));
+        assert!(!record(1, other_port, "missing or invalid token"));
+        assert!(!record(2, remote, "missing or invalid token"));
+        assert!(record(3, remote, "missing jwt token"));
+        assert!(record(61, remote, "missing or invalid token"));
+        audit.flush();
+
+        let entries = query_time_range(&path, 0, u64::MAX).unwrap();
+        let reasons: Vec<&str> = entries
+            .iter()
+            .map(|e| e.event.reason.as_deref().unwrap_or_default())
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                "missing or invalid token",
+                "missing jwt token",
+                "missing or invalid token (2 identical failures suppressed)",
+            ]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn other_actors_do_not_defeat_suppression_and_tracking_is_capped() {
+        let path = std::env::temp_dir().join(format!(
+            "toppy-gw-auth-audit-cap-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut audit = AuthAudit::open(path.to_str().unwrap(), Duration::from_secs(60)).unwrap();
+        audit.max_tracked = 2;
+        let start = Instant::now();
+        let record = |audit: &AuthAudit, secs: u64, remote: &str, actor: &str| {
+            audit
+                .record_failure_at(
+                    start + Duration::from_secs(secs),
+                    secs * 1000,
+                    Some(actor),
+                    None,
+                    remote.parse().unwrap(),
+                    "invalid jwt",
+                )
+                .unwrap()
+        };
+
+        assert!(record(&audit, 0, "192.0.2.1:1", "alice"));
+        assert!(!record(&audit, 1, "192.0.2.1:2", "bob"));
+        assert!(!record(&audit, 2, "192.0.2.1:3", "carol"));
+        assert!(record(&audit, 3, "192.0.2.2:1", "alice"));
+        // A third source evicts the oldest, so 192.0.2.1 counts as new again.
+        assert!(record(&audit, 4, "192.0.2.3:1", "alice"));
+        assert_eq!(audit.inner.lock().unwrap().recent.len(), 2);
+        assert!(record(&audit, 5, "192.0.2.1:4", "dave"));
+        audit.flush();
+
+        let entries = toppy_core::audit::query_time_range(&path, 0, u64::MAX).unwrap();
+        let actors: Vec<&str> = entries.iter().map(|e| e.event.actor.as_str()).collect();
+        assert_eq!(actors, ["alice", "alice", "alice", "dave"]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
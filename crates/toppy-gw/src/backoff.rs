@@ -0,0 +1,80 @@
+//! Delay between QUIC accepts after consecutive local accept failures, so a
+//! burst of errors (e.g. connection IDs exhausted) does not spin the loop and
+//! flood logs. Handshakes a client gets wrong do not count.
+
+use std::time::Duration;
+
+pub const ACCEPT_BACKOFF_BASE: Duration = Duration::from_millis(10);
+pub const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// Doubles from `base` per consecutive failure up to `max`; a success resets it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptBackoff {
+    base: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl AcceptBackoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            failures: 0,
+        }
+    }
+
+    /// Records a failed accept and returns the new delay.
+    pub fn failure(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        self.delay()
+    }
+
+    pub fn success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// How long to wait before the next accept; zero when the last one succeeded.
+    pub fn delay(&self) -> Duration {
+        if self.failures == 0 {
+            return Duration::ZERO;
+        }
+        let factor = 1u32.checked_shl(self.failures - 1).unwrap_or(u32::MAX);
+        self.base.saturating_mul(factor).min(self.max)
+    }
+}
+
+impl Default for AcceptBackoff {
+    fn default() -> Self {
+        Self::new(ACCEPT_BACKOFF_BASE, ACCEPT_BACKOFF_MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_caps_and_resets() {
+        let mut backoff = AcceptBackoff::new(Duration::from_millis(10), Duration::from_millis(50));
+        assert_eq!(backoff.delay(), Duration::ZERO);
+
+        let delays: Vec<u64> = (0..5)
+            .map(|_| backoff.failure().as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [10, 20, 40, 50, 50]);
+
+        backoff.success();
+        assert_eq!(backoff.delay(), Duration::ZERO);
+        assert_eq!(backoff.failure(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn backoff_stays_capped_after_many_failures() {
+        let mut backoff = AcceptBackoff::default();
+        for _ in 0..100 {
+            backoff.failure();
+        }
+        assert_eq!(backoff.delay(), ACCEPT_BACKOFF_MAX);
+    }
+}
@@ -0,0 +1,89 @@
+//! `GET /.well-known/toppy/capabilities` on the health server: what this
+//! gateway supports, so clients can choose how to connect beforehand. Only
+//! feature flags are reported, never credentials or policy contents.
+
+use serde::Serialize;
+use toppy_proto::{PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN};
+
+use crate::{env_flag, AuthMode, H3_ALPN};
+
+pub const CAPABILITIES_PATH: &str = "/.well-known/toppy/capabilities";
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// ALPN ids offered; clients sending none get the plain QUIC control protocol.
+    pub alpn: Vec<&'static str>,
+    pub zero_rtt: bool,
+    pub connect_udp: bool,
+    pub connect_ip: bool,
+    /// `none`, `token` or `jwt`.
+    pub auth_modes: Vec<&'static str>,
+    /// Control capsule versions accepted in `Hello`.
+    pub capsule_version: VersionRange,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl Capabilities {
+    pub fn new(auth: &AuthMode, zero_rtt: bool) -> Self {
+        let auth_mode = match auth {
+            AuthMode::None => "none",
+            AuthMode::SharedToken(_) => "token",
+            AuthMode::Jwt(_) => "jwt",
+        };
+        Self {
+            alpn: vec![H3_ALPN],
+            zero_rtt,
+            connect_udp: true,
+            connect_ip: false,
+            auth_modes: vec![auth_mode],
+            capsule_version: VersionRange {
+                min: PROTOCOL_VERSION_MIN,
+                max: PROTOCOL_VERSION_MAX,
+            },
+        }
+    }
+
+    /// Derived from the same `TOPPY_GW_*` variables the QUIC server reads.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self::new(
+            &AuthMode::from_env()?,
+            env_flag("TOPPY_GW_ENABLE_0RTT", false)?,
+        ))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_json_reports_config_flags() {
+        let caps = Capabilities::new(&AuthMode::SharedToken("dev-token".to_string()), true);
+        let json = caps.to_json();
+        assert!(!json.contains("dev-token"), "{}", json);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["alpn"], serde_json::json!(["h3"]));
+        assert_eq!(value["zero_rtt"], true);
+        assert_eq!(value["connect_udp"], true);
+        assert_eq!(value["connect_ip"], false);
+        assert_eq!(value["auth_modes"], serde_json::json!(["token"]));
+        assert_eq!(
+            value["capsule_version"],
+            serde_json::json!({ "min": PROTOCOL_VERSION_MIN, "max": PROTOCOL_VERSION_MAX })
+        );
+
+        let open = Capabilities::new(&AuthMode::None, false);
+        assert_eq!(open.auth_modes, ["none"]);
+        assert!(!open.zero_rtt);
+    }
+}
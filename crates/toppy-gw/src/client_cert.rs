@@ -0,0 +1,100 @@
+//! Identity of a TLS client certificate, used as the audit actor for its
+//! connection once mutual TLS is enabled.
+
+use rustls::pki_types::CertificateDer;
+use webpki::EndEntityCert;
+
+/// DER-encoded OID 2.5.4.3 (commonName).
+const OID_COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+
+/// Subject of the certificate the peer presented, if any.
+pub fn peer_subject(connection: &quinn::Connection) -> Option<String> {
+    let certs = connection
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    certs.first().and_then(cert_subject)
+}
+
+/// The subject CN, else the first DNS then URI subject alternative name.
+pub fn cert_subject(der: &CertificateDer<'_>) -> Option<String> {
+    let cert = EndEntityCert::try_from(der).ok()?;
+    common_name(cert.subject())
+        .or_else(|| cert.valid_dns_names().next().map(str::to_string))
+        .or_else(|| cert.valid_uri_names().next().map(str::to_string))
+}
+
+/// Finds the first commonName in the contents of an RDNSequence.
+fn common_name(mut rdns: &[u8]) -> Option<String> {
+    while !rdns.is_empty() {
+        let (_, mut attributes, rest) = der_next(rdns)?;
+        rdns = rest;
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = der_next(attributes)?;
+            attributes = rest;
+            let (tag, oid, value) = der_next(attribute)?;
+            if tag != 0x06 || oid != OID_COMMON_NAME {
+                continue;
+            }
+            // UTF8String, PrintableString or IA5String.
+            let (tag, value, _) = der_next(value)?;
+            if matches!(tag, 0x0c | 0x13 | 0x16) {
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Splits one DER element off `input` as (tag, contents, rest).
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let len = rest[..octets]
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, &rest[octets..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+
+    fn cert(common_name: Option<&str>, sans: &[&str]) -> CertificateDer<'static> {
+        let mut params =
+            CertificateParams::new(sans.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        if let Some(cn) = common_name {
+            params.distinguished_name.push(DnType::CommonName, cn);
+        }
+        let key = KeyPair::generate().unwrap();
+        params.self_signed(&key).unwrap().der().clone()
+    }
+
+    #[test]
+    fn cert_subject_prefers_common_name_then_san() {
+        let both = cert(Some("alice@example.com"), &["client.example.com"]);
+        assert_eq!(cert_subject(&both).as_deref(), Some("alice@example.com"));
+
+        let san_only = cert(None, &["client.example.com"]);
+        assert_eq!(
+            cert_subject(&san_only).as_deref(),
+            Some("client.example.com")
+        );
+
+        assert_eq!(cert_subject(&CertificateDer::from(vec![0x30, 0x03])), None);
+    }
+}
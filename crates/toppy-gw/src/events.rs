@@ -0,0 +1,200 @@
+//! In-memory ring of recent gateway events, served at `/debug/events` to admin-token holders.
+//!
+//! Events logged inside [`with_conn_id`] carry that connection's id, both in
+//! the ring and in the log line.
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use toppy_core::logging::{self, LogLevel};
+
+/// Number of events retained by the process-wide ring.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GwEvent {
+    /// Unix timestamp in milliseconds.
+    pub unix_ms: u64,
+    pub level: &'static str,
+    pub message: String,
+    /// Id of the QUIC connection the event belongs to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conn: Option<String>,
+}
+
+tokio::task_local! {
+    static CONN_ID: String;
+}
+
+/// Short random id (8 hex chars) assigned to a connection at accept time.
+pub fn new_conn_id() -> String {
+    let mut bytes = [0u8; 4];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        // Still unique within the process, just not random.
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        bytes = NEXT.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Runs `fut` with `id` attached to every event it logs.
+pub async fn with_conn_id<F: Future>(id: String, fut: F) -> F::Output {
+    CONN_ID.scope(id, fut).await
+}
+
+fn current_conn_id() -> Option<String> {
+    CONN_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Fixed-capacity buffer that overwrites the oldest event when full.
+#[derive(Debug)]
+pub struct EventRing {
+    capacity: usize,
+    events: VecDeque<GwEvent>,
+}
+
+impl EventRing {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, event: GwEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Returns the retained events, newest first.
+    pub fn snapshot(&self) -> Vec<GwEvent> {
+        self.events.iter().rev().cloned().collect()
+    }
+
+    pub fn snapshot_json(&self) -> String {
+        serde_json::to_string(&self.snapshot()).unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+fn ring() -> &'static Mutex<EventRing> {
+    static RING: OnceLock<Mutex<EventRing>> = OnceLock::new();
+    RING.get_or_init(|| Mutex::new(EventRing::new(DEFAULT_CAPACITY)))
+}
+
+fn now_unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn record(level: LogLevel, message: String) {
+    let conn = current_conn_id();
+    match &conn {
+        Some(id) => logging::log_with(level, &message, &[("conn", id)]),
+        None => logging::log(level, &message),
+    }
+    let event = GwEvent {
+        unix_ms: now_unix_ms(),
+        level: level.as_str(),
+        message,
+        conn,
+    };
+    ring().lock().unwrap_or_else(|e| e.into_inner()).push(event);
+}
+
+/// Logs through `toppy_core::logging` and records the event in the ring.
+pub fn info(message: impl Into<String>) {
+    record(LogLevel::Info, message.into());
+}
+
+/// Logs through `toppy_core::logging` and records the event in the ring.
+pub fn error(message: impl Into<String>) {
+    record(LogLevel::Error, message.into());
+}
+
+/// JSON array of the process-wide ring, newest first.
+pub fn snapshot_json() -> String {
+    ring()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .snapshot_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u64) -> GwEvent {
+        GwEvent {
+            unix_ms: n,
+            level: "info",
+            message: format!("event {}", n),
+            conn: None,
+        }
+    }
+
+    #[test]
+    fn ring_caps_at_capacity() {
+        let mut ring = EventRing::new(3);
+        for n in 0..5 {
+            ring.push(event(n));
+        }
+        let ids: Vec<u64> = ring.snapshot().iter().map(|e| e.unix_ms).collect();
+        assert_eq!(ids, vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn ring_snapshot_is_newest_first() {
+        let mut ring = EventRing::new(8);
+        ring.push(event(1));
+        ring.push(event(2));
+        let snapshot = ring.snapshot();
+        assert_eq!(snapshot[0].message, "event 2");
+        assert_eq!(snapshot[1].message, "event 1");
+
+        let json: serde_json::Value = serde_json::from_str(&ring.snapshot_json()).unwrap();
+        assert_eq!(json[0]["message"], "event 2");
+        assert_eq!(json[0]["level"], "info");
+    }
+
+    #[test]
+    fn events_in_connection_scope_share_its_id() {
+        let id = new_conn_id();
+        assert_eq!(id.len(), 8);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("runtime");
+        runtime.block_on(with_conn_id(id.clone(), async {
+            info("conn-id-test accepted");
+            error("conn-id-test token rejected");
+            info("conn-id-test closed");
+        }));
+        info("conn-id-test unscoped");
+
+        let events: Vec<GwEvent> = ring()
+            .lock()
+            .unwrap()
+            .snapshot()
+            .into_iter()
+            .filter(|e| e.message.starts_with("conn-id-test"))
+            .collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].conn, None);
+        for event in &events[1..] {
+            assert_eq!(
+                event.conn.as_deref(),
+                Some(id.as_str()),
+                "{}",
+                event.message
+            );
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Idle tracking and byte-rate limiting for CONNECT-UDP flows.
+//!
+//! UDP has no FIN, so a flow is reclaimed once no datagram has been relayed
+//! for `udp_flow_idle_secs`.
+
+use std::time::Duration;
+use tokio::time::Instant;
+use toppy_core::rate::TokenBucket;
+
+pub const DEFAULT_UDP_FLOW_IDLE_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IdleTimer {
+    timeout: Duration,
+    last_activity: Instant,
+}
+
+impl IdleTimer {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_activity: now,
+        }
+    }
+
+    /// Records activity, pushing the deadline out by a full timeout.
+    pub fn touch(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.last_activity + self.timeout
+    }
+
+    pub fn is_idle(&self, now: Instant) -> bool {
+        now >= self.deadline()
+    }
+}
+
+/// Caps a flow at `bytes_per_sec`, with up to one second of burst.
+///
+/// Datagrams over budget are dropped (UDP has no backpressure) and tallied so
+/// the excess can be logged when the flow ends.
+#[derive(Debug, Clone)]
+pub struct ByteLimiter {
+    bucket: TokenBucket,
+    started: Instant,
+    pub dropped_datagrams: u64,
+    pub dropped_bytes: u64,
+}
+
+impl ByteLimiter {
+    /// The bucket holds at least `max_datagram` bytes, so a rate below the
+    /// datagram size still lets full-size datagrams through (less often).
+    pub fn new(bytes_per_sec: u64, max_datagram: u64, now: Instant) -> Self {
+        Self {
+            bucket: TokenBucket::new(bytes_per_sec.max(max_datagram), bytes_per_sec),
+            started: now,
+            dropped_datagrams: 0,
+            dropped_bytes: 0,
+        }
+    }
+
+    /// Charges `len` bytes; returns `false` (and records the drop) when over budget.
+    pub fn allow(&mut self, len: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.started);
+        if self.bucket.try_take(len as u64, elapsed) {
+            return true;
+        }
+        self.dropped_datagrams += 1;
+        self.dropped_bytes += len as u64;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_flow_expires_while_active_flow_persists() {
+        let timeout = Duration::from_secs(30);
+        let start = Instant::now();
+        let idle = IdleTimer::new(timeout, start);
+        let mut active = IdleTimer::new(timeout, start);
+
+        for secs in [10, 20, 30, 40] {
+            active.touch(start + Duration::from_secs(secs));
+        }
+
+        let later = start + Duration::from_secs(45);
+        assert!(idle.is_idle(later));
+        assert!(!active.is_idle(later));
+        assert_eq!(active.deadline(), start + Duration::from_secs(70));
+    }
+
+    #[test]
+    fn byte_limiter_drops_over_budget_and_refills() {
+        let start = Instant::now();
+        let mut limiter = ByteLimiter::new(100, 10, start);
+
+        assert!(limiter.allow(60, start));
+        assert!(limiter.allow(40, start));
+        assert!(!limiter.allow(1, start));
+        assert!(!limiter.allow(50, start));
+        assert_eq!(limiter.dropped_datagrams, 2);
+        assert_eq!(limiter.dropped_bytes, 51);
+
+        let half_second = start + Duration::from_millis(500);
+        assert!(limiter.allow(50, half_second));
+        assert!(!limiter.allow(1, half_second));
+    }
+
+    #[test]
+    fn byte_limiter_passes_datagrams_larger_than_the_rate() {
+        let start = Instant::now();
+        let mut limiter = ByteLimiter::new(100, 1200, start);
+
+        assert!(limiter.allow(1200, start));
+        assert!(!limiter.allow(1200, start));
+        assert!(!limiter.allow(1200, start + Duration::from_secs(11)));
+        assert!(limiter.allow(1200, start + Duration::from_secs(12)));
+        assert_eq!(limiter.dropped_datagrams, 2);
+    }
+
+    #[test]
+    fn flow_is_not_idle_before_timeout() {
+        let start = Instant::now();
+        let timer = IdleTimer::new(Duration::from_secs(30), start);
+        assert!(!timer.is_idle(start + Duration::from_secs(29)));
+        assert!(timer.is_idle(start + Duration::from_secs(30)));
+    }
+}
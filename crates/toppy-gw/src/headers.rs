@@ -0,0 +1,105 @@
+//! Request headers required on CONNECT-UDP requests (`TOPPY_GW_REQUIRED_HEADERS`).
+//!
+//! Each rule is `name` (must be present) or `name=value` (must match exactly).
+//! The matched headers are logged with the flow and attached to its policy
+//! [`Target`](toppy_core::policy::Target), where rule `headers` can match them.
+
+use http::header::HeaderName;
+use http::HeaderMap;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRule {
+    name: HeaderName,
+    value: Option<String>,
+}
+
+/// Parses a comma-separated rule list; empty entries are ignored.
+pub fn parse_rules(spec: &str) -> Result<Vec<HeaderRule>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            let (name, value) = match rule.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+                None => (rule, None),
+            };
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("invalid header name {}: {}", name, e))?;
+            Ok(HeaderRule { name, value })
+        })
+        .collect()
+}
+
+/// Returns the headers named by `rules`, or why the request must be rejected.
+pub fn extract(
+    headers: &HeaderMap,
+    rules: &[HeaderRule],
+) -> Result<BTreeMap<String, String>, String> {
+    let mut matched = BTreeMap::new();
+    for rule in rules {
+        let value = headers
+            .get(&rule.name)
+            .ok_or_else(|| format!("missing header {}", rule.name))?
+            .to_str()
+            .map_err(|_| format!("header {} is not valid text", rule.name))?;
+        if let Some(expected) = &rule.value {
+            if value != expected {
+                return Err(format!("header {} has unexpected value", rule.name));
+            }
+        }
+        matched.insert(rule.name.to_string(), value.to_string());
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn extract_returns_allowlisted_headers() {
+        let rules = parse_rules("X-Tenant-Id, x-region=eu").unwrap();
+        let matched = extract(
+            &headers(&[
+                ("x-tenant-id", "acme"),
+                ("x-region", "eu"),
+                ("x-other", "ignored"),
+            ]),
+            &rules,
+        )
+        .unwrap();
+        assert_eq!(
+            matched.into_iter().collect::<Vec<_>>(),
+            [
+                ("x-region".to_string(), "eu".to_string()),
+                ("x-tenant-id".to_string(), "acme".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn extract_rejects_missing_or_mismatched_headers() {
+        let rules = parse_rules("x-tenant-id,x-region=eu").unwrap();
+        assert_eq!(
+            extract(&headers(&[("x-region", "eu")]), &rules).unwrap_err(),
+            "missing header x-tenant-id"
+        );
+        assert!(extract(
+            &headers(&[("x-tenant-id", "acme"), ("x-region", "us")]),
+            &rules
+        )
+        .unwrap_err()
+        .contains("x-region"));
+        assert!(extract(&HeaderMap::new(), &[]).unwrap().is_empty());
+        assert!(parse_rules("bad header").is_err());
+    }
+}
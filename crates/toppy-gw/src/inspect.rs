@@ -0,0 +1,128 @@
+//! Optional inspection of relayed CONNECT-UDP payloads.
+
+use std::env;
+use std::sync::Arc;
+use toppy_core::policy::Target;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InspectResult {
+    Allow,
+    /// Silently discard this datagram.
+    Drop,
+    /// Tear down the CONNECT-UDP stream.
+    Close(String),
+}
+
+/// Decides what happens to each UDP payload relayed for `target`.
+pub trait Inspector: Send + Sync {
+    fn inspect(&self, target: &Target, payload: &[u8]) -> InspectResult;
+}
+
+/// Default inspector: relays everything.
+pub struct NoopInspector;
+
+impl Inspector for NoopInspector {
+    fn inspect(&self, _target: &Target, _payload: &[u8]) -> InspectResult {
+        InspectResult::Allow
+    }
+}
+
+/// Drops payloads containing `needle`, or closes the stream on them.
+pub struct ContainsInspector {
+    needle: Vec<u8>,
+    close: bool,
+}
+
+impl ContainsInspector {
+    pub fn drop(needle: impl Into<Vec<u8>>) -> Self {
+        Self {
+            needle: needle.into(),
+            close: false,
+        }
+    }
+
+    pub fn close(needle: impl Into<Vec<u8>>) -> Self {
+        Self {
+            needle: needle.into(),
+            close: true,
+        }
+    }
+}
+
+impl Inspector for ContainsInspector {
+    fn inspect(&self, target: &Target, payload: &[u8]) -> InspectResult {
+        let matched =
+            !self.needle.is_empty() && payload.windows(self.needle.len()).any(|w| w == self.needle);
+        match (matched, self.close) {
+            (false, _) => InspectResult::Allow,
+            (true, false) => InspectResult::Drop,
+            (true, true) => InspectResult::Close(format!("blocked payload to {}", target)),
+        }
+    }
+}
+
+/// Selects the inspector from `TOPPY_GW_INSPECTOR`: unset or `none`,
+/// `drop-contains:<text>`, or `close-contains:<text>`.
+pub fn from_env() -> Result<Arc<dyn Inspector>, String> {
+    let spec = match env::var("TOPPY_GW_INSPECTOR") {
+        Ok(spec) => spec,
+        Err(_) => return Ok(Arc::new(NoopInspector)),
+    };
+    if spec == "none" {
+        return Ok(Arc::new(NoopInspector));
+    }
+    if let Some(needle) = spec
+        .strip_prefix("drop-contains:")
+        .filter(|n| !n.is_empty())
+    {
+        return Ok(Arc::new(ContainsInspector::drop(needle.as_bytes())));
+    }
+    if let Some(needle) = spec
+        .strip_prefix("close-contains:")
+        .filter(|n| !n.is_empty())
+    {
+        return Ok(Arc::new(ContainsInspector::close(needle.as_bytes())));
+    }
+    Err(format!("invalid TOPPY_GW_INSPECTOR {}", spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> Target {
+        Target::parse("127.0.0.1", 53).expect("target")
+    }
+
+    #[test]
+    fn noop_inspector_allows_all() {
+        let inspector = NoopInspector;
+        assert_eq!(inspector.inspect(&target(), b""), InspectResult::Allow);
+        assert_eq!(
+            inspector.inspect(&target(), b"anything"),
+            InspectResult::Allow
+        );
+    }
+
+    #[test]
+    fn drop_contains_inspector_drops_matching_payload() {
+        let inspector = ContainsInspector::drop(b"blocked.example".to_vec());
+        assert_eq!(
+            inspector.inspect(&target(), b"\x00query blocked.example A"),
+            InspectResult::Drop
+        );
+        assert_eq!(
+            inspector.inspect(&target(), b"\x00query allowed.example A"),
+            InspectResult::Allow
+        );
+    }
+
+    #[test]
+    fn close_contains_inspector_closes_stream() {
+        let inspector = ContainsInspector::close(b"blocked.example".to_vec());
+        assert!(matches!(
+            inspector.inspect(&target(), b"blocked.example"),
+            InspectResult::Close(reason) if reason.contains("127.0.0.1:53")
+        ));
+    }
+}
@@ -3,23 +3,70 @@ use quinn::ServerConfig;
 use rustls::pki_types::pem::{Error as PemError, PemObject};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
 use std::env;
-use std::fs;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 use tiny_http::{Header, Method, Response, Server, StatusCode};
-use toppy_core::auth::{validate_jwt_hs256, JwtConfig};
+use toppy_core::audit::SegmentPruner;
+use toppy_core::auth::{now_secs, validate_jwt_hs256_exp, JwtConfig, JWT_LEEWAY_SECS};
+use toppy_core::config;
+use toppy_core::policy::{decision_to_http_status, Decision, Policy, Target, TrafficKind};
+use toppy_proto::masque::{
+    max_udp_payload, parse_connect_udp_path, EncodeError, HttpDatagram, CONNECT_UDP_CONTEXT_ID,
+    DIAGNOSTIC_HEADER, DIAGNOSTIC_PROBE_HOST, DIAGNOSTIC_PROBE_PORT,
+};
+use toppy_proto::{
+    error_code, negotiate_version, Capsule, ControlMessage, CONTROL_HELLO, LEGACY_PONG,
+    LEGACY_UNAUTHORIZED,
+};
 
-use bytes::Bytes;
+use auth_audit::{AuthAudit, AUTH_AUDIT_WINDOW};
+use backoff::AcceptBackoff;
+use bytes::{Buf, Bytes};
+use capabilities::{Capabilities, CAPABILITIES_PATH};
+use flow::{ByteLimiter, IdleTimer};
 use h3::ext::Protocol;
 use h3_datagram::datagram_handler::HandleDatagramsExt;
 use http::StatusCode as HttpStatusCode;
+use inspect::{InspectResult, Inspector, NoopInspector};
+use ipnet::IpNet;
+use nat::{NatTable, DEFAULT_UDP_NAT_MAX};
+use session::TokenExpiry;
+use tls::{TlsPolicy, TLS_VERSIONS};
+
+mod admin;
+mod auth_audit;
+mod backoff;
+mod capabilities;
+mod client_cert;
+mod events;
+mod flow;
+mod headers;
+mod inspect;
+mod nat;
+mod proxy_protocol;
+mod session;
+mod tls;
+
+/// ALPN of the HTTP/3 (CONNECT-UDP) service; ALPN-less clients get the ping protocol.
+const H3_ALPN: &str = "h3";
+
+/// How often the audit log is checked for rotation under `TOPPY_GW_AUDIT_MAX_TOTAL_BYTES`.
+const AUDIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
 
 fn main() {
+    // The gateway has no config file; level/format come from TOPPY_LOG*.
+    if let Err(e) = toppy_core::logging::init(&toppy_core::config::Config::default()) {
+        eprintln!("invalid log settings: {}", e);
+        std::process::exit(1);
+    }
     let http_listen = env::var("TOPPY_GW_LISTEN").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
     let quic_listen =
         env::var("TOPPY_GW_QUIC_LISTEN").unwrap_or_else(|_| "0.0.0.0:4433".to_string());
+    let ping_listen = env::var("TOPPY_GW_PING_LISTEN").ok();
 
     let http_thread = thread::spawn(move || run_healthz(&http_listen));
 
@@ -27,12 +74,13 @@ fn main() {
         .enable_all()
         .build()
         .unwrap_or_else(|e| {
-            eprintln!("failed to start tokio runtime: {}", e);
+            events::error(format!("failed to start tokio runtime: {}", e));
             std::process::exit(1);
         });
     runtime.block_on(async move {
-        if let Err(e) = run_quic(&quic_listen).await {
-            eprintln!("quic server error: {}", e);
+        if let Err(e) = run_quic(&quic_listen, ping_listen.as_deref()).await {
+            events::error(format!("quic server error: {}", e));
+            std::process::exit(1);
         }
     });
 
@@ -40,14 +88,71 @@ fn main() {
 }
 
 fn run_healthz(listen: &str) {
-    let server = Server::http(listen).unwrap_or_else(|e| {
-        eprintln!("failed to start gateway on {}: {}", listen, e);
+    let proxy_protocol = env_flag("TOPPY_GW_PROXY_PROTOCOL", false).unwrap_or_else(|e| {
+        events::error(e);
         std::process::exit(1);
     });
+    // Behind PROXY protocol tiny_http only listens on loopback; `serve` owns `listen`.
+    let bind = if proxy_protocol {
+        "127.0.0.1:0"
+    } else {
+        listen
+    };
+    let capabilities = Capabilities::from_env()
+        .unwrap_or_else(|e| {
+            events::error(e);
+            std::process::exit(1);
+        })
+        .to_json();
+    let admin_token = env::var("TOPPY_GW_ADMIN_TOKEN").ok();
+    let server = Server::http(bind).unwrap_or_else(|e| {
+        events::error(format!("failed to start gateway on {}: {}", bind, e));
+        std::process::exit(1);
+    });
+    let clients = proxy_protocol::ClientAddrs::default();
+    if proxy_protocol {
+        let Some(backend) = server.server_addr().to_ip() else {
+            events::error("health server has no ip address");
+            std::process::exit(1);
+        };
+        let listen = listen.to_string();
+        let clients = clients.clone();
+        thread::spawn(move || {
+            if let Err(e) = proxy_protocol::serve(&listen, backend, clients) {
+                events::error(format!("failed to start gateway on {}: {}", listen, e));
+                std::process::exit(1);
+            }
+        });
+    }
 
-    println!("toppy-gw http listening on {}", listen);
+    events::info(format!(
+        "toppy-gw http listening on {}{}",
+        listen,
+        if proxy_protocol {
+            " (proxy protocol v2)"
+        } else {
+            ""
+        }
+    ));
 
     for request in server.incoming_requests() {
+        let client = request.remote_addr().copied().map(|addr| {
+            clients
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&addr)
+                .copied()
+                .unwrap_or(addr)
+        });
+        toppy_core::logging::log(
+            toppy_core::logging::LogLevel::Debug,
+            &format!(
+                "http {} {} from {}",
+                request.method(),
+                request.url(),
+                client.map_or("unknown".to_string(), |addr| addr.to_string())
+            ),
+        );
         if request.method() == &Method::Get && request.url() == "/healthz" {
             let mut response = Response::from_string("{\"status\":\"ok\"}\n");
             response.add_header(
@@ -58,11 +163,68 @@ fn run_healthz(listen: &str) {
             continue;
         }
 
+        if request.method() == &Method::Get && request.url() == CAPABILITIES_PATH {
+            let mut response = Response::from_string(capabilities.clone());
+            response.add_header(
+                Header::from_bytes("content-type", "application/json").expect("header"),
+            );
+            let _ = request.respond(response.with_status_code(StatusCode(200)));
+            continue;
+        }
+
+        if request.method() == &Method::Get && request.url() == "/debug/events" {
+            let authorization = request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("authorization"))
+                .map(|h| h.value.as_str());
+            let status = debug_events_status(admin_token.as_deref(), authorization);
+            if status != 200 {
+                let response = Response::from_string(if status == 404 {
+                    "not found\n"
+                } else {
+                    "unauthorized\n"
+                });
+                let _ = request.respond(response.with_status_code(StatusCode(status)));
+                continue;
+            }
+            let mut response = Response::from_string(events::snapshot_json());
+            response.add_header(
+                Header::from_bytes("content-type", "application/json").expect("header"),
+            );
+            response.add_header(Header::from_bytes("cache-control", "no-store").expect("header"));
+            let _ = request.respond(response.with_status_code(StatusCode(200)));
+            continue;
+        }
+
         let response = Response::from_string("not found\n").with_status_code(StatusCode(404));
         let _ = request.respond(response);
     }
 }
 
+/// HTTP status for a `/debug/events` request: the event ring is only served to
+/// callers presenting `TOPPY_GW_ADMIN_TOKEN` as a bearer token, and the endpoint
+/// does not exist when no admin token is configured.
+fn debug_events_status(admin_token: Option<&str>, authorization: Option<&str>) -> u16 {
+    let Some(expected) = admin_token else {
+        return 404;
+    };
+    match authorization.and_then(|v| v.strip_prefix("Bearer ")) {
+        Some(token) if admin::constant_time_eq(expected.as_bytes(), token.as_bytes()) => 200,
+        _ => 401,
+    }
+}
+
+/// Reads a boolean env var (`1`/`true` or `0`/`false`), using `default` when unset.
+fn env_flag(name: &str, default: bool) -> Result<bool, String> {
+    match env::var(name).as_deref() {
+        Ok("1") | Ok("true") => Ok(true),
+        Ok("0") | Ok("false") => Ok(false),
+        Ok(other) => Err(format!("invalid {} {}", name, other)),
+        Err(_) => Ok(default),
+    }
+}
+
 #[derive(Clone)]
 enum AuthMode {
     None,
@@ -78,120 +240,611 @@ impl AuthMode {
         let shared_token = env::var("TOPPY_GW_TOKEN").ok();
 
         if let Some(secret) = jwt_secret {
-            return Ok(AuthMode::Jwt(JwtConfig {
+            let require_exp = env_flag("TOPPY_GW_JWT_REQUIRE_EXP", true)?;
+            let max_token_age_secs =
+                match env::var("TOPPY_GW_JWT_MAX_AGE_SECS") {
+                    Ok(value) => Some(value.parse::<u64>().map_err(|e| {
+                        format!("invalid TOPPY_GW_JWT_MAX_AGE_SECS {}: {}", value, e)
+                    })?),
+                    Err(_) => None,
+                };
+            let cfg = JwtConfig {
                 secret,
                 issuer: jwt_issuer,
                 audience: jwt_audience,
-            }));
+                require_exp,
+                max_token_age_secs,
+            };
+            cfg.validate()
+                .map_err(|e| format!("invalid JWT config (TOPPY_GW_JWT_*): {}", e))?;
+            return Ok(AuthMode::Jwt(cfg));
+        }
+
+        if jwt_issuer.is_some() || jwt_audience.is_some() {
+            return Err(
+                "TOPPY_GW_JWT_ISS/TOPPY_GW_JWT_AUD are set but TOPPY_GW_JWT_SECRET is not"
+                    .to_string(),
+            );
         }
 
         if let Some(token) = shared_token {
             return Ok(AuthMode::SharedToken(token));
         }
 
+        if env_flag("TOPPY_GW_REQUIRE_AUTH", false)? {
+            return Err(
+                "TOPPY_GW_REQUIRE_AUTH is set but neither TOPPY_GW_TOKEN nor TOPPY_GW_JWT_SECRET is configured"
+                    .to_string(),
+            );
+        }
+
         Ok(AuthMode::None)
     }
 
-    fn validate(&self, token: Option<&str>) -> Result<(), String> {
+    /// Checks `token`, returning its expiry when it is a JWT carrying `exp`.
+    fn validate(&self, token: Option<&str>) -> Result<Option<TokenExpiry>, String> {
         match self {
-            AuthMode::None => Ok(()),
+            AuthMode::None => Ok(None),
             AuthMode::SharedToken(expected) => match token {
-                Some(value) if value == expected => Ok(()),
+                Some(value) if value == expected => Ok(None),
                 _ => Err("missing or invalid token".to_string()),
             },
             AuthMode::Jwt(cfg) => {
                 let token = token.ok_or_else(|| "missing jwt token".to_string())?;
-                validate_jwt_hs256(token, cfg)
+                let exp = validate_jwt_hs256_exp(token, cfg)?;
+                Ok(exp.map(|exp| TokenExpiry::new(exp, JWT_LEEWAY_SECS)))
             }
         }
     }
 }
 
-async fn run_quic(listen: &str) -> Result<(), String> {
-    let addr: SocketAddr = listen
+/// State shared by every QUIC connection handler.
+struct GwState {
+    auth_mode: AuthMode,
+    /// Token required for admin capsules; admin commands are disabled if unset.
+    admin_token: Option<String>,
+    /// Policy file (TOML, or JSON for `.json`) applied to CONNECT-UDP targets when set.
+    policy_path: Option<String>,
+    policy: RwLock<Option<Policy>>,
+    active_connections: AtomicU64,
+    total_connections: AtomicU64,
+    inspector: Arc<dyn Inspector>,
+    /// CONNECT-UDP flows with no datagrams for this long are closed.
+    udp_flow_idle: Duration,
+    /// Per-flow CONNECT-UDP byte-rate cap; unlimited when unset.
+    udp_bytes_per_sec: Option<u64>,
+    /// Close CONNECT-UDP streams once the authenticating JWT expires.
+    jwt_reauth: bool,
+    /// Server names clients may request via SNI; empty allows any.
+    allowed_sni: Vec<String>,
+    /// Source networks whose connections are accepted; empty allows any.
+    source_allow: Vec<IpNet>,
+    /// Headers every CONNECT-UDP request must carry.
+    required_headers: Vec<headers::HeaderRule>,
+    /// Largest UDP payload relayed in one HTTP datagram; larger ones are dropped.
+    max_datagram_size: usize,
+    /// Audit log receiving rejected authentication attempts.
+    auth_audit: Option<AuthAudit>,
+    /// Relays CONNECT-UDP payloads to their targets instead of echoing them.
+    udp_nat: Option<Arc<NatTable>>,
+}
+
+impl GwState {
+    fn new(auth_mode: AuthMode) -> Self {
+        Self {
+            auth_mode,
+            admin_token: None,
+            policy_path: None,
+            policy: RwLock::new(None),
+            active_connections: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            inspector: Arc::new(NoopInspector),
+            udp_flow_idle: Duration::from_secs(flow::DEFAULT_UDP_FLOW_IDLE_SECS),
+            udp_bytes_per_sec: None,
+            jwt_reauth: false,
+            allowed_sni: Vec::new(),
+            source_allow: Vec::new(),
+            required_headers: Vec::new(),
+            max_datagram_size: max_udp_payload(config::DEFAULT_MTU),
+            auth_audit: None,
+            udp_nat: None,
+        }
+    }
+
+    fn from_env() -> Result<Self, String> {
+        let mut state = Self::new(AuthMode::from_env()?);
+        state.admin_token = env::var("TOPPY_GW_ADMIN_TOKEN").ok();
+        state.policy_path = env::var("TOPPY_GW_POLICY").ok();
+        state.inspector = inspect::from_env()?;
+        state.jwt_reauth = env_flag("TOPPY_GW_JWT_REAUTH", false)?;
+        if let Ok(value) = env::var("TOPPY_GW_ALLOWED_SNI") {
+            state.allowed_sni = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Ok(value) = env::var("TOPPY_GW_SOURCE_ALLOW") {
+            state.source_allow = value
+                .split(',')
+                .map(str::trim)
+                .filter(|cidr| !cidr.is_empty())
+                .map(|cidr| {
+                    cidr.parse::<IpNet>()
+                        .map_err(|e| format!("invalid TOPPY_GW_SOURCE_ALLOW cidr {}: {}", cidr, e))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Ok(value) = env::var("TOPPY_GW_REQUIRED_HEADERS") {
+            state.required_headers = headers::parse_rules(&value)
+                .map_err(|e| format!("invalid TOPPY_GW_REQUIRED_HEADERS: {}", e))?;
+        }
+        if let Ok(value) = env::var("TOPPY_GW_UDP_FLOW_IDLE_SECS") {
+            let secs = value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or_else(|| format!("invalid TOPPY_GW_UDP_FLOW_IDLE_SECS {}", value))?;
+            state.udp_flow_idle = Duration::from_secs(secs);
+        }
+        if let Ok(value) = env::var("TOPPY_GW_UDP_BYTES_PER_SEC") {
+            let rate = value
+                .parse::<u64>()
+                .ok()
+                .filter(|rate| *rate > 0)
+                .ok_or_else(|| format!("invalid TOPPY_GW_UDP_BYTES_PER_SEC {}", value))?;
+            state.udp_bytes_per_sec = Some(rate);
+        }
+        if let Ok(value) = env::var("TOPPY_GW_MAX_DATAGRAM_SIZE") {
+            state.max_datagram_size = value
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| format!("invalid TOPPY_GW_MAX_DATAGRAM_SIZE {}", value))?;
+        }
+        if env_flag("TOPPY_GW_UDP_RELAY", false)? {
+            if state.policy_path.is_none() {
+                return Err(
+                    "TOPPY_GW_UDP_RELAY requires TOPPY_GW_POLICY; without one the gateway would relay anywhere"
+                        .to_string(),
+                );
+            }
+            let max_entries = match env::var("TOPPY_GW_UDP_NAT_MAX") {
+                Ok(value) => value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|max| *max > 0)
+                    .ok_or_else(|| format!("invalid TOPPY_GW_UDP_NAT_MAX {}", value))?,
+                Err(_) => DEFAULT_UDP_NAT_MAX,
+            };
+            state.udp_nat = Some(Arc::new(NatTable::new(max_entries)));
+        }
+        if let Ok(path) = env::var("TOPPY_GW_AUDIT_PATH") {
+            if let Ok(value) = env::var("TOPPY_GW_AUDIT_MAX_TOTAL_BYTES") {
+                let max_total = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|bytes| *bytes > 0)
+                    .ok_or_else(|| format!("invalid TOPPY_GW_AUDIT_MAX_TOTAL_BYTES {}", value))?;
+                let pruned = toppy_core::audit::prune_segments(&path, max_total)
+                    .map_err(|e| format!("prune audit log {} failed: {}", path, e))?;
+                log_pruned(pruned);
+                spawn_audit_pruner(SegmentPruner::new(&path, max_total));
+            }
+            state.auth_audit = Some(AuthAudit::open(&path, AUTH_AUDIT_WINDOW)?);
+        }
+        if state.policy_path.is_some() {
+            admin::reload_policy(&state)?;
+        }
+        Ok(state)
+    }
+
+    /// Validates `token` like [`AuthMode::validate`], auditing rejections
+    /// under the `client` certificate subject when there is one.
+    fn authenticate(
+        &self,
+        token: Option<&str>,
+        remote: SocketAddr,
+        client: Option<&str>,
+    ) -> Result<Option<TokenExpiry>, String> {
+        let result = self.auth_mode.validate(token);
+        if let (Err(reason), Some(audit)) = (&result, &self.auth_audit) {
+            audit.record_failure(client, token, remote, reason);
+        }
+        result
+    }
+
+    /// Evaluates a CONNECT-UDP target. Without a policy everything is allowed
+    /// in echo mode, and nothing when flows are relayed.
+    fn evaluate(&self, target: &Target, kind: TrafficKind) -> Decision {
+        match self
+            .policy
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+        {
+            Some(policy) => policy.evaluate_for(target, kind),
+            None if self.udp_nat.is_some() => Decision::Deny {
+                reason: format!("target {} denied: udp relay has no policy", target),
+            },
+            None => Decision::Allow { rule_index: None },
+        }
+    }
+}
+
+/// Requests carrying [`DIAGNOSTIC_HEADER`]`: 1` (doctor probes) are diagnostic,
+/// but only for the fixed probe target; the header on any other target is
+/// ignored so it cannot be used to get around the allow rules.
+fn traffic_kind(headers: &http::HeaderMap, target: &Target) -> TrafficKind {
+    let probe = Target::parse(DIAGNOSTIC_PROBE_HOST, DIAGNOSTIC_PROBE_PORT).ok();
+    match headers.get(DIAGNOSTIC_HEADER) {
+        Some(value) if value == "1" && probe.as_ref() == Some(target) => TrafficKind::Diagnostic,
+        _ => TrafficKind::User,
+    }
+}
+
+/// Accepts any source when `allow` is empty; IPv4-mapped IPv6 addresses
+/// (dual-stack sockets) are matched as IPv4.
+fn source_allowed(allow: &[IpNet], ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    allow.is_empty() || allow.iter().any(|net| net.contains(&ip))
+}
+
+/// Accepts any SNI when `allowed` is empty; otherwise the client's SNI must
+/// match an entry (case-insensitively). Guards against domain fronting.
+fn check_sni(allowed: &[String], sni: Option<&str>) -> Result<(), String> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+    match sni {
+        Some(name) if allowed.iter().any(|a| a.eq_ignore_ascii_case(name)) => Ok(()),
+        Some(name) => Err(format!("sni {} not allowed", name)),
+        None => Err("missing sni".to_string()),
+    }
+}
+
+/// Extracts the target from `/.well-known/masque/udp/{host}/{port}/`.
+///
+/// Only IP literals are supported.
+fn connect_udp_target(path: &str) -> Result<Target, String> {
+    let target = parse_connect_udp_path(path)
+        .map_err(|e| format!("invalid connect-udp path {}: {}", path, e))?;
+    Target::parse(&target.host, target.port)
+}
+
+/// Which QUIC listener a connection arrived on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QuicService {
+    /// The only listener: the negotiated ALPN picks the handler.
+    Combined,
+    /// `TOPPY_GW_QUIC_LISTEN` when `TOPPY_GW_PING_LISTEN` is set.
+    H3,
+    /// `TOPPY_GW_PING_LISTEN`.
+    Ping,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QuicHandler {
+    H3,
+    Ping,
+}
+
+impl QuicService {
+    /// ALPN ids the listener offers; the ping listener negotiates none.
+    fn alpn_protocols(self) -> Vec<Vec<u8>> {
+        match self {
+            QuicService::Combined | QuicService::H3 => vec![H3_ALPN.as_bytes().to_vec()],
+            QuicService::Ping => Vec::new(),
+        }
+    }
+
+    /// The handler for a connection that negotiated `alpn` on this listener.
+    fn route(self, alpn: Option<&[u8]>) -> Result<QuicHandler, String> {
+        let is_h3 = alpn == Some(H3_ALPN.as_bytes());
+        match self {
+            QuicService::Combined if is_h3 => Ok(QuicHandler::H3),
+            QuicService::Combined | QuicService::Ping => Ok(QuicHandler::Ping),
+            QuicService::H3 if is_h3 => Ok(QuicHandler::H3),
+            QuicService::H3 => Err(format!(
+                "h3 listener requires ALPN {}; send ping to TOPPY_GW_PING_LISTEN",
+                H3_ALPN
+            )),
+        }
+    }
+}
+
+fn parse_listen(name: &str, listen: &str) -> Result<SocketAddr, String> {
+    listen
         .parse()
-        .map_err(|e| format!("invalid quic listen {}: {}", listen, e))?;
+        .map_err(|e| format!("invalid {} {}: {}", name, listen, e))
+}
+
+/// Logs each audit segment the pruner deleted.
+fn log_pruned(pruned: Vec<std::path::PathBuf>) {
+    for segment in pruned {
+        events::info(format!("pruned audit segment {}", segment.display()));
+    }
+}
+
+/// Keeps the audit log under its cap as logrotate adds segments.
+fn spawn_audit_pruner(mut pruner: SegmentPruner) {
+    thread::spawn(move || loop {
+        thread::sleep(AUDIT_PRUNE_INTERVAL);
+        match pruner.check() {
+            Ok(pruned) => log_pruned(pruned),
+            Err(e) => events::error(format!("prune audit log failed: {}", e)),
+        }
+    });
+}
+
+/// Serves h3 and ping on `listen`, or on `listen` and `ping_listen`
+/// respectively when a separate ping listener is configured.
+async fn run_quic(listen: &str, ping_listen: Option<&str>) -> Result<(), String> {
+    let addr = parse_listen("quic listen", listen)?;
+    let ping_addr = ping_listen
+        .map(|ping_listen| parse_listen("ping listen", ping_listen))
+        .transpose()?;
     let cert_path = env::var("TOPPY_GW_CERT").ok();
     let key_path = env::var("TOPPY_GW_KEY").ok();
-    let auth_mode = AuthMode::from_env()?;
-    let server_config = build_quic_config(cert_path.as_deref(), key_path.as_deref())?;
-    let endpoint = quinn::Endpoint::server(server_config, addr)
-        .map_err(|e| format!("quic bind failed: {}", e))?;
+    let state = Arc::new(GwState::from_env()?);
+    let enable_0rtt = env_flag("TOPPY_GW_ENABLE_0RTT", false)?;
+    let tls = TlsPolicy::from_env()?;
+    // Built once so both listeners present the same certificate.
+    let rustls_cfg =
+        server_tls_config(cert_path.as_deref(), key_path.as_deref(), enable_0rtt, &tls)?;
+    let bind = |service: QuicService, addr: SocketAddr| {
+        let mut rustls_cfg = rustls_cfg.clone();
+        rustls_cfg.alpn_protocols = service.alpn_protocols();
+        quinn::Endpoint::server(build_quic_config(rustls_cfg)?, addr)
+            .map_err(|e| format!("quic bind {} failed: {}", addr, e))
+    };
 
-    println!("toppy-gw quic listening on {}", listen);
+    let Some(ping_addr) = ping_addr else {
+        let endpoint = bind(QuicService::Combined, addr)?;
+        events::info(format!("toppy-gw quic listening on {}", listen));
+        return serve_quic(endpoint, state, QuicService::Combined).await;
+    };
+    let h3_endpoint = bind(QuicService::H3, addr)?;
+    let ping_endpoint = bind(QuicService::Ping, ping_addr)?;
+    events::info(format!(
+        "toppy-gw quic listening on {} (h3) and {} (ping)",
+        addr, ping_addr
+    ));
+    tokio::try_join!(
+        serve_quic(h3_endpoint, state.clone(), QuicService::H3),
+        serve_quic(ping_endpoint, state, QuicService::Ping),
+    )?;
+    Ok(())
+}
 
-    while let Some(incoming) = endpoint.accept().await {
-        let auth_mode = auth_mode.clone();
-        tokio::spawn(async move {
+/// Accepts connections on `endpoint` until it is closed.
+async fn serve_quic(
+    endpoint: quinn::Endpoint,
+    state: Arc<GwState>,
+    service: QuicService,
+) -> Result<(), String> {
+    let backoff = Arc::new(Mutex::new(AcceptBackoff::default()));
+    loop {
+        let delay = backoff.lock().unwrap_or_else(|e| e.into_inner()).delay();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let Some(incoming) = endpoint.accept().await else {
+            break;
+        };
+        // Refused before the handshake so scanners cost no crypto work.
+        let remote = incoming.remote_address();
+        if !source_allowed(&state.source_allow, remote.ip()) {
+            events::error(format!("quic source {} not allowed", remote));
+            incoming.refuse();
+            continue;
+        }
+        let state = state.clone();
+        let backoff = backoff.clone();
+        let conn_id = events::new_conn_id();
+        tokio::spawn(events::with_conn_id(conn_id, async move {
+            events::info(format!("quic connection from {}", remote));
             match incoming.await {
                 Ok(connection) => {
-                    if let Err(e) = handle_connection(connection, auth_mode).await {
-                        eprintln!("quic connection error: {}", e);
+                    backoff.lock().unwrap_or_else(|e| e.into_inner()).success();
+                    state.total_connections.fetch_add(1, Ordering::Relaxed);
+                    state.active_connections.fetch_add(1, Ordering::Relaxed);
+                    let res = handle_connection(connection, state.clone(), service).await;
+                    state.active_connections.fetch_sub(1, Ordering::Relaxed);
+                    match res {
+                        Ok(()) => events::info("quic connection closed"),
+                        Err(e) => events::error(format!("quic connection error: {}", e)),
                     }
                 }
-                Err(e) => {
-                    eprintln!("quic accept failed: {}", e);
+                Err(e) if local_accept_failure(&e) => {
+                    let delay = backoff.lock().unwrap_or_else(|e| e.into_inner()).failure();
+                    events::error(format!(
+                        "quic accept failed: {} (next accept in {}ms)",
+                        e,
+                        delay.as_millis()
+                    ));
                 }
+                Err(e) => events::error(format!("quic handshake from {} failed: {}", remote, e)),
             }
-        });
+        }));
     }
 
     Ok(())
 }
 
+/// Whether a failed accept is the gateway's own problem rather than the
+/// client's; only these drive the accept backoff.
+fn local_accept_failure(err: &quinn::ConnectionError) -> bool {
+    matches!(
+        err,
+        quinn::ConnectionError::CidsExhausted | quinn::ConnectionError::LocallyClosed
+    )
+}
+
 async fn handle_connection(
     connection: quinn::Connection,
-    auth_mode: AuthMode,
+    state: Arc<GwState>,
+    service: QuicService,
 ) -> Result<(), String> {
-    let is_h3 = connection
+    let handshake = connection
         .handshake_data()
-        .and_then(|any| any.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
-        .and_then(|hs| hs.protocol)
-        .as_deref()
-        == Some(b"h3");
+        .and_then(|any| any.downcast::<quinn::crypto::rustls::HandshakeData>().ok());
+    let sni = handshake.as_ref().and_then(|hs| hs.server_name.as_deref());
+    if let Err(reason) = check_sni(&state.allowed_sni, sni) {
+        events::error(format!(
+            "closing connection from {}: {}",
+            connection.remote_address(),
+            reason
+        ));
+        connection.close(error_code::FORBIDDEN.into(), reason.as_bytes());
+        return Ok(());
+    }
+    let alpn = handshake.and_then(|hs| hs.protocol);
+    match service.route(alpn.as_deref()) {
+        Ok(QuicHandler::H3) => handle_h3_connection(connection, state).await,
+        Ok(QuicHandler::Ping) => handle_ping_connection(connection, state).await,
+        Err(reason) => {
+            events::error(format!(
+                "closing connection from {}: {}",
+                connection.remote_address(),
+                reason
+            ));
+            connection.close(error_code::BAD_REQUEST.into(), reason.as_bytes());
+            Ok(())
+        }
+    }
+}
+
+/// One client request read from a non-h3 control stream.
+#[derive(Debug, PartialEq, Eq)]
+enum ControlRead<'a> {
+    /// The client opened and finished the stream without sending anything.
+    Empty,
+    /// `ping`, optionally followed by a token.
+    Ping(Option<&'a str>),
+    /// Anything else; expected to be an admin capsule.
+    Capsule(&'a [u8]),
+}
 
-    if is_h3 {
-        handle_h3_connection(connection, auth_mode).await
+fn classify_control_read(data: &[u8]) -> ControlRead<'_> {
+    if data.is_empty() {
+        return ControlRead::Empty;
+    }
+    if !data.starts_with(b"ping") {
+        return ControlRead::Capsule(data);
+    }
+    let token = if data == b"ping" {
+        None
     } else {
-        handle_ping_connection(connection, auth_mode).await
+        data.strip_prefix(b"ping ")
+    };
+    ControlRead::Ping(
+        token
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .map(|value| value.trim()),
+    )
+}
+
+/// Answers a `Hello` capsule with the selected version, or the error to send.
+fn handle_hello(capsule: &Capsule) -> Result<u16, ControlMessage> {
+    match ControlMessage::from_capsule(capsule) {
+        Ok(ControlMessage::Hello { min, max }) => negotiate_version(min, max),
+        _ => Err(ControlMessage::bad_request()),
+    }
+}
+
+/// Reply to `ping`: a `Pong` or `Error` message once the connection negotiated
+/// a version via `Hello`, else the legacy raw bytes older clients expect.
+fn ping_reply(auth: Result<(), String>, version: Option<u16>) -> Vec<u8> {
+    match (auth, version) {
+        (Ok(()), Some(_)) => ControlMessage::Pong.encode(),
+        (Err(message), Some(_)) => ControlMessage::Error {
+            code: error_code::UNAUTHORIZED,
+            message,
+        }
+        .encode(),
+        (Ok(()), None) => LEGACY_PONG.to_vec(),
+        (Err(_), None) => LEGACY_UNAUTHORIZED.to_vec(),
     }
 }
 
+/// Early (0-RTT) data can be replayed by an attacker, so on streams opened in
+/// 0-RTT only the idempotent `ping` and `Hello` are answered; admin capsules
+/// are refused. Connections are only handed over once the handshake completes.
 async fn handle_ping_connection(
     connection: quinn::Connection,
-    auth_mode: AuthMode,
+    state: Arc<GwState>,
 ) -> Result<(), String> {
+    let remote = connection.remote_address();
+    let client = client_cert::peer_subject(&connection);
+    // Control protocol version negotiated via `Hello`, if the client sent one.
+    let mut version: Option<u16> = None;
     loop {
-        let (mut send, mut recv) = connection
-            .accept_bi()
-            .await
-            .map_err(|e| format!("quic stream accept failed: {}", e))?;
+        let (mut send, mut recv) = match connection.accept_bi().await {
+            Ok(streams) => streams,
+            // A client closing its connection is the normal end of a session.
+            Err(quinn::ConnectionError::ApplicationClosed(_))
+            | Err(quinn::ConnectionError::LocallyClosed) => return Ok(()),
+            Err(e) => return Err(format!("quic stream accept failed: {}", e)),
+        };
 
-        let data = recv
-            .read_to_end(256)
-            .await
-            .map_err(|e| format!("quic read failed: {}", e))?;
-        if !data.starts_with(b"ping") {
-            let _ = send.finish();
-            continue;
-        }
-        let token = if data == b"ping" {
-            None
-        } else {
-            data.strip_prefix(b"ping ")
+        let data = match recv.read_to_end(256).await {
+            Ok(data) => data,
+            Err(e) => {
+                // Only this stream is broken; keep serving the connection.
+                events::error(format!("control stream read failed: {}", e));
+                continue;
+            }
         };
-        let provided = token
-            .and_then(|value| std::str::from_utf8(value).ok())
-            .map(|value| value.trim());
-        if let Err(err) = auth_mode.validate(provided) {
-            eprintln!("token rejected: {}", err);
-            send.write_all(b"unauthorized")
-                .await
-                .map_err(|e| format!("quic write failed: {}", e))?;
-            let _ = send.finish();
-            continue;
-        }
-        send.write_all(b"pong")
+        let early = recv.is_0rtt();
+        let response = match classify_control_read(&data) {
+            ControlRead::Empty => {
+                events::info("control stream finished without data");
+                let _ = send.finish();
+                continue;
+            }
+            ControlRead::Capsule(data) => match Capsule::decode(data) {
+                Ok((capsule, _)) if capsule.kind == CONTROL_HELLO => match handle_hello(&capsule) {
+                    Ok(selected) => {
+                        events::info(format!("control protocol v{} negotiated", selected));
+                        version = Some(selected);
+                        ControlMessage::HelloAck { version: selected }.encode()
+                    }
+                    Err(reply) => {
+                        events::error(format!("control hello rejected: {:?}", reply));
+                        reply.encode()
+                    }
+                },
+                Ok((capsule, _)) if early => {
+                    events::error(format!(
+                        "control capsule {} refused in 0-RTT data",
+                        capsule.kind_name()
+                    ));
+                    ControlMessage::bad_request().encode()
+                }
+                Ok((capsule, _)) => {
+                    events::info(format!(
+                        "control capsule {} ({} bytes, protocol {})",
+                        capsule.kind_name(),
+                        capsule.payload.len(),
+                        version.map_or("unnegotiated".to_string(), |v| format!("v{}", v))
+                    ));
+                    admin::handle_admin(&state, &capsule).to_capsule().encode()
+                }
+                Err(_) => ControlMessage::bad_request().encode(),
+            },
+            ControlRead::Ping(provided) => {
+                let auth = state
+                    .authenticate(provided, remote, client.as_deref())
+                    .map(|_| ());
+                if let Err(err) = &auth {
+                    events::error(format!("token rejected: {}", err));
+                }
+                ping_reply(auth, version)
+            }
+        };
+        send.write_all(&response)
             .await
             .map_err(|e| format!("quic write failed: {}", e))?;
         let _ = send.finish();
@@ -200,8 +853,10 @@ async fn handle_ping_connection(
 
 async fn handle_h3_connection(
     connection: quinn::Connection,
-    auth_mode: AuthMode,
+    state: Arc<GwState>,
 ) -> Result<(), String> {
+    let remote = connection.remote_address();
+    let client = client_cert::peer_subject(&connection);
     let quinn_conn = h3_quinn::Connection::new(connection);
     let mut server_builder = h3::server::builder();
     server_builder.enable_extended_connect(true);
@@ -243,9 +898,63 @@ async fn handle_h3_connection(
         let token = authz
             .and_then(|v| v.strip_prefix("Bearer ").or(Some(v)))
             .map(|v| v.trim());
-        if let Err(err) = auth_mode.validate(token) {
+        let token_expiry = match state.authenticate(token, remote, client.as_deref()) {
+            Ok(expiry) => expiry.filter(|_| state.jwt_reauth),
+            Err(err) => {
+                let res = http::Response::builder()
+                    .status(HttpStatusCode::UNAUTHORIZED)
+                    .body(())
+                    .map_err(|e| format!("h3 response build failed: {e}"))?;
+                stream
+                    .send_response(res)
+                    .await
+                    .map_err(|e| format!("h3 send response failed: {e:?}"))?;
+                let _ = stream.finish().await;
+                events::error(format!("connect-udp unauthorized: {err}"));
+                continue;
+            }
+        };
+
+        let request_headers = match headers::extract(req.headers(), &state.required_headers) {
+            Ok(matched) => matched,
+            Err(err) => {
+                let res = http::Response::builder()
+                    .status(HttpStatusCode::BAD_REQUEST)
+                    .body(())
+                    .map_err(|e| format!("h3 response build failed: {e}"))?;
+                stream
+                    .send_response(res)
+                    .await
+                    .map_err(|e| format!("h3 send response failed: {e:?}"))?;
+                let _ = stream.finish().await;
+                events::error(format!("connect-udp bad request: {err}"));
+                continue;
+            }
+        };
+
+        let target = match connect_udp_target(req.uri().path()) {
+            Ok(target) => target.with_headers(request_headers.clone()),
+            Err(err) => {
+                let res = http::Response::builder()
+                    .status(HttpStatusCode::BAD_REQUEST)
+                    .body(())
+                    .map_err(|e| format!("h3 response build failed: {e}"))?;
+                stream
+                    .send_response(res)
+                    .await
+                    .map_err(|e| format!("h3 send response failed: {e:?}"))?;
+                let _ = stream.finish().await;
+                events::error(format!("connect-udp bad request: {err}"));
+                continue;
+            }
+        };
+
+        let kind = traffic_kind(req.headers(), &target);
+        let decision = state.evaluate(&target, kind);
+        let status = decision_to_http_status(&decision);
+        if status != HttpStatusCode::OK {
             let res = http::Response::builder()
-                .status(HttpStatusCode::UNAUTHORIZED)
+                .status(status)
                 .body(())
                 .map_err(|e| format!("h3 response build failed: {e}"))?;
             stream
@@ -253,13 +962,53 @@ async fn handle_h3_connection(
                 .await
                 .map_err(|e| format!("h3 send response failed: {e:?}"))?;
             let _ = stream.finish().await;
-            eprintln!("connect-udp unauthorized: {err}");
+            if let Decision::Deny { reason } = &decision {
+                events::error(format!("connect-udp denied: {reason}"));
+            }
             continue;
         }
 
+        // Diagnostic probes are always echoed, never relayed.
+        let nat = state.udp_nat.as_ref().filter(|_| kind == TrafficKind::User);
+        if let Some(refused) = nat.and_then(|_| nat::refused_target(target.ip)) {
+            let res = http::Response::builder()
+                .status(HttpStatusCode::FORBIDDEN)
+                .body(())
+                .map_err(|e| format!("h3 response build failed: {e}"))?;
+            stream
+                .send_response(res)
+                .await
+                .map_err(|e| format!("h3 send response failed: {e:?}"))?;
+            let _ = stream.finish().await;
+            events::error(format!(
+                "connect-udp denied: relay to {} target {} refused",
+                refused, target
+            ));
+            continue;
+        }
+        let relay = match nat {
+            Some(nat) => match nat.open(SocketAddr::new(target.ip, target.port)).await {
+                Ok(binding) => Some(binding),
+                Err(err) => {
+                    let res = http::Response::builder()
+                        .status(HttpStatusCode::SERVICE_UNAVAILABLE)
+                        .body(())
+                        .map_err(|e| format!("h3 response build failed: {e}"))?;
+                    stream
+                        .send_response(res)
+                        .await
+                        .map_err(|e| format!("h3 send response failed: {e:?}"))?;
+                    let _ = stream.finish().await;
+                    events::error(format!("connect-udp relay unavailable: {err}"));
+                    continue;
+                }
+            },
+            None => None,
+        };
+
         // Minimal CONNECT-UDP handshake: accept the request.
         let res = http::Response::builder()
-            .status(HttpStatusCode::OK)
+            .status(status)
             .body(())
             .map_err(|e| format!("h3 response build failed: {e}"))?;
         stream
@@ -267,22 +1016,152 @@ async fn handle_h3_connection(
             .await
             .map_err(|e| format!("h3 send response failed: {e:?}"))?;
 
-        // Datagram echo for this CONNECT-UDP stream: any datagram associated with this
-        // request stream is echoed back verbatim.
+        if !request_headers.is_empty() {
+            let tags: Vec<String> = request_headers
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            events::info(format!(
+                "connect-udp flow {} opened ({})",
+                target,
+                tags.join(", ")
+            ));
+        }
+
+        if let Some(relay) = relay.as_ref() {
+            events::info(format!(
+                "connect-udp flow {} relayed from port {}",
+                target,
+                relay.port()
+            ));
+        }
+
+        // Datagrams for this CONNECT-UDP stream are relayed to the target when
+        // TOPPY_GW_UDP_RELAY is set and echoed back verbatim otherwise, unless
+        // the inspector drops them.
         let stream_id = stream.id();
         let mut dg_sender = h3_conn.get_datagram_sender(stream_id);
         let mut dg_reader = h3_conn.get_datagram_reader();
 
+        let mut idle = IdleTimer::new(state.udp_flow_idle, tokio::time::Instant::now());
+        let mut reauth_deadline =
+            token_expiry.map(|expiry| expiry.deadline(now_secs(), tokio::time::Instant::now()));
+        let mut limiter = state.udp_bytes_per_sec.map(|rate| {
+            ByteLimiter::new(
+                rate,
+                state.max_datagram_size as u64,
+                tokio::time::Instant::now(),
+            )
+        });
+        let mut oversize_dropped = 0u64;
+        let mut relay_buf = vec![0u8; u16::MAX as usize];
+
         loop {
             tokio::select! {
+                _ = tokio::time::sleep_until(idle.deadline()) => {
+                    if !idle.is_idle(tokio::time::Instant::now()) {
+                        continue;
+                    }
+                    events::info(format!(
+                        "connect-udp flow {} closed after {}s idle",
+                        target,
+                        state.udp_flow_idle.as_secs()
+                    ));
+                    break;
+                }
+                _ = async {
+                    match reauth_deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if let Some(expiry) = token_expiry.filter(|e| !e.is_expired(now_secs())) {
+                        // The wall clock moved; re-arm rather than close early.
+                        reauth_deadline =
+                            Some(expiry.deadline(now_secs(), tokio::time::Instant::now()));
+                        continue;
+                    }
+                    events::info(format!("connect-udp flow {} closed: token expired", target));
+                    let _ = stream
+                        .send_data(Bytes::from(ControlMessage::unauthorized().encode()))
+                        .await;
+                    break;
+                }
                 dg = dg_reader.read_datagram() => {
                     let dg = dg.map_err(|e| format!("h3 recv datagram failed: {e:?}"))?;
                     if dg.stream_id() != stream_id {
                         continue;
                     }
-                    let payload = dg.into_payload();
+                    idle.touch(tokio::time::Instant::now());
+                    let mut payload = dg.into_payload();
+                    let payload = payload.copy_to_bytes(payload.remaining());
+                    if let Some(limiter) = limiter.as_mut() {
+                        if !limiter.allow(payload.len(), tokio::time::Instant::now()) {
+                            continue;
+                        }
+                    }
+                    // Inspect the UDP payload, i.e. what follows the context ID.
+                    let (context_id, udp_payload) = match HttpDatagram::decode_ref(&payload) {
+                        Ok((context_id, udp_payload, _)) => (context_id, udp_payload),
+                        Err(err) => {
+                            events::error(format!("connect-udp datagram dropped: {}", err));
+                            continue;
+                        }
+                    };
+                    let verdict = if udp_payload.len() > state.max_datagram_size {
+                        oversize_dropped += 1;
+                        InspectResult::Drop
+                    } else {
+                        state.inspector.inspect(&target, udp_payload)
+                    };
+                    match verdict {
+                        InspectResult::Allow => match relay.as_ref() {
+                            Some(relay) if context_id == CONNECT_UDP_CONTEXT_ID => {
+                                // Send errors (e.g. an earlier ICMP unreachable) lose only this datagram.
+                                let _ = relay.send(udp_payload).await;
+                            }
+                            Some(_) => {}
+                            None => {
+                                dg_sender
+                                    .send_datagram(payload)
+                                    .map_err(|e| format!("h3 send datagram failed: {e}"))?;
+                            }
+                        },
+                        InspectResult::Drop => {}
+                        InspectResult::Close(reason) => {
+                            events::info(format!("connect-udp closed by inspector: {reason}"));
+                            break;
+                        }
+                    }
+                }
+                received = async {
+                    match relay.as_ref() {
+                        Some(relay) => relay.recv(&mut relay_buf).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let len = match received {
+                        Ok(len) => len,
+                        // The target is not listening (yet); keep the flow open.
+                        Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => continue,
+                        Err(err) => {
+                            events::error(format!("connect-udp relay {} recv failed: {err}", target));
+                            break;
+                        }
+                    };
+                    idle.touch(tokio::time::Instant::now());
+                    let datagram = match HttpDatagram::new(CONNECT_UDP_CONTEXT_ID, &relay_buf[..len])
+                        .encode_bounded(state.max_datagram_size)
+                    {
+                        Ok(datagram) => datagram,
+                        Err(EncodeError::TooLarge { .. }) => {
+                            oversize_dropped += 1;
+                            continue;
+                        }
+                        Err(e) => return Err(format!("h3 datagram encode failed: {e:?}")),
+                    };
                     dg_sender
-                        .send_datagram(payload)
+                        .send_datagram(Bytes::from(datagram))
                         .map_err(|e| format!("h3 send datagram failed: {e}"))?;
                 }
                 chunk = stream.recv_data() => {
@@ -295,6 +1174,21 @@ async fn handle_h3_connection(
                 }
             }
         }
+        if let Some(limiter) = limiter.filter(|l| l.dropped_datagrams > 0) {
+            events::info(format!(
+                "connect-udp flow {} exceeded {} B/s: dropped {} datagram(s), {} bytes",
+                target,
+                state.udp_bytes_per_sec.unwrap_or_default(),
+                limiter.dropped_datagrams,
+                limiter.dropped_bytes
+            ));
+        }
+        if oversize_dropped > 0 {
+            events::info(format!(
+                "connect-udp flow {} dropped {} datagram(s) over {} bytes",
+                target, oversize_dropped, state.max_datagram_size
+            ));
+        }
         let _ = stream.finish().await;
     }
 
@@ -302,7 +1196,8 @@ async fn handle_h3_connection(
 }
 
 fn load_cert_chain(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
-    let data = fs::read(path).map_err(|e| format!("failed to read cert {}: {}", path, e))?;
+    let data = config::read_bounded(Path::new(path), config::max_cert_bytes()?)
+        .map_err(|e| format!("cert: {}", e))?;
     let certs = CertificateDer::pem_slice_iter(&data)
         .collect::<Result<Vec<_>, _>>()
         .map_err(|e| format!("failed to parse certs {}: {}", path, e))?;
@@ -313,7 +1208,8 @@ fn load_cert_chain(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
 }
 
 fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
-    let data = fs::read(path).map_err(|e| format!("failed to read key {}: {}", path, e))?;
+    let data = config::read_bounded(Path::new(path), config::max_cert_bytes()?)
+        .map_err(|e| format!("key: {}", e))?;
     match PrivateKeyDer::from_pem_slice(&data) {
         Ok(key) => Ok(key),
         Err(PemError::NoItemsFound) => Err(format!("no private key found in {}", path)),
@@ -321,10 +1217,29 @@ fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
     }
 }
 
-fn build_quic_config(
+/// Builds the QUIC server config around the TLS config from [`server_tls_config`].
+fn build_quic_config(rustls_cfg: rustls::ServerConfig) -> Result<ServerConfig, String> {
+    let crypto = QuicServerConfig::try_from(rustls_cfg)
+        .map_err(|e| format!("quic server crypto config failed: {e}"))?;
+    let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    let mut transport = quinn::TransportConfig::default();
+    transport.max_idle_timeout(Some(
+        Duration::from_secs(10)
+            .try_into()
+            .map_err(|_| "invalid idle timeout".to_string())?,
+    ));
+    server_config.transport = Arc::new(transport);
+    Ok(server_config)
+}
+
+/// Builds the listener's TLS config. With `enable_0rtt`, resumed clients may
+/// send early data; see [`handle_ping_connection`] for what it may carry.
+fn server_tls_config(
     cert_path: Option<&str>,
     key_path: Option<&str>,
-) -> Result<ServerConfig, String> {
+    enable_0rtt: bool,
+    tls: &TlsPolicy,
+) -> Result<rustls::ServerConfig, String> {
     let (cert_chain, key) = match (cert_path, key_path) {
         (Some(cert_path), Some(key_path)) => {
             (load_cert_chain(cert_path)?, load_private_key(key_path)?)
@@ -348,21 +1263,498 @@ fn build_quic_config(
         }
     };
 
-    let mut rustls_cfg = rustls::ServerConfig::builder()
+    let mut rustls_cfg = rustls::ServerConfig::builder_with_provider(Arc::new(tls.provider()))
+        .with_protocol_versions(TLS_VERSIONS)
+        .map_err(|e| format!("tls config failed: {e}"))?
         .with_no_client_auth()
         .with_single_cert(cert_chain, key)
         .map_err(|e| e.to_string())?;
     // Enable HTTP/3 ALPN. Non-H3 clients can still connect without ALPN.
-    rustls_cfg.alpn_protocols = vec![b"h3".to_vec()];
-    let crypto = QuicServerConfig::try_from(rustls_cfg)
-        .map_err(|e| format!("quic server crypto config failed: {e}"))?;
-    let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
-    let mut transport = quinn::TransportConfig::default();
-    transport.max_idle_timeout(Some(
-        Duration::from_secs(10)
-            .try_into()
-            .map_err(|_| "invalid idle timeout".to_string())?,
-    ));
-    server_config.transport = Arc::new(transport);
-    Ok(server_config)
+    rustls_cfg.alpn_protocols = vec![H3_ALPN.as_bytes().to_vec()];
+    if enable_0rtt {
+        // QUIC only allows 0 or u32::MAX.
+        rustls_cfg.max_early_data_size = u32::MAX;
+    }
+    Ok(rustls_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_udp_flow_closes_after_idle_timeout() {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut server_tls =
+            rustls::ServerConfig::builder_with_provider(Arc::new(TlsPolicy::default().provider()))
+                .with_protocol_versions(TLS_VERSIONS)
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der())),
+                )
+                .unwrap();
+        server_tls.alpn_protocols = QuicService::H3.alpn_protocols();
+        let server = quinn::Endpoint::server(
+            build_quic_config(server_tls).unwrap(),
+            "127.0.0.1:0".parse().unwrap(),
+        )
+        .unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let mut state = GwState::new(AuthMode::SharedToken("dev-token".to_string()));
+        state.udp_flow_idle = Duration::from_millis(300);
+        tokio::spawn(serve_quic(server, Arc::new(state), QuicService::H3));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let mut client_tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(TLS_VERSIONS)
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        client_tls.alpn_protocols = vec![H3_ALPN.as_bytes().to_vec()];
+        let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(client_tls).unwrap(),
+        )));
+        let connection = client
+            .connect(server_addr, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let (h3_conn, mut sender) = h3::client::builder()
+            .enable_extended_connect(true)
+            .enable_datagram(true)
+            .build::<_, _, Bytes>(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+
+        let target = toppy_proto::masque::UdpTarget::new("127.0.0.1", 9);
+        let mut req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri(format!("https://localhost{}", target.to_path()))
+            .header("authorization", "Bearer dev-token")
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(Protocol::CONNECT_UDP);
+        let mut stream = sender.send_request(req).await.unwrap();
+        let resp = stream.recv_response().await.unwrap();
+        assert_eq!(resp.status(), HttpStatusCode::OK);
+
+        // An echoed datagram halfway through restarts the idle timer.
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let touched = tokio::time::Instant::now();
+        let mut dg_sender = h3_conn.get_datagram_sender(stream.id());
+        let mut dg_reader = h3_conn.get_datagram_reader();
+        dg_sender
+            .send_datagram(Bytes::from_static(b"\x00ping"))
+            .unwrap();
+        let echoed = dg_reader.read_datagram().await.unwrap();
+        assert_eq!(echoed.stream_id(), stream.id());
+
+        let end = tokio::time::timeout(Duration::from_secs(5), stream.recv_data())
+            .await
+            .expect("flow closed by the gateway")
+            .unwrap();
+        assert!(end.is_none());
+        assert!(touched.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn debug_events_require_the_admin_token() {
+        assert_eq!(debug_events_status(None, None), 404);
+        assert_eq!(debug_events_status(None, Some("Bearer admin")), 404);
+        assert_eq!(debug_events_status(Some("admin"), None), 401);
+        assert_eq!(debug_events_status(Some("admin"), Some("admin")), 401);
+        assert_eq!(
+            debug_events_status(Some("admin"), Some("Bearer wrong")),
+            401
+        );
+        assert_eq!(
+            debug_events_status(Some("admin"), Some("Bearer admin")),
+            200
+        );
+    }
+
+    const AUTH_VARS: [&str; 5] = [
+        "TOPPY_GW_JWT_SECRET",
+        "TOPPY_GW_JWT_ISS",
+        "TOPPY_GW_JWT_AUD",
+        "TOPPY_GW_TOKEN",
+        "TOPPY_GW_REQUIRE_AUTH",
+    ];
+
+    fn with_auth_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+        let _guard = toppy_core::test_support::ENV_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let prev: Vec<_> = AUTH_VARS.iter().map(|k| (*k, env::var(k).ok())).collect();
+        for key in AUTH_VARS {
+            env::remove_var(key);
+        }
+        for (key, value) in vars {
+            env::set_var(key, value);
+        }
+        let out = f();
+        for (key, value) in prev {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn load_cert_chain_reads_testdata_cert() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost-cert.pem");
+        assert!(!load_cert_chain(path).expect("cert chain").is_empty());
+    }
+
+    #[test]
+    fn source_allowed_matches_cidrs() {
+        let allow: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()];
+        assert!(source_allowed(&allow, "10.1.2.3".parse().unwrap()));
+        assert!(source_allowed(&allow, "::ffff:10.1.2.3".parse().unwrap()));
+        assert!(source_allowed(&allow, "::1".parse().unwrap()));
+        assert!(!source_allowed(&allow, "192.168.1.1".parse().unwrap()));
+        assert!(source_allowed(&[], "192.168.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn check_sni_enforces_allow_list() {
+        let allowed = vec!["gw.example".to_string(), "localhost".to_string()];
+        assert!(check_sni(&allowed, Some("gw.example")).is_ok());
+        assert!(check_sni(&allowed, Some("LOCALHOST")).is_ok());
+        assert!(check_sni(&allowed, Some("front.example"))
+            .unwrap_err()
+            .contains("front.example"));
+        assert!(check_sni(&allowed, None).is_err());
+        assert!(check_sni(&[], Some("anything.example")).is_ok());
+        assert!(check_sni(&[], None).is_ok());
+    }
+
+    #[test]
+    fn handle_hello_selects_version_or_rejects() {
+        let hello = ControlMessage::hello().to_capsule();
+        assert_eq!(handle_hello(&hello), Ok(toppy_proto::PROTOCOL_VERSION_MAX));
+
+        let too_new = ControlMessage::Hello {
+            min: u16::MAX - 1,
+            max: u16::MAX,
+        };
+        assert!(matches!(
+            handle_hello(&too_new.to_capsule()),
+            Err(ControlMessage::Error {
+                code: error_code::UNSUPPORTED_VERSION,
+                ..
+            })
+        ));
+        assert_eq!(
+            handle_hello(&Capsule::new(CONTROL_HELLO, vec![1])),
+            Err(ControlMessage::bad_request())
+        );
+    }
+
+    #[test]
+    fn control_read_classifies_payloads() {
+        assert_eq!(classify_control_read(b""), ControlRead::Empty);
+        assert_eq!(classify_control_read(b"ping"), ControlRead::Ping(None));
+        assert_eq!(
+            classify_control_read(b"ping dev-token\n"),
+            ControlRead::Ping(Some("dev-token"))
+        );
+        assert_eq!(
+            classify_control_read(&[0x4c, 0x01, 0x00]),
+            ControlRead::Capsule(&[0x4c, 0x01, 0x00])
+        );
+    }
+
+    #[test]
+    fn ping_reply_is_structured_only_after_hello() {
+        let rejected = || Err("token expired".to_string());
+        assert_eq!(ping_reply(Ok(()), None), b"pong");
+        assert_eq!(ping_reply(rejected(), None), b"unauthorized");
+        assert_eq!(
+            ControlMessage::decode_ping_reply(&ping_reply(Ok(()), Some(1))),
+            Ok(ControlMessage::Pong)
+        );
+        assert_eq!(
+            ControlMessage::decode_ping_reply(&ping_reply(rejected(), Some(1))),
+            Ok(ControlMessage::Error {
+                code: error_code::UNAUTHORIZED,
+                message: "token expired".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn auth_from_env_errors_when_required_and_missing() {
+        let res = with_auth_env(&[("TOPPY_GW_REQUIRE_AUTH", "1")], AuthMode::from_env);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn auth_from_env_allows_open_by_default() {
+        let res = with_auth_env(&[], AuthMode::from_env);
+        assert!(matches!(res, Ok(AuthMode::None)));
+    }
+
+    #[test]
+    fn auth_from_env_rejects_empty_jwt_secret() {
+        let res = with_auth_env(&[("TOPPY_GW_JWT_SECRET", "")], AuthMode::from_env);
+        assert!(matches!(res, Err(err) if err.contains("secret must not be empty")));
+    }
+
+    #[test]
+    fn auth_from_env_rejects_issuer_without_secret() {
+        let res = with_auth_env(&[("TOPPY_GW_JWT_ISS", "toppy")], AuthMode::from_env);
+        assert!(matches!(res, Err(err) if err.contains("TOPPY_GW_JWT_SECRET")));
+    }
+
+    #[test]
+    fn auth_from_env_accepts_valid_jwt_config() {
+        let res = with_auth_env(
+            &[
+                ("TOPPY_GW_JWT_SECRET", "dev-secret"),
+                ("TOPPY_GW_JWT_ISS", "toppy"),
+                ("TOPPY_GW_JWT_AUD", "toppy-gw"),
+            ],
+            AuthMode::from_env,
+        );
+        assert!(matches!(res, Ok(AuthMode::Jwt(cfg)) if cfg.issuer.as_deref() == Some("toppy")));
+    }
+
+    #[test]
+    fn auth_from_env_required_with_token() {
+        let res = with_auth_env(
+            &[
+                ("TOPPY_GW_REQUIRE_AUTH", "1"),
+                ("TOPPY_GW_TOKEN", "dev-token"),
+            ],
+            AuthMode::from_env,
+        );
+        assert!(matches!(res, Ok(AuthMode::SharedToken(_))));
+    }
+
+    #[test]
+    fn early_data_is_enabled_only_with_0rtt() {
+        let policy = TlsPolicy::default();
+        let tls = server_tls_config(None, None, true, &policy).unwrap();
+        assert_eq!(tls.max_early_data_size, u32::MAX);
+        assert!(build_quic_config(tls).is_ok());
+        let tls = server_tls_config(None, None, false, &policy).unwrap();
+        assert_eq!(tls.max_early_data_size, 0);
+    }
+
+    #[test]
+    fn separate_listeners_route_each_protocol_to_its_handler() {
+        let h3 = Some(H3_ALPN.as_bytes());
+
+        // One listener: ALPN decides.
+        assert_eq!(QuicService::Combined.route(h3), Ok(QuicHandler::H3));
+        assert_eq!(QuicService::Combined.route(None), Ok(QuicHandler::Ping));
+
+        // Two listeners: each only serves its own protocol.
+        assert_eq!(QuicService::H3.route(h3), Ok(QuicHandler::H3));
+        assert!(QuicService::H3.route(None).is_err());
+        assert_eq!(QuicService::Ping.route(None), Ok(QuicHandler::Ping));
+        assert_eq!(QuicService::H3.alpn_protocols(), [H3_ALPN.as_bytes()]);
+        assert!(QuicService::Ping.alpn_protocols().is_empty());
+
+        // The ping listener's TLS config still builds without any ALPN.
+        let mut tls = server_tls_config(None, None, false, &TlsPolicy::default()).unwrap();
+        tls.alpn_protocols = QuicService::Ping.alpn_protocols();
+        assert!(build_quic_config(tls).is_ok());
+    }
+
+    #[test]
+    fn tls_config_offers_only_configured_suites() {
+        let suites = |tls: &rustls::ServerConfig| -> Vec<&str> {
+            tls.crypto_provider()
+                .cipher_suites
+                .iter()
+                .map(tls::suite_name)
+                .collect()
+        };
+        let default = server_tls_config(None, None, false, &TlsPolicy::default()).unwrap();
+        assert_eq!(
+            suites(&default),
+            [
+                "TLS13_AES_256_GCM_SHA384",
+                "TLS13_AES_128_GCM_SHA256",
+                "TLS13_CHACHA20_POLY1305_SHA256"
+            ]
+        );
+
+        let policy = TlsPolicy::parse(Some(
+            "TLS13_AES_128_GCM_SHA256, tls13_chacha20_poly1305_sha256",
+        ))
+        .unwrap();
+        let tls = server_tls_config(None, None, false, &policy).unwrap();
+        assert_eq!(
+            suites(&tls),
+            ["TLS13_AES_128_GCM_SHA256", "TLS13_CHACHA20_POLY1305_SHA256"]
+        );
+        assert!(build_quic_config(tls).is_ok());
+
+        let err = TlsPolicy::parse(Some("TLS13_AES_128_GCM_SHA256,TLS_RSA_WITH_RC4_128_SHA"))
+            .unwrap_err();
+        assert_eq!(err, "unknown TLS 1.3 cipher suite TLS_RSA_WITH_RC4_128_SHA");
+        // TLS 1.2 suites cannot be negotiated over QUIC.
+        assert!(TlsPolicy::parse(Some("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256")).is_err());
+        assert!(TlsPolicy::parse(Some("TLS13_AES_256_GCM_SHA384"))
+            .unwrap_err()
+            .contains("must include TLS13_AES_128_GCM_SHA256"));
+    }
+
+    #[test]
+    fn diagnostic_probe_bypasses_policy_only_when_allowed() {
+        let state = GwState::new(AuthMode::SharedToken("dev-token".to_string()));
+        let policy = |allow_diagnostics| Policy {
+            allow: vec![toppy_core::policy::PolicyRule::parse("10.0.0.0/8", vec![22]).unwrap()],
+            deny: Vec::new(),
+            allow_diagnostics,
+        };
+        let target = Target::parse("127.0.0.1", 9).unwrap();
+        let mut probe = http::HeaderMap::new();
+        probe.insert(DIAGNOSTIC_HEADER, "1".parse().unwrap());
+        assert_eq!(traffic_kind(&probe, &target), TrafficKind::Diagnostic);
+        assert_eq!(
+            traffic_kind(&http::HeaderMap::new(), &target),
+            TrafficKind::User
+        );
+
+        *state.policy.write().unwrap() = Some(policy(true));
+        assert_eq!(
+            state.evaluate(&target, traffic_kind(&probe, &target)),
+            Decision::Allow { rule_index: None }
+        );
+        assert!(matches!(
+            state.evaluate(&target, TrafficKind::User),
+            Decision::Deny { .. }
+        ));
+
+        *state.policy.write().unwrap() = Some(policy(false));
+        assert!(matches!(
+            state.evaluate(&target, traffic_kind(&probe, &target)),
+            Decision::Deny { .. }
+        ));
+    }
+
+    #[test]
+    fn required_headers_reach_the_policy() {
+        let mut state = GwState::new(AuthMode::SharedToken("dev-token".to_string()));
+        state.required_headers = headers::parse_rules("x-tenant-id").unwrap();
+        let rule = toppy_core::policy::PolicyRule::parse("10.0.0.0/8", vec![22])
+            .unwrap()
+            .with_headers(&std::collections::BTreeMap::from([(
+                "x-tenant-id".to_string(),
+                "acme".to_string(),
+            )]))
+            .unwrap();
+        *state.policy.write().unwrap() = Some(Policy {
+            allow: vec![rule],
+            deny: Vec::new(),
+            allow_diagnostics: false,
+        });
+        let decide = |tenant: &'static str| {
+            let mut request = http::HeaderMap::new();
+            request.insert("x-tenant-id", tenant.parse().unwrap());
+            let matched = headers::extract(&request, &state.required_headers).unwrap();
+            let target = Target::parse("10.0.0.5", 22).unwrap().with_headers(matched);
+            state.evaluate(&target, TrafficKind::User)
+        };
+        assert!(matches!(decide("acme"), Decision::Allow { .. }));
+        assert!(matches!(decide("other"), Decision::Deny { .. }));
+    }
+
+    #[test]
+    fn diagnostic_header_does_not_exempt_other_targets() {
+        let state = GwState::new(AuthMode::SharedToken("dev-token".to_string()));
+        *state.policy.write().unwrap() = Some(Policy {
+            allow: vec![toppy_core::policy::PolicyRule::parse("10.0.0.0/8", vec![22]).unwrap()],
+            deny: Vec::new(),
+            allow_diagnostics: true,
+        });
+        let mut probe = http::HeaderMap::new();
+        probe.insert(DIAGNOSTIC_HEADER, "1".parse().unwrap());
+        for other in [
+            Target::parse("192.0.2.7", 53).unwrap(),
+            Target::parse("127.0.0.1", 22).unwrap(),
+        ] {
+            let kind = traffic_kind(&probe, &other);
+            assert_eq!(kind, TrafficKind::User, "{}", other);
+            assert!(matches!(
+                state.evaluate(&other, kind),
+                Decision::Deny { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn only_local_accept_failures_back_off() {
+        assert!(local_accept_failure(&quinn::ConnectionError::CidsExhausted));
+        assert!(local_accept_failure(&quinn::ConnectionError::LocallyClosed));
+        assert!(!local_accept_failure(&quinn::ConnectionError::TimedOut));
+        assert!(!local_accept_failure(&quinn::ConnectionError::Reset));
+        assert!(!local_accept_failure(
+            &quinn::ConnectionError::VersionMismatch
+        ));
+    }
+
+    #[test]
+    fn udp_relay_without_policy_denies_everything() {
+        let mut state = GwState::new(AuthMode::None);
+        let target = Target::parse("192.0.2.7", 53).unwrap();
+        assert_eq!(
+            state.evaluate(&target, TrafficKind::User),
+            Decision::Allow { rule_index: None }
+        );
+        state.udp_nat = Some(Arc::new(NatTable::new(DEFAULT_UDP_NAT_MAX)));
+        assert!(matches!(
+            state.evaluate(&target, TrafficKind::User),
+            Decision::Deny { .. }
+        ));
+
+        let _env = toppy_core::test_support::scoped_env(&[
+            ("TOPPY_GW_UDP_RELAY", Some("1")),
+            ("TOPPY_GW_POLICY", None),
+        ]);
+        let err = GwState::from_env().err().expect("relay without policy");
+        assert!(err.contains("requires TOPPY_GW_POLICY"), "{}", err);
+    }
+
+    #[test]
+    fn rejected_token_appends_deny_audit_entry() {
+        let path = env::temp_dir().join(format!("toppy-gw-auth-deny-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut state = GwState::new(AuthMode::SharedToken("dev-token".to_string()));
+        state.auth_audit =
+            Some(AuthAudit::open(path.to_str().unwrap(), AUTH_AUDIT_WINDOW).unwrap());
+        let remote: SocketAddr = "198.51.100.4:40000".parse().unwrap();
+
+        assert!(state.authenticate(Some("dev-token"), remote, None).is_ok());
+        assert!(state.authenticate(Some("wrong"), remote, None).is_err());
+        state.auth_audit.as_ref().unwrap().flush();
+
+        let entries = toppy_core::audit::query_time_range(&path, 0, u64::MAX).unwrap();
+        assert_eq!(entries.len(), 1);
+        let event = &entries[0].event;
+        assert_eq!(
+            (
+                event.actor.as_str(),
+                event.action.as_str(),
+                event.target.as_str()
+            ),
+            ("anonymous", "auth", "198.51.100.4:40000")
+        );
+        assert!(!event.allowed);
+        assert_eq!(event.reason.as_deref(), Some("missing or invalid token"));
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,170 @@
+//! Source-address translation for relayed CONNECT-UDP flows
+//! (`TOPPY_GW_UDP_RELAY=1`). Every flow sends from its own ephemeral port,
+//! connected to its target, so replies are delivered only to the stream that
+//! caused them even when several clients talk to the same destination.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+pub const DEFAULT_UDP_NAT_MAX: usize = 4096;
+
+/// Why the relay refuses `ip` whatever the policy says: the gateway's own
+/// loopback and link-local networks are never reachable through it.
+pub fn refused_target(ip: IpAddr) -> Option<&'static str> {
+    let ip = ip.to_canonical();
+    if ip.is_loopback() || ip.is_unspecified() {
+        return Some("loopback");
+    }
+    let link_local = match ip {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    };
+    link_local.then_some("link-local")
+}
+
+/// Bounds the number of relay sockets open at once.
+pub struct NatTable {
+    max_entries: usize,
+    open: AtomicUsize,
+}
+
+impl NatTable {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            open: AtomicUsize::new(0),
+        }
+    }
+
+    /// Binds a socket connected to `target`; fails when the table is full.
+    pub async fn open(self: &Arc<Self>, target: SocketAddr) -> Result<NatBinding, String> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < self.max_entries).then_some(open + 1)
+            })
+            .map_err(|_| format!("udp nat table full ({} flows)", self.max_entries))?;
+        // Released by the binding's drop, or right here if binding fails.
+        let slot = Slot(Arc::clone(self));
+        let bind = match target {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .map_err(|e| format!("udp relay bind failed: {e}"))?;
+        socket
+            .connect(target)
+            .await
+            .map_err(|e| format!("udp relay connect {} failed: {e}", target))?;
+        let port = socket
+            .local_addr()
+            .map_err(|e| format!("udp relay local addr failed: {e}"))?
+            .port();
+        Ok(NatBinding {
+            socket,
+            port,
+            _slot: slot,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+}
+
+/// One counted entry of a [`NatTable`].
+struct Slot(Arc<NatTable>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// One flow's relay socket; its table entry is released on drop.
+pub struct NatBinding {
+    socket: UdpSocket,
+    port: u16,
+    _slot: Slot,
+}
+
+impl NatBinding {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub async fn send(&self, payload: &[u8]) -> io::Result<usize> {
+        self.socket.send(payload).await
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.recv(buf).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_flows_to_one_target_receive_only_their_replies() {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, from)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..n], from).await;
+            }
+        });
+
+        let table = Arc::new(NatTable::new(2));
+        let a = table.open(target).await.unwrap();
+        let b = table.open(target).await.unwrap();
+        assert_ne!(a.port(), b.port());
+        let full = table.open(target).await;
+        assert!(matches!(full, Err(err) if err.contains("table full")));
+
+        for _ in 0..3 {
+            a.send(b"from-a").await.unwrap();
+            b.send(b"from-b").await.unwrap();
+        }
+        let mut buf = [0u8; 64];
+        for _ in 0..3 {
+            let n = a.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"from-a");
+            let n = b.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"from-b");
+        }
+
+        drop(a);
+        assert_eq!(table.len(), 1);
+        assert!(table.open(target).await.is_ok());
+    }
+
+    #[test]
+    fn loopback_and_link_local_targets_are_refused() {
+        for ip in ["127.0.0.1", "::1", "0.0.0.0", "::ffff:127.0.0.2"] {
+            assert_eq!(
+                refused_target(ip.parse().unwrap()),
+                Some("loopback"),
+                "{}",
+                ip
+            );
+        }
+        for ip in ["169.254.169.254", "fe80::1", "::ffff:169.254.0.1"] {
+            assert_eq!(
+                refused_target(ip.parse().unwrap()),
+                Some("link-local"),
+                "{}",
+                ip
+            );
+        }
+        for ip in ["10.0.0.5", "192.0.2.7", "2001:db8::1", "fec0::1"] {
+            assert_eq!(refused_target(ip.parse().unwrap()), None, "{}", ip);
+        }
+    }
+}
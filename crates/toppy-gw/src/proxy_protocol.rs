@@ -0,0 +1,239 @@
+//! PROXY protocol v2 in front of the HTTP health server
+//! (`TOPPY_GW_PROXY_PROTOCOL=1`).
+//!
+//! tiny_http cannot be handed a pre-read stream, so [`serve`] accepts on the
+//! public address, strips the header and relays the rest of the connection to
+//! a loopback tiny_http listener. The real client address is kept in
+//! [`ClientAddrs`], keyed by the relay's address as tiny_http sees it.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::events;
+
+pub const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// How long a client may take to send its header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Relay address (as seen by tiny_http) to the client address it stands for.
+pub type ClientAddrs = Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyHeader {
+    /// Sent by the balancer itself (e.g. health probes); no client address.
+    Local,
+    Proxied {
+        source: SocketAddr,
+        destination: SocketAddr,
+    },
+}
+
+/// Parses a complete v2 header at the start of `buf`, returning it and its
+/// length in bytes. TLVs are skipped.
+pub fn parse_v2(buf: &[u8]) -> Result<(ProxyHeader, usize), String> {
+    if buf.len() < 16 {
+        return Err("truncated proxy header".to_string());
+    }
+    if buf[..12] != SIGNATURE {
+        return Err("missing proxy protocol v2 signature".to_string());
+    }
+    let (version, command) = (buf[12] >> 4, buf[12] & 0x0f);
+    if version != 2 {
+        return Err(format!("unsupported proxy protocol version {}", version));
+    }
+    let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    let body = buf.get(16..len).ok_or("truncated proxy header")?;
+    match command {
+        0x0 => return Ok((ProxyHeader::Local, len)),
+        0x1 => {}
+        other => return Err(format!("unknown proxy command {:#x}", other)),
+    }
+
+    let header = match buf[13] >> 4 {
+        // AF_INET
+        0x1 => {
+            let addrs = body.get(..12).ok_or("short ipv4 address block")?;
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    addrs[at],
+                    addrs[at + 1],
+                    addrs[at + 2],
+                    addrs[at + 3],
+                ))
+            };
+            let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+            ProxyHeader::Proxied {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10)),
+            }
+        }
+        // AF_INET6
+        0x2 => {
+            let addrs = body.get(..36).ok_or("short ipv6 address block")?;
+            let ip = |at: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addrs[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+            ProxyHeader::Proxied {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34)),
+            }
+        }
+        // AF_UNSPEC / AF_UNIX carry no usable IP address.
+        0x0 | 0x3 => ProxyHeader::Local,
+        other => return Err(format!("unknown proxy address family {:#x}", other)),
+    };
+    Ok((header, len))
+}
+
+/// Reads exactly one v2 header from `stream`, leaving the payload unread.
+pub fn read_v2(stream: &mut impl Read) -> Result<ProxyHeader, String> {
+    let mut buf = vec![0u8; 16];
+    stream
+        .read_exact(&mut buf)
+        .map_err(|e| format!("read proxy header failed: {}", e))?;
+    if buf[..12] != SIGNATURE {
+        return Err("missing proxy protocol v2 signature".to_string());
+    }
+    let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    buf.resize(16 + len, 0);
+    stream
+        .read_exact(&mut buf[16..])
+        .map_err(|e| format!("read proxy header failed: {}", e))?;
+    parse_v2(&buf).map(|(header, _)| header)
+}
+
+/// Accepts PROXY-prefixed connections on `listen` and relays them to `backend`.
+pub fn serve(listen: &str, backend: SocketAddr, clients: ClientAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                events::error(format!("proxy protocol accept failed: {}", e));
+                continue;
+            }
+        };
+        let clients = clients.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay(stream, backend, &clients) {
+                events::error(format!("proxy protocol: {}", e));
+            }
+        });
+    }
+    Ok(())
+}
+
+fn relay(mut client: TcpStream, backend: SocketAddr, clients: &ClientAddrs) -> Result<(), String> {
+    let peer = client.peer_addr().map_err(|e| e.to_string())?;
+    client
+        .set_read_timeout(Some(HEADER_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let header = read_v2(&mut client).map_err(|e| format!("{} from {}", e, peer))?;
+    client.set_read_timeout(None).map_err(|e| e.to_string())?;
+    let source = match header {
+        ProxyHeader::Proxied { source, .. } => source,
+        ProxyHeader::Local => peer,
+    };
+
+    let upstream =
+        TcpStream::connect(backend).map_err(|e| format!("backend connect failed: {}", e))?;
+    let relay_addr = upstream.local_addr().map_err(|e| e.to_string())?;
+    clients
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(relay_addr, source);
+
+    let result = copy_both(client, upstream);
+    clients
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&relay_addr);
+    result.map_err(|e| format!("relay for {} failed: {}", source, e))
+}
+
+fn copy_both(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let (mut client_read, mut upstream_write) = (client.try_clone()?, upstream.try_clone()?);
+    let inbound = thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_read, mut client_write) = (upstream, client);
+    let _ = io::copy(&mut upstream_read, &mut client_write);
+    let _ = client_write.shutdown(Shutdown::Write);
+    let _ = inbound.join();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(ver_cmd: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = SIGNATURE.to_vec();
+        buf.extend_from_slice(&[ver_cmd, family]);
+        buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    #[test]
+    fn parse_v2_extracts_ipv4_source() {
+        let mut body = vec![203, 0, 113, 9, 10, 0, 0, 1];
+        body.extend_from_slice(&51234u16.to_be_bytes());
+        body.extend_from_slice(&8080u16.to_be_bytes());
+        body.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]); // NOOP TLV
+        let mut buf = header(0x21, 0x11, &body);
+        buf.extend_from_slice(b"GET /healthz HTTP/1.1\r\n");
+
+        let (parsed, len) = parse_v2(&buf).expect("header");
+        assert_eq!(
+            parsed,
+            ProxyHeader::Proxied {
+                source: "203.0.113.9:51234".parse().unwrap(),
+                destination: "10.0.0.1:8080".parse().unwrap(),
+            }
+        );
+        assert_eq!(&buf[len..], b"GET /healthz HTTP/1.1\r\n");
+
+        let mut reader = io::Cursor::new(buf);
+        assert_eq!(read_v2(&mut reader).expect("read"), parsed);
+        assert_eq!(reader.position() as usize, len);
+    }
+
+    #[test]
+    fn parse_v2_handles_ipv6_and_local() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&443u16.to_be_bytes());
+        body.extend_from_slice(&8080u16.to_be_bytes());
+        let (parsed, _) = parse_v2(&header(0x21, 0x21, &body)).expect("header");
+        assert!(matches!(
+            parsed,
+            ProxyHeader::Proxied { source, .. } if source == "[2001:db8::7]:443".parse().unwrap()
+        ));
+        assert_eq!(
+            parse_v2(&header(0x20, 0x00, &[])).expect("local").0,
+            ProxyHeader::Local
+        );
+    }
+
+    #[test]
+    fn parse_v2_rejects_malformed_headers() {
+        assert!(parse_v2(b"GET /healthz HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse_v2(&header(0x11, 0x11, &[0; 12])).is_err());
+        assert!(parse_v2(&header(0x2f, 0x11, &[0; 12])).is_err());
+        assert!(parse_v2(&header(0x21, 0x11, &[0; 4])).is_err());
+        let mut truncated = header(0x21, 0x11, &[0; 12]);
+        truncated.truncate(20);
+        assert!(parse_v2(&truncated).is_err());
+    }
+}
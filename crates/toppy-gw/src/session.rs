@@ -0,0 +1,56 @@
+//! Expiry tracking for sessions authenticated with a JWT.
+//!
+//! A token is only checked when a CONNECT-UDP request arrives, so with
+//! `TOPPY_GW_JWT_REAUTH` the relay closes the stream once the token's `exp`
+//! (plus leeway) passes and the client must reconnect with a fresh token.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenExpiry {
+    exp: u64,
+    leeway: u64,
+}
+
+impl TokenExpiry {
+    pub fn new(exp: u64, leeway: u64) -> Self {
+        Self { exp, leeway }
+    }
+
+    pub fn is_expired(&self, now_secs: u64) -> bool {
+        now_secs > self.exp.saturating_add(self.leeway)
+    }
+
+    /// Maps the expiry onto the monotonic clock, given the current wall time.
+    pub fn deadline(&self, now_secs: u64, now: Instant) -> Instant {
+        let remaining = self
+            .exp
+            .saturating_add(self.leeway)
+            .saturating_sub(now_secs);
+        // One extra second so the deadline lands strictly after expiry.
+        now + Duration::from_secs(remaining + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_honours_leeway() {
+        let expiry = TokenExpiry::new(1_000, 60);
+        assert!(!expiry.is_expired(999));
+        assert!(!expiry.is_expired(1_060));
+        assert!(expiry.is_expired(1_061));
+    }
+
+    #[test]
+    fn deadline_tracks_remaining_lifetime() {
+        let now = Instant::now();
+        let expiry = TokenExpiry::new(1_000, 60);
+        assert_eq!(expiry.deadline(900, now), now + Duration::from_secs(161));
+        // Already expired: close on the next tick.
+        assert_eq!(expiry.deadline(5_000, now), now + Duration::from_secs(1));
+    }
+}
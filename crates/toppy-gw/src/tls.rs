@@ -0,0 +1,76 @@
+//! TLS constraints for the QUIC listener (`TOPPY_GW_TLS_CIPHERS`). QUIC
+//! always runs TLS 1.3, so the listener is TLS 1.3-only and by default offers
+//! every TLS 1.3 suite of the ring provider.
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::{CipherSuite, SupportedCipherSuite, SupportedProtocolVersion};
+use std::env;
+
+/// Protocol versions the listener negotiates.
+pub const TLS_VERSIONS: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+
+#[derive(Debug, Clone)]
+pub struct TlsPolicy {
+    /// Suites offered, in server preference order.
+    pub cipher_suites: Vec<SupportedCipherSuite>,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        Self {
+            cipher_suites: tls13_suites().collect(),
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// `ciphers` is a comma-separated list of TLS 1.3 suite names such as
+    /// `TLS13_AES_256_GCM_SHA384`.
+    pub fn parse(ciphers: Option<&str>) -> Result<Self, String> {
+        let Some(ciphers) = ciphers else {
+            return Ok(Self::default());
+        };
+        let cipher_suites = ciphers
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                tls13_suites()
+                    .find(|suite| suite_name(suite).eq_ignore_ascii_case(name))
+                    .ok_or_else(|| format!("unknown TLS 1.3 cipher suite {}", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // QUIC protects its initial packets with this suite.
+        if !cipher_suites
+            .iter()
+            .any(|suite| suite.suite() == CipherSuite::TLS13_AES_128_GCM_SHA256)
+        {
+            return Err("cipher suites must include TLS13_AES_128_GCM_SHA256 for QUIC".to_string());
+        }
+        Ok(Self { cipher_suites })
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let ciphers = env::var("TOPPY_GW_TLS_CIPHERS").ok();
+        Self::parse(ciphers.as_deref()).map_err(|e| format!("invalid TOPPY_GW_TLS_CIPHERS: {}", e))
+    }
+
+    /// The ring provider restricted to [`TlsPolicy::cipher_suites`].
+    pub fn provider(&self) -> CryptoProvider {
+        CryptoProvider {
+            cipher_suites: self.cipher_suites.clone(),
+            ..ring::default_provider()
+        }
+    }
+}
+
+fn tls13_suites() -> impl Iterator<Item = SupportedCipherSuite> {
+    ring::default_provider()
+        .cipher_suites
+        .into_iter()
+        .filter(|suite| matches!(suite, SupportedCipherSuite::Tls13(_)))
+}
+
+pub fn suite_name(suite: &SupportedCipherSuite) -> &'static str {
+    suite.suite().as_str().unwrap_or("unknown")
+}
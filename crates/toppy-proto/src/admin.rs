@@ -0,0 +1,88 @@
+//! Admin capsules carried on a gateway control stream.
+//!
+//! A request capsule's payload is the admin token; the gateway answers with
+//! a single response capsule whose payload is a JSON document.
+
+use crate::masque::{DecodeError, InvalidReason};
+use crate::Capsule;
+
+pub const STATS_REQUEST: u16 = 0x0a01;
+pub const RELOAD_POLICY: u16 = 0x0a02;
+pub const ADMIN_OK: u16 = 0x0a81;
+pub const ADMIN_ERROR: u16 = 0x0a82;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminRequest {
+    StatsRequest,
+    ReloadPolicy,
+}
+
+impl AdminRequest {
+    pub fn kind(self) -> u16 {
+        match self {
+            AdminRequest::StatsRequest => STATS_REQUEST,
+            AdminRequest::ReloadPolicy => RELOAD_POLICY,
+        }
+    }
+
+    pub fn from_kind(kind: u16) -> Option<Self> {
+        match kind {
+            STATS_REQUEST => Some(AdminRequest::StatsRequest),
+            RELOAD_POLICY => Some(AdminRequest::ReloadPolicy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminCommand {
+    pub request: AdminRequest,
+    pub token: String,
+}
+
+impl AdminCommand {
+    pub fn new(request: AdminRequest, token: impl Into<String>) -> Self {
+        Self {
+            request,
+            token: token.into(),
+        }
+    }
+
+    pub fn to_capsule(&self) -> Capsule {
+        Capsule::new(self.request.kind(), self.token.as_bytes())
+    }
+
+    pub fn from_capsule(capsule: &Capsule) -> Result<Self, DecodeError> {
+        let request = AdminRequest::from_kind(capsule.kind).ok_or(DecodeError::Invalid(
+            InvalidReason::UnexpectedKind(capsule.kind),
+        ))?;
+        let token = String::from_utf8_lossy(&capsule.payload).into_owned();
+        Ok(Self { request, token })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminResponse {
+    /// JSON result document.
+    Ok(String),
+    /// JSON error document.
+    Error(String),
+}
+
+impl AdminResponse {
+    pub fn to_capsule(&self) -> Capsule {
+        match self {
+            AdminResponse::Ok(json) => Capsule::new(ADMIN_OK, json.as_bytes()),
+            AdminResponse::Error(json) => Capsule::new(ADMIN_ERROR, json.as_bytes()),
+        }
+    }
+
+    pub fn from_capsule(capsule: &Capsule) -> Result<Self, DecodeError> {
+        let json = String::from_utf8_lossy(&capsule.payload).into_owned();
+        match capsule.kind {
+            ADMIN_OK => Ok(AdminResponse::Ok(json)),
+            ADMIN_ERROR => Ok(AdminResponse::Error(json)),
+            other => Err(DecodeError::Invalid(InvalidReason::UnexpectedKind(other))),
+        }
+    }
+}
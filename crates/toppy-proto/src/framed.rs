@@ -0,0 +1,115 @@
+//! Capsule framing over an async byte stream (e.g. a QUIC bidi stream joined
+//! with `tokio::io::join`), with a cap on frame size.
+
+use crate::masque::{decode_varint, DecodeError};
+use crate::Capsule;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Default cap on an encoded capsule, matching the control stream's read limit.
+pub const DEFAULT_MAX_FRAME: usize = 256;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(std::io::Error),
+    Decode(DecodeError),
+    /// A frame of `size` encoded bytes exceeded `max`.
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "io error: {}", e),
+            FrameError::Decode(e) => write!(f, "decode error: {}", e),
+            FrameError::TooLarge { size, max } => {
+                write!(f, "frame of {} bytes exceeds {} byte limit", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<std::io::Error> for FrameError {
+    fn from(e: std::io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+pub struct FramedStream<S> {
+    inner: S,
+    buf: Vec<u8>,
+    max_frame: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> FramedStream<S> {
+    pub fn new(inner: S, max_frame: usize) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            max_frame,
+        }
+    }
+
+    pub async fn send(&mut self, frame: &Capsule) -> Result<(), FrameError> {
+        let bytes = frame.encode();
+        if bytes.len() > self.max_frame {
+            return Err(FrameError::TooLarge {
+                size: bytes.len(),
+                max: self.max_frame,
+            });
+        }
+        self.inner.write_all(&bytes).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    /// Next frame, or `None` once the peer finished the stream between frames.
+    /// Oversize frames are rejected as soon as their length prefix arrives.
+    pub async fn recv(&mut self) -> Result<Option<Capsule>, FrameError> {
+        loop {
+            match Capsule::decode(&self.buf) {
+                Ok((capsule, used)) => {
+                    self.buf.drain(..used);
+                    return Ok(Some(capsule));
+                }
+                Err(DecodeError::Truncated) => {}
+                Err(e) => return Err(FrameError::Decode(e)),
+            }
+            if let Some(size) = declared_size(&self.buf) {
+                if size > self.max_frame {
+                    return Err(FrameError::TooLarge {
+                        size,
+                        max: self.max_frame,
+                    });
+                }
+            }
+
+            let mut chunk = [0u8; 512];
+            let n = self.inner.read(&mut chunk).await?;
+            if n == 0 {
+                return if self.buf.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(FrameError::Decode(DecodeError::Truncated))
+                };
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Encoded size of the capsule at the front of `buf`, once its header is complete.
+fn declared_size(buf: &[u8]) -> Option<usize> {
+    let (_, kind_len) = decode_varint(buf).ok()?;
+    let (len, len_len) = decode_varint(&buf[kind_len..]).ok()?;
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    Some((kind_len + len_len).saturating_add(len))
+}
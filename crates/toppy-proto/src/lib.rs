@@ -3,6 +3,10 @@
 //! This crate defines minimal capsule and control message types used by the CLI
 //! and gateway during early development.
 
+use std::borrow::Cow;
+
+use masque::{decode_varint, encode_varint, varint_len, DecodeError, InvalidReason};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Capsule {
     pub kind: u16,
@@ -16,19 +20,247 @@ impl Capsule {
             payload: payload.into(),
         }
     }
+
+    /// Encodes as: varint(kind) || varint(payload length) || payload
+    pub fn encode(&self) -> Vec<u8> {
+        let len = self.payload.len() as u64;
+        let mut out =
+            Vec::with_capacity(varint_len(self.kind as u64) + varint_len(len) + self.payload.len());
+        // Both values are far below the 2^62 varint limit.
+        let _ = encode_varint(self.kind as u64, &mut out);
+        let _ = encode_varint(len, &mut out);
+        out.extend_from_slice(&self.payload);
+        out
+    }
+
+    /// Decodes one capsule from the front of `input`, returning it and the
+    /// number of bytes consumed.
+    pub fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let (kind, kind_len) = decode_varint(input)?;
+        let kind = u16::try_from(kind)
+            .map_err(|_| DecodeError::Invalid(InvalidReason::KindOutOfRange(kind)))?;
+        let (len, len_len) = decode_varint(&input[kind_len..])?;
+        let start = kind_len + len_len;
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .ok_or(DecodeError::Truncated)?;
+        if input.len() < end {
+            return Err(DecodeError::Truncated);
+        }
+        Ok((Self::new(kind, &input[start..end]), end))
+    }
+
+    /// Registered name of `kind` for logging, or `unknown(0x....)`.
+    pub fn kind_name(&self) -> Cow<'static, str> {
+        let name = match self.kind {
+            CONTROL_PING => "control.ping",
+            CONTROL_PONG => "control.pong",
+            CONTROL_CLOSE => "control.close",
+            CONTROL_ERROR => "control.error",
+            CONTROL_HELLO => "control.hello",
+            CONTROL_HELLO_ACK => "control.hello_ack",
+            admin::STATS_REQUEST => "admin.stats_request",
+            admin::RELOAD_POLICY => "admin.reload_policy",
+            admin::ADMIN_OK => "admin.ok",
+            admin::ADMIN_ERROR => "admin.error",
+            kind => return Cow::Owned(format!("unknown(0x{:04x})", kind)),
+        };
+        Cow::Borrowed(name)
+    }
+}
+
+pub const CONTROL_PING: u16 = 0x0c01;
+pub const CONTROL_PONG: u16 = 0x0c02;
+pub const CONTROL_CLOSE: u16 = 0x0c03;
+pub const CONTROL_ERROR: u16 = 0x0c04;
+pub const CONTROL_HELLO: u16 = 0x0c05;
+pub const CONTROL_HELLO_ACK: u16 = 0x0c06;
+
+/// Oldest control protocol version this build speaks.
+pub const PROTOCOL_VERSION_MIN: u16 = 1;
+/// Newest control protocol version this build speaks.
+pub const PROTOCOL_VERSION_MAX: u16 = 1;
+
+/// Picks the highest version in both `[min, max]` and the local range, or
+/// returns the `Error` to send back when the ranges don't overlap.
+pub fn negotiate_version(min: u16, max: u16) -> Result<u16, ControlMessage> {
+    let selected = max.min(PROTOCOL_VERSION_MAX);
+    if min > max || selected < min.max(PROTOCOL_VERSION_MIN) {
+        return Err(ControlMessage::Error {
+            code: error_code::UNSUPPORTED_VERSION,
+            message: format!(
+                "no common version: peer {}-{}, local {}-{}",
+                min, max, PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_MAX
+            ),
+        });
+    }
+    Ok(selected)
+}
+
+/// Codes carried by [`ControlMessage::Error`].
+pub mod error_code {
+    pub const BAD_REQUEST: u16 = 1;
+    pub const UNAUTHORIZED: u16 = 2;
+    pub const FORBIDDEN: u16 = 3;
+    pub const RATE_LIMITED: u16 = 4;
+    pub const INTERNAL: u16 = 5;
+    pub const UNSUPPORTED_VERSION: u16 = 6;
+
+    /// Default human-readable message for a code.
+    pub fn default_message(code: u16) -> &'static str {
+        match code {
+            BAD_REQUEST => "bad request",
+            UNAUTHORIZED => "unauthorized",
+            FORBIDDEN => "forbidden",
+            RATE_LIMITED => "rate limited",
+            INTERNAL => "internal error",
+            UNSUPPORTED_VERSION => "unsupported version",
+            _ => "unknown error",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ControlMessage {
     Ping,
     Pong,
-    Close { reason: String },
+    Close {
+        reason: String,
+    },
+    Error {
+        code: u16,
+        message: String,
+    },
+    /// Opens a session with the supported version range (inclusive).
+    Hello {
+        min: u16,
+        max: u16,
+    },
+    /// The version selected by the gateway in reply to `Hello`.
+    HelloAck {
+        version: u16,
+    },
 }
 
 impl ControlMessage {
     pub fn is_terminal(&self) -> bool {
-        matches!(self, Self::Close { .. })
+        matches!(self, Self::Close { .. } | Self::Error { .. })
+    }
+
+    /// Builds an `Error` with the code's default message.
+    pub fn error(code: u16) -> Self {
+        Self::Error {
+            code,
+            message: error_code::default_message(code).to_string(),
+        }
+    }
+
+    pub fn bad_request() -> Self {
+        Self::error(error_code::BAD_REQUEST)
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::error(error_code::UNAUTHORIZED)
+    }
+
+    pub fn rate_limited() -> Self {
+        Self::error(error_code::RATE_LIMITED)
+    }
+
+    /// `Hello` advertising this build's supported range.
+    pub fn hello() -> Self {
+        Self::Hello {
+            min: PROTOCOL_VERSION_MIN,
+            max: PROTOCOL_VERSION_MAX,
+        }
+    }
+
+    /// Error payload: u16 code (big-endian) || UTF-8 message.
+    /// Hello payload: u16 min || u16 max; HelloAck payload: u16 version.
+    pub fn to_capsule(&self) -> Capsule {
+        match self {
+            Self::Ping => Capsule::new(CONTROL_PING, Vec::new()),
+            Self::Pong => Capsule::new(CONTROL_PONG, Vec::new()),
+            Self::Close { reason } => Capsule::new(CONTROL_CLOSE, reason.as_bytes()),
+            Self::Error { code, message } => {
+                let mut payload = Vec::with_capacity(2 + message.len());
+                payload.extend_from_slice(&code.to_be_bytes());
+                payload.extend_from_slice(message.as_bytes());
+                Capsule::new(CONTROL_ERROR, payload)
+            }
+            Self::Hello { min, max } => {
+                let mut payload = Vec::with_capacity(4);
+                payload.extend_from_slice(&min.to_be_bytes());
+                payload.extend_from_slice(&max.to_be_bytes());
+                Capsule::new(CONTROL_HELLO, payload)
+            }
+            Self::HelloAck { version } => Capsule::new(CONTROL_HELLO_ACK, version.to_be_bytes()),
+        }
+    }
+
+    pub fn from_capsule(capsule: &Capsule) -> Result<Self, DecodeError> {
+        match capsule.kind {
+            CONTROL_PING => Ok(Self::Ping),
+            CONTROL_PONG => Ok(Self::Pong),
+            CONTROL_CLOSE => Ok(Self::Close {
+                reason: String::from_utf8_lossy(&capsule.payload).into_owned(),
+            }),
+            CONTROL_ERROR => {
+                let (code, message) = match capsule.payload.as_slice() {
+                    [hi, lo, rest @ ..] => (u16::from_be_bytes([*hi, *lo]), rest),
+                    _ => return Err(DecodeError::Truncated),
+                };
+                Ok(Self::Error {
+                    code,
+                    message: String::from_utf8_lossy(message).into_owned(),
+                })
+            }
+            CONTROL_HELLO => match capsule.payload.as_slice() {
+                [a, b, c, d] => Ok(Self::Hello {
+                    min: u16::from_be_bytes([*a, *b]),
+                    max: u16::from_be_bytes([*c, *d]),
+                }),
+                _ => Err(DecodeError::Truncated),
+            },
+            CONTROL_HELLO_ACK => match capsule.payload.as_slice() {
+                [hi, lo] => Ok(Self::HelloAck {
+                    version: u16::from_be_bytes([*hi, *lo]),
+                }),
+                _ => Err(DecodeError::Truncated),
+            },
+            other => Err(DecodeError::Invalid(InvalidReason::UnexpectedKind(other))),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.to_capsule().encode()
+    }
+
+    /// Decodes one message from the front of `input`, returning it and the
+    /// number of bytes consumed.
+    pub fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let (capsule, used) = Capsule::decode(input)?;
+        Ok((Self::from_capsule(&capsule)?, used))
+    }
+
+    /// Decodes the reply to a `ping`: a `Pong` or `Error` message, or the
+    /// [`LEGACY_PONG`] / [`LEGACY_UNAUTHORIZED`] bytes of older gateways.
+    pub fn decode_ping_reply(input: &[u8]) -> Result<Self, DecodeError> {
+        match input {
+            LEGACY_PONG => Ok(Self::Pong),
+            LEGACY_UNAUTHORIZED => Ok(Self::unauthorized()),
+            _ => Self::decode(input).map(|(msg, _)| msg),
+        }
     }
 }
 
+/// Raw `ping` replies from before capsule replies; still sent to clients that
+/// did not negotiate a version with `Hello`.
+pub const LEGACY_PONG: &[u8] = b"pong";
+pub const LEGACY_UNAUTHORIZED: &[u8] = b"unauthorized";
+
+pub mod admin;
+#[cfg(feature = "framed")]
+pub mod framed;
 pub mod masque;
@@ -21,27 +21,202 @@ impl HttpDatagram {
         Ok(out)
     }
 
+    /// Like [`encode`](Self::encode) but rejects payloads over `max_payload`
+    /// bytes (see [`max_udp_payload`]) instead of producing an oversize datagram.
+    pub fn encode_bounded(&self, max_payload: usize) -> Result<Vec<u8>, EncodeError> {
+        if self.payload.len() > max_payload {
+            return Err(EncodeError::TooLarge {
+                size: self.payload.len(),
+                max: max_payload,
+            });
+        }
+        self.encode()
+    }
+
     pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
-        let (context_id, n) = decode_varint(input)?;
-        Ok(Self {
-            context_id,
-            payload: input[n..].to_vec(),
-        })
+        let (context_id, payload, _) = Self::decode_ref(input)?;
+        Ok(Self::new(context_id, payload))
+    }
+
+    /// Like [`decode`](Self::decode) but borrows the payload from `input`.
+    /// Returns the context ID, the payload and the context ID's length.
+    pub fn decode_ref(input: &[u8]) -> Result<(u64, &[u8], usize), DecodeErrorAt> {
+        let (context_id, n) = decode_varint_at(input, 0)?;
+        Ok((context_id, &input[n..], n))
     }
 }
 
 /// CONNECT-UDP uses Context ID 0 for UDP payload datagrams.
 pub const CONNECT_UDP_CONTEXT_ID: u64 = 0;
 
+/// Worst-case bytes between the path MTU and a CONNECT-UDP payload: IPv6 (40),
+/// UDP (8), QUIC short header (25), AEAD tag (16), DATAGRAM frame type (1),
+/// quarter stream ID (4) and context ID (1).
+pub const CONNECT_UDP_OVERHEAD: usize = 95;
+
+/// Largest UDP payload that fits in one HTTP datagram on a path with `mtu`.
+pub fn max_udp_payload(mtu: u16) -> usize {
+    (mtu as usize).saturating_sub(CONNECT_UDP_OVERHEAD)
+}
+
+/// Path prefix of the CONNECT-UDP well-known URI template (RFC 9298).
+pub const CONNECT_UDP_PATH_PREFIX: &str = "/.well-known/masque/udp/";
+
+/// Request header (value `1`) marking a CONNECT-UDP request as a diagnostic
+/// probe rather than user traffic.
+pub const DIAGNOSTIC_HEADER: &str = "toppy-diagnostic";
+
+/// The only target a diagnostic probe may name: the discard port on loopback.
+/// The gateway echoes such flows instead of relaying them.
+pub const DIAGNOSTIC_PROBE_HOST: &str = "127.0.0.1";
+pub const DIAGNOSTIC_PROBE_PORT: u16 = 9;
+
+/// Target of a CONNECT-UDP request, as carried in its path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpTarget {
+    /// Host name or IP literal, percent-decoded and without brackets.
+    pub host: String,
+    pub port: u16,
+}
+
+impl UdpTarget {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+
+    /// `/.well-known/masque/udp/{host}/{port}/`, percent-encoding the host
+    /// (so IPv6 colons become `%3A`).
+    pub fn to_path(&self) -> String {
+        let mut path = CONNECT_UDP_PATH_PREFIX.to_string();
+        for byte in self.host.bytes() {
+            if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+                path.push(byte as char);
+            } else {
+                path.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        path.push_str(&format!("/{}/", self.port));
+        path
+    }
+}
+
+/// Parses `/.well-known/masque/udp/{host}/{port}/`; the trailing slash is optional.
+pub fn parse_connect_udp_path(path: &str) -> Result<UdpTarget, DecodeError> {
+    let bad = |what| DecodeError::Invalid(InvalidReason::BadPath(what));
+    let rest = path
+        .strip_prefix(CONNECT_UDP_PATH_PREFIX)
+        .ok_or(bad("missing well-known prefix"))?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    let (host, port) = rest.split_once('/').ok_or(bad("missing port"))?;
+    let host = percent_decode(host).ok_or(bad("bad percent-encoding in host"))?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(&host)
+        .to_string();
+    if host.is_empty() {
+        return Err(bad("empty host"));
+    }
+    if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(bad("invalid port"));
+    }
+    let port = port.parse::<u16>().map_err(|_| bad("invalid port"))?;
+    Ok(UdpTarget { host, port })
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DecodeError {
     Truncated,
-    Invalid,
+    Invalid(InvalidReason),
 }
 
+/// Why an input was rejected as malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidReason {
+    /// The two-bit length prefix did not map to a known encoding length.
+    BadPrefix(u8),
+    /// A capsule kind did not fit in 16 bits.
+    KindOutOfRange(u64),
+    /// A capsule kind was not expected in this context.
+    UnexpectedKind(u16),
+    /// A CONNECT-UDP path did not match the well-known URI template.
+    BadPath(&'static str),
+}
+
+impl std::fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidReason::BadPrefix(byte) => write!(f, "bad varint prefix in byte {:#04x}", byte),
+            InvalidReason::KindOutOfRange(kind) => write!(f, "capsule kind {} out of range", kind),
+            InvalidReason::UnexpectedKind(kind) => {
+                write!(f, "unexpected capsule kind {:#06x}", kind)
+            }
+            InvalidReason::BadPath(what) => write!(f, "bad connect-udp path: {}", what),
+        }
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated input"),
+            DecodeError::Invalid(reason) => write!(f, "invalid input: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A [`DecodeError`] with the offset, into the whole input, of the byte
+/// that failed to decode; see [`decode_varint_at`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeErrorAt {
+    pub offset: usize,
+    pub error: DecodeError,
+}
+
+impl From<DecodeErrorAt> for DecodeError {
+    fn from(err: DecodeErrorAt) -> Self {
+        err.error
+    }
+}
+
+impl std::fmt::Display for DecodeErrorAt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.error, self.offset)
+    }
+}
+
+impl std::error::Error for DecodeErrorAt {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EncodeError {
     OutOfRange,
+    /// A payload of `size` bytes exceeded the `max` allowed.
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
 }
 
 /// Encodes a QUIC variable-length integer.
@@ -72,6 +247,9 @@ pub fn encode_varint(value: u64, out: &mut Vec<u8>) -> Result<(), EncodeError> {
     }
 }
 
+/// Decodes a QUIC variable-length integer, returning the value and its length.
+///
+/// Non-minimal encodings are accepted, as RFC 9000 §16 requires.
 pub fn decode_varint(input: &[u8]) -> Result<(u64, usize), DecodeError> {
     let first = *input.first().ok_or(DecodeError::Truncated)?;
     let prefix = first >> 6;
@@ -80,7 +258,7 @@ pub fn decode_varint(input: &[u8]) -> Result<(u64, usize), DecodeError> {
         0b01 => 2,
         0b10 => 4,
         0b11 => 8,
-        _ => return Err(DecodeError::Invalid),
+        _ => return Err(DecodeError::Invalid(InvalidReason::BadPrefix(first))),
     };
 
     if input.len() < len {
@@ -103,12 +281,26 @@ pub fn decode_varint(input: &[u8]) -> Result<(u64, usize), DecodeError> {
             ]);
             raw & 0x3fff_ffff_ffff_ffff
         }
-        _ => return Err(DecodeError::Invalid),
+        _ => return Err(DecodeError::Invalid(InvalidReason::BadPrefix(first))),
     };
 
     Ok((value, len))
 }
 
+/// Decodes the varint starting at `input[offset]`, for walking a stream of
+/// values; the returned length excludes `offset`. A truncated value fails at
+/// the first missing byte, an invalid one at its first byte.
+pub fn decode_varint_at(input: &[u8], offset: usize) -> Result<(u64, usize), DecodeErrorAt> {
+    let rest = input.get(offset..).unwrap_or_default();
+    decode_varint(rest).map_err(|error| DecodeErrorAt {
+        offset: match error {
+            DecodeError::Truncated => offset.max(input.len()),
+            DecodeError::Invalid(_) => offset,
+        },
+        error,
+    })
+}
+
 pub fn varint_len(value: u64) -> usize {
     match value {
         0..=63 => 1,
@@ -154,10 +346,75 @@ mod tests {
         assert_eq!(decoded, dg);
     }
 
+    #[test]
+    fn http_datagram_decode_ref_borrows_payload() {
+        let bytes = HttpDatagram::new(CONNECT_UDP_CONTEXT_ID, vec![5, 6, 7])
+            .encode()
+            .unwrap();
+        let (context_id, payload, n) = HttpDatagram::decode_ref(&bytes).unwrap();
+        let owned = HttpDatagram::decode(&bytes).unwrap();
+        assert_eq!(context_id, owned.context_id);
+        assert_eq!(payload, owned.payload.as_slice());
+        assert_eq!(n, 1);
+        // The payload points into the input buffer rather than a copy.
+        assert!(std::ptr::eq(payload.as_ptr(), bytes[n..].as_ptr()));
+        assert!(!std::ptr::eq(payload.as_ptr(), owned.payload.as_ptr()));
+    }
+
     #[test]
     fn decode_varint_truncated() {
         assert_eq!(decode_varint(&[]), Err(DecodeError::Truncated));
         // 2-byte encoding but only 1 byte provided.
         assert_eq!(decode_varint(&[0b01 << 6]), Err(DecodeError::Truncated));
     }
+
+    #[test]
+    fn decode_varint_at_reports_offset_of_missing_byte() {
+        // 5, then a 4-byte varint cut off after two bytes.
+        let stream = [0x05, 0x80, 0x01];
+        assert_eq!(decode_varint_at(&stream, 0), Ok((5, 1)));
+        let err = decode_varint_at(&stream, 1).unwrap_err();
+        assert_eq!(
+            err,
+            DecodeErrorAt {
+                offset: 3,
+                error: DecodeError::Truncated
+            }
+        );
+        assert_eq!(err.to_string(), "truncated input at byte 3");
+        assert_eq!(DecodeError::from(err), DecodeError::Truncated);
+
+        assert_eq!(decode_varint_at(&stream, 3).unwrap_err().offset, 3);
+        assert_eq!(decode_varint_at(&stream, 9).unwrap_err().offset, 9);
+    }
+
+    #[test]
+    fn http_datagram_decode_ref_reports_offset() {
+        let err = HttpDatagram::decode_ref(&[0x40]).unwrap_err();
+        assert_eq!(err.offset, 1);
+        assert_eq!(err.to_string(), "truncated input at byte 1");
+        assert_eq!(HttpDatagram::decode_ref(&[]).unwrap_err().offset, 0);
+    }
+
+    #[test]
+    fn decode_varint_accepts_non_minimal_encoding() {
+        // 5 fits in one byte but is encoded on two.
+        assert_eq!(decode_varint(&[0x40, 0x05]), Ok((5, 2)));
+        // 63 encoded on four bytes.
+        assert_eq!(decode_varint(&[0x80, 0x00, 0x00, 0x3f]), Ok((63, 4)));
+    }
+
+    #[test]
+    fn http_datagram_decode_accepts_non_minimal_context_id() {
+        assert_eq!(HttpDatagram::decode(&[]), Err(DecodeError::Truncated));
+        let datagram = HttpDatagram::decode(&[0x40, 0x00, 0xaa]).expect("decode");
+        assert_eq!(datagram.context_id, 0);
+        assert_eq!(datagram.payload, vec![0xaa]);
+    }
+
+    #[test]
+    fn decode_error_display_includes_reason() {
+        let err = DecodeError::Invalid(InvalidReason::KindOutOfRange(70_000));
+        assert!(err.to_string().contains("capsule kind 70000 out of range"));
+    }
 }
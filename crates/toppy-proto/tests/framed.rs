@@ -0,0 +1,49 @@
+use toppy_proto::framed::{FrameError, FramedStream};
+use toppy_proto::{Capsule, ControlMessage};
+
+#[tokio::test]
+async fn framed_stream_roundtrips_capsules_over_duplex() {
+    let (client, server) = tokio::io::duplex(8);
+    let mut client = FramedStream::new(client, 64);
+    let mut server = FramedStream::new(server, 64);
+
+    let frames = [
+        ControlMessage::hello().to_capsule(),
+        Capsule::new(0x0a01, vec![7; 40]),
+        ControlMessage::Ping.to_capsule(),
+    ];
+    let sent = frames.clone();
+    let writer = tokio::spawn(async move {
+        for frame in &sent {
+            client.send(frame).await.unwrap();
+        }
+        // Dropping the client half ends the stream.
+        assert!(matches!(
+            client.send(&Capsule::new(1, vec![0; 100])).await,
+            Err(FrameError::TooLarge { max: 64, .. })
+        ));
+    });
+
+    for frame in &frames {
+        assert_eq!(server.recv().await.unwrap().as_ref(), Some(frame));
+    }
+    writer.await.unwrap();
+    assert!(server.recv().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn framed_stream_rejects_oversize_declared_length() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut server = FramedStream::new(server, 16);
+    // kind 0x01, declared length 1000 (2-byte varint): rejected before the payload arrives.
+    tokio::io::AsyncWriteExt::write_all(&mut client, &[0x01, 0x43, 0xe8])
+        .await
+        .unwrap();
+    assert!(matches!(
+        server.recv().await,
+        Err(FrameError::TooLarge {
+            size: 1003,
+            max: 16
+        })
+    ));
+}
@@ -1,5 +1,9 @@
-use toppy_proto::masque::{HttpDatagram, CONNECT_UDP_CONTEXT_ID};
-use toppy_proto::{Capsule, ControlMessage};
+use toppy_proto::admin::{AdminCommand, AdminRequest, AdminResponse};
+use toppy_proto::masque::{
+    max_udp_payload, parse_connect_udp_path, DecodeError, EncodeError, HttpDatagram, InvalidReason,
+    UdpTarget, CONNECT_UDP_CONTEXT_ID,
+};
+use toppy_proto::{error_code, negotiate_version, Capsule, ControlMessage, PROTOCOL_VERSION_MAX};
 
 #[test]
 fn capsule_new_sets_fields() {
@@ -11,6 +15,7 @@ fn capsule_new_sets_fields() {
 #[test]
 fn control_message_terminal_detection() {
     assert!(!ControlMessage::Ping.is_terminal());
+    assert!(ControlMessage::unauthorized().is_terminal());
     assert!(ControlMessage::Close {
         reason: "done".to_string()
     }
@@ -24,3 +29,272 @@ fn connect_udp_http_datagram_roundtrip() {
     let decoded = HttpDatagram::decode(&bytes).unwrap();
     assert_eq!(decoded, dg);
 }
+
+#[test]
+fn max_udp_payload_subtracts_overhead() {
+    assert_eq!(max_udp_payload(1350), 1255);
+    assert_eq!(max_udp_payload(1280), 1185);
+    assert_eq!(max_udp_payload(64), 0);
+}
+
+#[test]
+fn encode_bounded_rejects_oversize_payload() {
+    let dg = HttpDatagram::new(CONNECT_UDP_CONTEXT_ID, vec![0; 5]);
+    assert_eq!(dg.encode_bounded(5).unwrap(), dg.encode().unwrap());
+    assert_eq!(
+        dg.encode_bounded(4),
+        Err(EncodeError::TooLarge { size: 5, max: 4 })
+    );
+}
+
+#[test]
+fn capsule_encode_decode_roundtrip() {
+    let capsule = Capsule::new(0x0a01, b"secret".to_vec());
+    let bytes = capsule.encode();
+    let (decoded, used) = Capsule::decode(&bytes).unwrap();
+    assert_eq!(decoded, capsule);
+    assert_eq!(used, bytes.len());
+}
+
+#[test]
+fn capsule_kind_name_known_and_unknown() {
+    assert_eq!(Capsule::new(0x0c01, Vec::new()).kind_name(), "control.ping");
+    assert_eq!(
+        Capsule::new(0x0a02, Vec::new()).kind_name(),
+        "admin.reload_policy"
+    );
+    assert_eq!(
+        Capsule::new(0x1234, Vec::new()).kind_name(),
+        "unknown(0x1234)"
+    );
+}
+
+#[test]
+fn capsule_decode_truncated_payload() {
+    let mut bytes = Capsule::new(7, vec![1, 2, 3]).encode();
+    bytes.pop();
+    assert_eq!(Capsule::decode(&bytes), Err(DecodeError::Truncated));
+}
+
+#[test]
+fn admin_command_capsule_roundtrip() {
+    let cmd = AdminCommand::new(AdminRequest::ReloadPolicy, "admin-token");
+    let (capsule, _) = Capsule::decode(&cmd.to_capsule().encode()).unwrap();
+    assert_eq!(AdminCommand::from_capsule(&capsule).unwrap(), cmd);
+
+    let resp = AdminResponse::Ok("{\"reloaded\":true}".to_string());
+    assert_eq!(
+        AdminResponse::from_capsule(&resp.to_capsule()).unwrap(),
+        resp
+    );
+}
+
+#[test]
+fn admin_command_rejects_unknown_kind() {
+    let capsule = Capsule::new(0x0bad, b"token".to_vec());
+    assert_eq!(
+        AdminCommand::from_capsule(&capsule),
+        Err(DecodeError::Invalid(InvalidReason::UnexpectedKind(0x0bad)))
+    );
+}
+
+#[test]
+fn control_message_codec_roundtrip() {
+    let messages = [
+        ControlMessage::Ping,
+        ControlMessage::Pong,
+        ControlMessage::Close {
+            reason: "done".to_string(),
+        },
+        ControlMessage::Error {
+            code: 4242,
+            message: "custom".to_string(),
+        },
+    ];
+    for msg in messages {
+        let bytes = msg.encode();
+        let (decoded, used) = ControlMessage::decode(&bytes).unwrap();
+        assert_eq!(decoded, msg);
+        assert_eq!(used, bytes.len());
+    }
+}
+
+#[test]
+fn ping_reply_decodes_capsules_and_legacy_bytes() {
+    assert_eq!(
+        ControlMessage::decode_ping_reply(b"pong"),
+        Ok(ControlMessage::Pong)
+    );
+    assert_eq!(
+        ControlMessage::decode_ping_reply(b"unauthorized"),
+        Ok(ControlMessage::unauthorized())
+    );
+
+    let pong = ControlMessage::Pong.encode();
+    assert_eq!(
+        ControlMessage::decode_ping_reply(&pong),
+        Ok(ControlMessage::Pong)
+    );
+    let error = ControlMessage::Error {
+        code: error_code::UNAUTHORIZED,
+        message: "token expired".to_string(),
+    };
+    assert_eq!(
+        ControlMessage::decode_ping_reply(&error.encode()),
+        Ok(error)
+    );
+    assert!(ControlMessage::decode_ping_reply(b"").is_err());
+}
+
+#[test]
+fn control_message_error_codes_map_to_messages() {
+    let cases = [
+        (
+            ControlMessage::bad_request(),
+            error_code::BAD_REQUEST,
+            "bad request",
+        ),
+        (
+            ControlMessage::unauthorized(),
+            error_code::UNAUTHORIZED,
+            "unauthorized",
+        ),
+        (
+            ControlMessage::rate_limited(),
+            error_code::RATE_LIMITED,
+            "rate limited",
+        ),
+        (
+            ControlMessage::error(error_code::FORBIDDEN),
+            error_code::FORBIDDEN,
+            "forbidden",
+        ),
+        (
+            ControlMessage::error(error_code::INTERNAL),
+            error_code::INTERNAL,
+            "internal error",
+        ),
+    ];
+    for (msg, code, message) in cases {
+        assert_eq!(
+            msg,
+            ControlMessage::Error {
+                code,
+                message: message.to_string()
+            }
+        );
+    }
+    assert_eq!(error_code::default_message(999), "unknown error");
+}
+
+#[test]
+fn control_message_error_rejects_short_payload() {
+    let capsule = Capsule::new(toppy_proto::CONTROL_ERROR, vec![0x01]);
+    assert_eq!(
+        ControlMessage::from_capsule(&capsule),
+        Err(DecodeError::Truncated)
+    );
+}
+
+#[test]
+fn hello_negotiates_compatible_version() {
+    let bytes = ControlMessage::Hello {
+        min: 1,
+        max: PROTOCOL_VERSION_MAX + 5,
+    }
+    .encode();
+    let (decoded, used) = ControlMessage::decode(&bytes).unwrap();
+    assert_eq!(used, bytes.len());
+    let ControlMessage::Hello { min, max } = decoded else {
+        panic!("expected hello, got {:?}", decoded);
+    };
+    let version = negotiate_version(min, max).unwrap();
+    assert_eq!(version, PROTOCOL_VERSION_MAX);
+
+    let ack = ControlMessage::HelloAck { version };
+    assert_eq!(ControlMessage::decode(&ack.encode()).unwrap().0, ack);
+}
+
+#[test]
+fn hello_rejects_incompatible_version() {
+    let hello = ControlMessage::Hello {
+        min: PROTOCOL_VERSION_MAX + 1,
+        max: PROTOCOL_VERSION_MAX + 3,
+    };
+    let (decoded, _) = ControlMessage::decode(&hello.encode()).unwrap();
+    let ControlMessage::Hello { min, max } = decoded else {
+        panic!("expected hello, got {:?}", decoded);
+    };
+    let reply = negotiate_version(min, max).unwrap_err();
+    assert!(reply.is_terminal());
+    let (decoded, _) = ControlMessage::decode(&reply.encode()).unwrap();
+    assert!(matches!(
+        decoded,
+        ControlMessage::Error {
+            code: error_code::UNSUPPORTED_VERSION,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn hello_with_short_payload_is_truncated() {
+    let capsule = Capsule::new(toppy_proto::CONTROL_HELLO, vec![0, 1]);
+    assert_eq!(
+        ControlMessage::from_capsule(&capsule),
+        Err(DecodeError::Truncated)
+    );
+}
+
+#[test]
+fn connect_udp_path_roundtrips_ipv4() {
+    let target = UdpTarget::new("192.0.2.10", 443);
+    assert_eq!(target.to_path(), "/.well-known/masque/udp/192.0.2.10/443/");
+    assert_eq!(
+        parse_connect_udp_path(&target.to_path()),
+        Ok(target.clone())
+    );
+    assert_eq!(
+        parse_connect_udp_path("/.well-known/masque/udp/192.0.2.10/443"),
+        Ok(target)
+    );
+}
+
+#[test]
+fn connect_udp_path_roundtrips_ipv6() {
+    let target = UdpTarget::new("2001:db8::1", 53);
+    assert_eq!(
+        target.to_path(),
+        "/.well-known/masque/udp/2001%3Adb8%3A%3A1/53/"
+    );
+    assert_eq!(
+        parse_connect_udp_path(&target.to_path()),
+        Ok(target.clone())
+    );
+    assert_eq!(
+        parse_connect_udp_path("/.well-known/masque/udp/%5b2001%3adb8%3a%3a1%5d/53/"),
+        Ok(target)
+    );
+}
+
+#[test]
+fn connect_udp_path_rejects_malformed() {
+    for path in [
+        "/masque/udp/192.0.2.10/443/",
+        "/.well-known/masque/udp/192.0.2.10/",
+        "/.well-known/masque/udp//443/",
+        "/.well-known/masque/udp/192.0.2.10/70000/",
+        "/.well-known/masque/udp/192.0.2.10/+443/",
+        "/.well-known/masque/udp/192.0.2.10/443/extra/",
+        "/.well-known/masque/udp/2001%3/53/",
+    ] {
+        assert!(
+            matches!(
+                parse_connect_udp_path(path),
+                Err(DecodeError::Invalid(InvalidReason::BadPath(_)))
+            ),
+            "{}",
+            path
+        );
+    }
+}